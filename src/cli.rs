@@ -0,0 +1,1133 @@
+use clap::{Args, Parser, Subcommand};
+
+use csv_to_mtx::convert::OutputFormat;
+use csv_to_mtx::csv_output::FloatFormat;
+use csv_to_mtx::export::{BatchFormat, ExportOptions};
+use csv_to_mtx::generate::{GenerateOptions, GeneratedFormat, ValueDistribution};
+use csv_to_mtx::input::{ReadOptions, RectOrientation};
+use csv_to_mtx::matrix::{Normalize, Symmetrize, Triangle};
+use csv_to_mtx::mtx::{parse_magic, Endianness, GzipFilter, IndexCheck, IndexWidth, MtxReadOptions};
+use csv_to_mtx::options::ConversionOptions;
+use csv_to_mtx::value_transform::{CellAggregate, ClampNegative, TimeUnit, ValueTransform};
+use csv_to_mtx::zones::{CompareZones, ZoneSource, ZonesFile, ZonesMode};
+
+/// Converts CSV files into MTX/MTX.GZ files and works with the resulting matrices.
+#[derive(Parser, Debug)]
+#[command(name = "csv_to_mtx", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// Converts a CSV file into an MTX file.
+    Convert(Box<ConvertArgs>),
+    /// Prints the dimensions of an MTX file and a summary of its values.
+    Inspect(InspectArgs),
+    /// Compares two MTX files cell by cell.
+    Diff(DiffArgs),
+    /// Writes the transpose of an MTX file.
+    Transpose(TransposeArgs),
+    /// Writes the row and column sums of an MTX file, its productions and attractions.
+    Marginals(MarginalsArgs),
+    /// Writes an MTX file as text for reviewing it.
+    Export(ExportArgs),
+    /// Converts every MTX file in a directory to a text format, next to each input.
+    BatchConvert(BatchConvertArgs),
+    /// Packs several MTX files into a single container file.
+    Pack(PackArgs),
+    /// Writes each matrix in a container file back out as an MTX file.
+    Unpack(UnpackArgs),
+    /// Checks that a zones file lists the zones of an MTX file in the same order.
+    ValidateZones(ValidateZonesArgs),
+    /// Reports the zones shared by several CSV or MTX files and the zones unique to each.
+    ZonesIntersect(ZonesIntersectArgs),
+    /// Writes a synthetic input CSV file for benchmarks and tests.
+    Generate(GenerateArgs),
+    /// Round-trips a generated matrix through every MTX layout and prints PASS or FAIL.
+    Selftest(SelftestArgs),
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct ConvertArgs {
+    /// The input CSV file, or a directory of CSV parts read one after another.
+    pub input: String,
+    /// The output file, use `.mtx.gz` to compress it or `-` to write to standard output.
+    pub output: String,
+    /// Optional CSV file listing the zone system.
+    pub zones: Option<String>,
+    /// A TOML file supplying default options, overridden by any flags given.
+    #[arg(long)]
+    pub config: Option<String>,
+    /// The character separating fields in the CSV files.
+    #[arg(long)]
+    pub delimiter: Option<char>,
+    /// How the zones file combines with the zones found in the data.
+    #[arg(long, value_enum)]
+    pub zones_mode: Option<ZonesMode>,
+    /// Where the zones come from, rather than deciding by whether a zones file is given.
+    #[arg(long = "zones", id = "zone_source", value_enum)]
+    pub zone_source: Option<ZoneSource>,
+    /// A CSV file listing the only zones to use, in the order the matrix is written in.
+    #[arg(long)]
+    pub select_zones_file: Option<String>,
+    /// The first row of the zones file is a header rather than a zone.
+    #[arg(long, overrides_with = "no_zones_header")]
+    pub zones_header: bool,
+    /// The first row of the zones file is a zone (the default).
+    #[arg(long, overrides_with = "zones_header")]
+    pub no_zones_header: bool,
+    /// The file format to write.
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+    /// The byte order to write the MTX file in.
+    #[arg(long, value_enum)]
+    pub output_endianness: Option<Endianness>,
+    /// Lays out the values of the MTX file so that they compress better, recording
+    /// the layout in its header.
+    #[arg(long, value_enum)]
+    pub gzip_filter: Option<GzipFilter>,
+    /// Writes the zone numbers of the MTX file as 16, 32 or 64-bit integers, recording
+    /// the width in its header.
+    #[arg(long, value_enum)]
+    pub index_width: Option<IndexWidth>,
+    /// Writes a .mtx.gz file as concatenated gzip members of this many rows each, which
+    /// gunzip reads as one file and block-aware readers can decompress in parallel.
+    #[arg(long, value_name = "ROWS")]
+    pub gzip_blocks: Option<usize>,
+    /// Starts the MTX file with this hexadecimal magic number instead of C4D4F1B2, for
+    /// private formats sharing the layout.
+    #[arg(long, value_name = "HEX", value_parser = parse_magic)]
+    pub magic: Option<u32>,
+    /// Pads each row of values so it starts at a multiple of this many bytes in the
+    /// uncompressed MTX file, recording the stride in the header.
+    #[arg(long, value_name = "BYTES")]
+    pub row_align: Option<usize>,
+    /// Writes the sum of each origin's row to this CSV file.
+    #[arg(long)]
+    pub row_sums: Option<String>,
+    /// Writes the sum of each destination's column to this CSV file.
+    #[arg(long)]
+    pub col_sums: Option<String>,
+    /// Writes the intrazonal (diagonal) values to this CSV file.
+    #[arg(long)]
+    pub diagonal: Option<String>,
+    /// Overwrite the output file if it already exists.
+    #[arg(long, overrides_with = "no_force")]
+    pub force: bool,
+    /// Refuses to overwrite an existing output file (the default).
+    #[arg(long, overrides_with = "force")]
+    pub no_force: bool,
+    /// Streams 3-column input straight into the matrix instead of holding every row in memory.
+    #[arg(long, overrides_with = "no_low_memory")]
+    pub low_memory: bool,
+    /// Holds every row of the input in memory (the default).
+    #[arg(long, overrides_with = "low_memory")]
+    pub no_low_memory: bool,
+    /// Syncs the MTX file to disk before it replaces the output file.
+    #[arg(long, overrides_with = "no_fsync")]
+    pub fsync: bool,
+    /// Leaves syncing the MTX file to the operating system (the default).
+    #[arg(long, overrides_with = "fsync")]
+    pub no_fsync: bool,
+    /// Stores the source file, creation time and tool version in the MTX file.
+    #[arg(long, overrides_with = "no_stamp_metadata")]
+    pub stamp_metadata: bool,
+    /// Stores no provenance metadata in the MTX file (the default).
+    #[arg(long, overrides_with = "stamp_metadata")]
+    pub no_stamp_metadata: bool,
+    /// Writes the same bytes on every run, leaving the creation time out of the metadata.
+    #[arg(long, overrides_with = "no_reproducible")]
+    pub reproducible: bool,
+    /// Stores the creation time in the metadata (the default).
+    #[arg(long, overrides_with = "reproducible")]
+    pub no_reproducible: bool,
+    /// Writes the same bytes on every platform and with any number of threads, implying --reproducible.
+    #[arg(long, overrides_with = "no_canonical_order")]
+    pub canonical_order: bool,
+    /// Turns off --canonical-order (the default).
+    #[arg(long, overrides_with = "canonical_order")]
+    pub no_canonical_order: bool,
+    /// The value of cells without any data.
+    #[arg(long, allow_negative_numbers = true)]
+    pub fill: Option<f32>,
+    /// Values below this are replaced with the fill value.
+    #[arg(long, allow_negative_numbers = true)]
+    pub min_value: Option<f32>,
+    /// Values above this are clamped to it.
+    #[arg(long, allow_negative_numbers = true)]
+    pub max_value: Option<f32>,
+    /// Only prints the number of non-zero cells, without building the matrix or writing the output.
+    #[arg(long, overrides_with = "no_count_only")]
+    pub count_only: bool,
+    /// Builds and writes the matrix (the default).
+    #[arg(long, overrides_with = "count_only")]
+    pub no_count_only: bool,
+    /// Only prints how the conversion would run, such as the input's format, the zones
+    /// and the output, reading no more of the input than its header row.
+    #[arg(long, overrides_with = "no_explain")]
+    pub explain: bool,
+    /// Runs the conversion rather than only printing how it would run (the default).
+    #[arg(long, overrides_with = "explain")]
+    pub no_explain: bool,
+    /// Keeps the records read before a gzipped input turns out to be truncated instead of failing.
+    #[arg(long, overrides_with = "no_tolerate_truncation")]
+    pub tolerate_truncation: bool,
+    /// Fails on a truncated gzipped input (the default).
+    #[arg(long, overrides_with = "tolerate_truncation")]
+    pub no_tolerate_truncation: bool,
+    /// Splits a rectangular input that stacks several matrices, separated by blank lines or
+    /// repeated header rows, into numbered output files.
+    #[arg(long, overrides_with = "no_multi_block")]
+    pub multi_block: bool,
+    /// Reads a rectangular input as a single matrix (the default).
+    #[arg(long, overrides_with = "multi_block")]
+    pub no_multi_block: bool,
+    /// Writes a matrix per value of this column of a long-format input, given by 0-based
+    /// position or header name, naming each output file after the value.
+    #[arg(long, value_name = "COLUMN")]
+    pub pivot_col: Option<String>,
+    /// A `zone,partition` CSV file; writes an MTX file per partition holding the rows of
+    /// its origins and every destination, named after the partition.
+    #[arg(long, value_name = "FILE")]
+    pub partition_file: Option<String>,
+    /// Combines each cell with its mirror across the diagonal before the triangle is taken.
+    #[arg(long, value_enum)]
+    pub symmetrize: Option<Symmetrize>,
+    /// Only writes one triangle of the matrix, zeroing the cells in the other.
+    #[arg(long, value_enum)]
+    pub triangle: Option<Triangle>,
+    /// Divides each cell by the sum of its row, its column or the whole matrix, after
+    /// the symmetry options are applied.
+    #[arg(long, value_enum)]
+    pub normalize: Option<Normalize>,
+    /// Zeroes the non-zero cells below this quantile of them, such as `0.95` to keep
+    /// the top 5% of flows.
+    #[arg(long)]
+    pub top_percentile: Option<f64>,
+    /// Adds padding zones, numbered after the largest zone, until the number of zones
+    /// is a power of two, for readers that need such a matrix.
+    #[arg(long, overrides_with = "no_pad_to_pow2")]
+    pub pad_to_pow2: bool,
+    /// Writes the matrix over its zones without padding (the default).
+    #[arg(long, overrides_with = "pad_to_pow2")]
+    pub no_pad_to_pow2: bool,
+    /// Computes each row's value from the named columns of an input with a header row,
+    /// such as `distance / time`. The first two columns are the origin and destination.
+    #[arg(long)]
+    pub value_expr: Option<String>,
+    /// Takes each row's value as the weighted sum of named columns of an input with a
+    /// header row, such as `auto=0.6,transit=0.4`.
+    #[arg(long)]
+    pub value_weights: Option<String>,
+    /// Reads a rectangular input whose rows have no origin column, so the n-th row is
+    /// the origin of the n-th destination in the header.
+    #[arg(long, overrides_with = "no_implicit_origins")]
+    pub implicit_origins: bool,
+    /// Reads the origin of each row of a rectangular input from its first column (the default).
+    #[arg(long, overrides_with = "implicit_origins")]
+    pub no_implicit_origins: bool,
+    /// Whether the header row of a rectangular input lists the destinations (the default)
+    /// or the origins, for files written as the transpose.
+    #[arg(long, value_enum)]
+    pub rect_orientation: Option<RectOrientation>,
+    /// Values that mark a missing value, such as `NA,null,-`. Their cells keep the fill
+    /// value instead of being reported as not numbers.
+    #[arg(long, value_delimiter = ',')]
+    pub na_values: Vec<String>,
+    /// Accepts zone labels written as integral decimals, such as `12.0`. A label such as
+    /// `12.5` is then an error.
+    #[arg(long, overrides_with = "no_lenient_zone_ids")]
+    pub lenient_zone_ids: bool,
+    /// Accepts only integers as zone labels (the default).
+    #[arg(long, overrides_with = "lenient_zone_ids")]
+    pub no_lenient_zone_ids: bool,
+    /// Asks at the terminal whether a first row of four fields starting with two zones is
+    /// the header row of a square CSV or a row of origin, destination and value. Without
+    /// a terminal, such an input is an error explaining how to choose.
+    #[arg(long, overrides_with = "no_interactive")]
+    pub interactive: bool,
+    /// Reads an input whose first row could be either format as a square CSV without asking (the default).
+    #[arg(long, overrides_with = "interactive")]
+    pub no_interactive: bool,
+    /// Removes this text from the start of every zone label before it is parsed, such as
+    /// the `Z` of `Z00123`.
+    #[arg(long, value_name = "TEXT")]
+    pub zone_prefix: Option<String>,
+    /// Removes this text from the end of every zone label before it is parsed.
+    #[arg(long, value_name = "TEXT")]
+    pub zone_suffix: Option<String>,
+    /// A `name,zone` CSV file giving the zone number of each zone name labelling the
+    /// rows, columns or header of the input, such as `Downtown`.
+    #[arg(long, value_name = "FILE")]
+    pub zone_map: Option<String>,
+    /// A `zone,district` CSV file whose districts the flows are summed into, writing a
+    /// matrix over the districts rather than the zones.
+    #[arg(long, value_name = "FILE")]
+    pub aggregate: Option<String>,
+    /// Turns the text of each value into a number with a built-in transform, such as
+    /// `minutes-colon` reading `3:30` as 3.5 minutes.
+    #[arg(long, visible_alias = "value-format", value_enum)]
+    pub value_transform: Option<ValueTransform>,
+    /// The unit `--value-transform hms` reads `HH:MM:SS` times in.
+    #[arg(long, value_enum)]
+    pub time_unit: Option<TimeUnit>,
+    /// What is done with negative values: kept, treated as missing so their cells take
+    /// the fill value, replaced by their magnitude, or an error.
+    #[arg(long, value_enum)]
+    pub clamp_negative: Option<ClampNegative>,
+    /// Reports the cells whose values are outside this `min:max` range, such as travel
+    /// times in the wrong unit, failing with --strict.
+    #[arg(long, value_name = "MIN:MAX")]
+    pub value_range: Option<String>,
+    /// Splits each cell of a rectangular input at this text, for cells holding several
+    /// values such as `3|4`, and combines them with --cell-agg.
+    #[arg(long, value_name = "SEPARATOR")]
+    pub cell_split: Option<String>,
+    /// How the values of a split cell are combined, adding them by default.
+    #[arg(long, value_enum)]
+    pub cell_agg: Option<CellAggregate>,
+    /// Discards the first N lines of the input, such as a title or a date, before its format is detected.
+    #[arg(long, value_name = "N")]
+    pub skip_rows: Option<usize>,
+    /// Fails unless the row labels of a rectangular input are the same zones as its header,
+    /// reporting the zones missing from either.
+    #[arg(long, overrides_with = "no_require_square")]
+    pub require_square: bool,
+    /// Allows the row labels of a rectangular input to differ from its header (the default).
+    #[arg(long, overrides_with = "require_square")]
+    pub no_require_square: bool,
+    /// Skips the rows of a 3-column input whose value is exactly zero, saving their memory.
+    #[arg(long, overrides_with = "no_drop_zero_rows")]
+    pub drop_zero_rows: bool,
+    /// Keeps the rows of a 3-column input whose value is zero (the default).
+    #[arg(long, overrides_with = "drop_zero_rows")]
+    pub no_drop_zero_rows: bool,
+    /// Which columns hold the origin, destination and value, such as
+    /// `origin=4,destination=3,value=2`, by 0-based position or by header name with `--header`.
+    #[arg(long)]
+    pub columns: Option<String>,
+    /// The first row of an input read with `--columns` is a header naming its columns.
+    #[arg(long, overrides_with = "no_header")]
+    pub header: bool,
+    /// The first row of an input read with `--columns` is a row of data (the default).
+    #[arg(long, overrides_with = "header")]
+    pub no_header: bool,
+    /// Renumbers the zones written with the matrix to 1..N in sorted order and writes
+    /// the `zone,new_id` mapping to this CSV file.
+    #[arg(long)]
+    pub renumber: Option<String>,
+    /// Writes a double-precision MTX file when a value cannot be stored exactly as a 32-bit float.
+    #[arg(long, overrides_with = "no_auto_promote")]
+    pub auto_promote: bool,
+    /// Always writes a single-precision MTX file (the default).
+    #[arg(long, overrides_with = "auto_promote")]
+    pub no_auto_promote: bool,
+    /// How the numbers in the sums and diagonal files are written: `shortest` (the default),
+    /// `{:.N}` for N decimal places, `{:e}` or `{:.Ne}`.
+    #[arg(long)]
+    pub float_format: Option<FloatFormat>,
+    /// Prints these quantiles of the non-zero cell values, such as `0.5,0.95`.
+    #[arg(long, value_delimiter = ',')]
+    pub quantiles: Vec<f64>,
+    /// Prints the first N non-zero cells of the built matrix as `origin,destination,value`.
+    #[arg(long, value_name = "N")]
+    pub sample_cells: Option<usize>,
+    /// Prints the N largest cells of the built matrix as `origin,destination,value`,
+    /// largest first.
+    #[arg(long, value_name = "N")]
+    pub top_flows: Option<usize>,
+    /// Writes the flows of `--top-flows` to this CSV file instead of standard error.
+    #[arg(long)]
+    pub top_flows_file: Option<String>,
+    /// Reads N randomly chosen cells back from the written MTX file and fails if any
+    /// differs from the built matrix.
+    #[arg(long, value_name = "N")]
+    pub verify_sample: Option<usize>,
+    /// Prints the estimated memory of the rows read, the dense matrix and the write
+    /// buffers to standard error, for sizing the memory of jobs.
+    #[arg(long, overrides_with = "no_profile_memory")]
+    pub profile_memory: bool,
+    /// Prints no memory estimate (the default).
+    #[arg(long, overrides_with = "profile_memory")]
+    pub no_profile_memory: bool,
+    /// Writes the MTX file in both byte orders, inserting `.le` and `.be` before its
+    /// extension, for archives that must stay readable whatever the reader.
+    #[arg(long, overrides_with = "no_dual_endian")]
+    pub dual_endian: bool,
+    /// Writes the MTX file in a single byte order (the default).
+    #[arg(long, overrides_with = "dual_endian")]
+    pub no_dual_endian: bool,
+    /// Also writes the transpose of the matrix to this file, in the same format,
+    /// without reading the input again.
+    #[arg(long, value_name = "FILE")]
+    pub also_transpose: Option<String>,
+    /// Writes every record left out of the matrix to this CSV file, with its line and
+    /// why it was left out.
+    #[arg(long)]
+    pub reject_log: Option<String>,
+    /// Fails before building the matrix when it would have more than N zones, such as
+    /// the 65535 zones some readers are limited to.
+    #[arg(long, value_name = "N")]
+    pub max_zones: Option<usize>,
+    /// A base CSV file whose matrix is subtracted from the input's, writing the difference
+    /// over the zones of both files.
+    #[arg(long, value_name = "BASE")]
+    pub relative_to: Option<String>,
+    /// Whether --relative-to refuses an input and base whose zones differ, or compares
+    /// them over the zones of both with a warning.
+    #[arg(long, value_enum)]
+    pub compare_zones: Option<CompareZones>,
+    /// The number of threads reading the input and building the matrix, instead of
+    /// rayon's global pool.
+    #[arg(long, value_name = "N")]
+    pub read_threads: Option<usize>,
+    /// The number of threads writing the matrix and its other outputs, instead of
+    /// rayon's global pool.
+    #[arg(long, value_name = "N")]
+    pub write_threads: Option<usize>,
+    /// Fails on suspicious input, such as zone numbers far larger than the number of zones, instead of warning.
+    #[arg(long, overrides_with = "no_strict")]
+    pub strict: bool,
+    /// Warns about suspicious input instead of failing (the default).
+    #[arg(long, overrides_with = "strict")]
+    pub no_strict: bool,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct InspectArgs {
+    /// The MTX file to inspect.
+    pub input: String,
+    /// Prints the top-left N by N corner of the matrix instead of its summary, reading
+    /// only those cells.
+    #[arg(long, value_name = "N", conflicts_with = "cell")]
+    pub head: Option<usize>,
+    /// Prints the value of the cell from ORIGIN to DESTINATION instead of the summary,
+    /// reading only that cell.
+    #[arg(long, value_delimiter = ',', value_name = "ORIGIN,DESTINATION")]
+    pub cell: Vec<i32>,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct DiffArgs {
+    /// The first MTX file.
+    pub first: String,
+    /// The second MTX file.
+    pub second: String,
+    /// Writes the difference (first - second) to this MTX file.
+    #[arg(long)]
+    pub output: Option<String>,
+    /// The largest absolute difference for two cells to be considered equal.
+    #[arg(long, default_value_t = 0.0)]
+    pub tolerance: f32,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct TransposeArgs {
+    /// The MTX file to transpose.
+    pub input: String,
+    /// The output file for the transposed matrix.
+    pub output: String,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct MarginalsArgs {
+    /// The MTX file to sum.
+    pub input: String,
+    /// The `zone,total` CSV file to write the row sums of the origins to.
+    pub productions: String,
+    /// The `zone,total` CSV file to write the column sums of the destinations to.
+    pub attractions: String,
+    /// How the totals are written: `shortest` (the default), `{:.N}` for N decimal
+    /// places, `{:e}` or `{:.Ne}`.
+    #[arg(long, default_value = "shortest")]
+    pub float_format: FloatFormat,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct ExportArgs {
+    /// The MTX file to export.
+    pub input: String,
+    /// Writes the whole matrix, including zeros, as a CSV file with the destinations as
+    /// its header row and the origins as its first column.
+    #[arg(long)]
+    pub to_rectangular: Option<String>,
+    /// Writes the non-zero cells as a Matrix Market coordinate file, with the zone numbers
+    /// of its rows and columns in comments.
+    #[arg(long)]
+    pub to_matrix_market: Option<String>,
+    /// Appends a `Total` column of row sums and a `Total` row of column sums to the
+    /// rectangular CSV file, with the grand total in the corner.
+    #[arg(long)]
+    pub with_totals: bool,
+    /// How the values are written: `shortest` (the default), `{:.N}` for N decimal
+    /// places, `{:e}` or `{:.Ne}`.
+    #[arg(long, default_value = "shortest")]
+    pub float_format: FloatFormat,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+impl ExportArgs {
+    /// Gets the files to export to.
+    pub fn options(&self) -> ExportOptions {
+        ExportOptions {
+            to_rectangular: self.to_rectangular.clone(),
+            to_matrix_market: self.to_matrix_market.clone(),
+            with_totals: self.with_totals,
+            float_format: self.float_format,
+        }
+    }
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct BatchConvertArgs {
+    /// The directory whose `.mtx` and `.mtx.gz` files are converted.
+    pub directory: String,
+    /// The format to convert the files to.
+    #[arg(long, value_enum)]
+    pub to: BatchFormat,
+    /// How the values are written: `shortest` (the default), `{:.N}` for N decimal
+    /// places, `{:e}` or `{:.Ne}`.
+    #[arg(long, default_value = "shortest")]
+    pub float_format: FloatFormat,
+    /// Stops at the first file that cannot be converted instead of skipping it.
+    #[arg(long)]
+    pub strict: bool,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct PackArgs {
+    /// The container file to write.
+    pub output: String,
+    /// The MTX files to pack, named by their file name without the `.mtx` or `.mtx.gz` extension.
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+    /// Syncs the container to disk before it replaces the output file.
+    #[arg(long)]
+    pub fsync: bool,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct UnpackArgs {
+    /// The container file written by `pack`.
+    pub input: String,
+    /// The directory to write each matrix to as `<name>.mtx`.
+    pub output_directory: String,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct ValidateZonesArgs {
+    /// The zones CSV file, with the zone numbers in its first column.
+    pub zones: String,
+    /// The MTX file to check the zones file against.
+    pub against: String,
+    /// The first row of the zones file is a header rather than a zone.
+    #[arg(long)]
+    pub zones_header: bool,
+    /// The character separating fields in the zones file.
+    #[arg(long, default_value_t = ',')]
+    pub delimiter: char,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+impl ValidateZonesArgs {
+    /// Gets the zones file to validate.
+    pub fn zones_file(&self) -> std::io::Result<ZonesFile<'_>> {
+        if !self.delimiter.is_ascii() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The delimiter '{}' must be a single ASCII character", self.delimiter),
+            ));
+        }
+        Ok(ZonesFile {
+            delimiter: self.delimiter as u8,
+            has_header: self.zones_header,
+            ..ZonesFile::new(&self.zones)
+        })
+    }
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct ZonesIntersectArgs {
+    /// The CSV and MTX files to compare. Files ending in `.mtx` or `.mtx.gz` are read as MTX files.
+    #[arg(required = true, num_args = 2..)]
+    pub inputs: Vec<String>,
+    /// The character separating fields in the CSV files.
+    #[arg(long, default_value_t = ',')]
+    pub delimiter: char,
+    #[command(flatten)]
+    pub read: MtxInputArgs,
+}
+
+impl ZonesIntersectArgs {
+    /// Gets the options for reading the CSV inputs.
+    pub fn csv_read_options(&self) -> std::io::Result<ReadOptions> {
+        let options = ConversionOptions {
+            delimiter: self.delimiter,
+            tolerate_truncation: self.read.tolerate_truncation,
+            ..ConversionOptions::default()
+        };
+        options.read_options()
+    }
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct GenerateArgs {
+    /// The CSV file to write.
+    pub output: String,
+    /// The number of zones, numbered from 1.
+    #[arg(long, default_value_t = 100)]
+    pub zones: usize,
+    /// The fraction of the cells that have a value.
+    #[arg(long, default_value_t = 0.1)]
+    pub density: f64,
+    /// The layout of the file.
+    #[arg(long, value_enum, default_value_t = GeneratedFormat::Column)]
+    pub format: GeneratedFormat,
+    /// How the values are distributed.
+    #[arg(long, value_enum, default_value_t = ValueDistribution::Uniform)]
+    pub distribution: ValueDistribution,
+    /// The mean of the values.
+    #[arg(long, default_value_t = 1.0)]
+    pub mean: f64,
+    /// The seed for the random numbers, so the same file can be generated again.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+}
+
+impl GenerateArgs {
+    /// Gets the settings for the generated file.
+    pub fn options(&self) -> GenerateOptions {
+        GenerateOptions {
+            zones: self.zones,
+            density: self.density,
+            format: self.format,
+            distribution: self.distribution,
+            mean: self.mean,
+            seed: self.seed,
+        }
+    }
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct SelftestArgs {
+    /// The seed for the generated matrix, taken from the clock by default.
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl SelftestArgs {
+    /// Gets the seed for the generated matrix.
+    pub fn seed(&self) -> u64 {
+        self.seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+        })
+    }
+}
+
+/// How the subcommands reading MTX files read them.
+#[derive(Args, Debug, PartialEq)]
+pub struct MtxInputArgs {
+    /// The byte order of the MTX input, detected from its header by default.
+    #[arg(long, value_enum)]
+    pub input_endianness: Option<Endianness>,
+    /// Keeps the cells read before a gzipped MTX file turns out to be truncated, leaving the rest zero.
+    #[arg(long)]
+    pub tolerate_truncation: bool,
+    /// How the zones of the index arrays are checked: a warning for a zone listed more
+    /// than once, an error for one, or an error unless they are strictly increasing.
+    #[arg(long, value_enum)]
+    pub index_check: Option<IndexCheck>,
+    /// The hexadecimal magic number the MTX input starts with, for files written with --magic.
+    #[arg(long, value_name = "HEX", value_parser = parse_magic)]
+    pub expect_magic: Option<u32>,
+}
+
+impl MtxInputArgs {
+    /// Gets the options for reading the MTX input.
+    pub fn read_options(&self) -> MtxReadOptions {
+        MtxReadOptions {
+            endianness: self.input_endianness,
+            tolerate_truncation: self.tolerate_truncation,
+            index_check: self.index_check.unwrap_or_default(),
+            expect_magic: self.expect_magic,
+        }
+    }
+}
+
+/// The subcommand names, used to recognize the legacy `csv_to_mtx <input> <output>` form.
+const SUBCOMMANDS: [&str; 14] = [
+    "convert",
+    "inspect",
+    "diff",
+    "transpose",
+    "marginals",
+    "export",
+    "batch-convert",
+    "pack",
+    "unpack",
+    "validate-zones",
+    "zones-intersect",
+    "generate",
+    "selftest",
+    "help",
+];
+
+impl Cli {
+    /// Parses the command line, routing the legacy form without a subcommand
+    /// to `convert`.
+    pub fn parse_with_legacy<I>(args: I) -> Result<Cli, clap::Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut args: Vec<String> = args.into_iter().collect();
+        if let Some(first) = args.get(1)
+            && !SUBCOMMANDS.contains(&first.as_str())
+            && !["-h", "--help", "-V", "--version"].contains(&first.as_str())
+        {
+            args.insert(1, "convert".to_string());
+        }
+        Cli::try_parse_from(args)
+    }
+}
+
+impl ConvertArgs {
+    /// Resolves the conversion options by starting from the config file, if any,
+    /// and then applying the command-line flags on top.
+    pub fn options(&self) -> std::io::Result<ConversionOptions> {
+        let mut options = match &self.config {
+            Some(config_file) => ConversionOptions::from_config_file(config_file)?,
+            None => ConversionOptions::default(),
+        };
+        if let Some(zones) = &self.zones {
+            options.zones_file = Some(zones.clone());
+        }
+        if let Some(delimiter) = self.delimiter {
+            options.delimiter = delimiter;
+        }
+        if let Some(select_zones_file) = &self.select_zones_file {
+            options.select_zones_file = Some(select_zones_file.clone());
+        }
+        if let Some(zones_mode) = self.zones_mode {
+            options.zones_mode = zones_mode;
+        }
+        if let Some(zone_source) = self.zone_source {
+            options.zone_source = Some(zone_source);
+        }
+        if self.zones_header {
+            options.zones_header = true;
+        }
+        if self.no_zones_header {
+            options.zones_header = false;
+        }
+        if let Some(format) = self.format {
+            options.format = format;
+        }
+        if let Some(endianness) = self.output_endianness {
+            options.output_endianness = endianness;
+        }
+        if let Some(gzip_filter) = self.gzip_filter {
+            options.gzip_filter = gzip_filter;
+        }
+        if let Some(index_width) = self.index_width {
+            options.index_width = index_width;
+        }
+        if let Some(gzip_blocks) = self.gzip_blocks {
+            options.gzip_blocks = Some(gzip_blocks);
+        }
+        if let Some(magic) = self.magic {
+            options.magic = Some(magic);
+        }
+        if let Some(row_align) = self.row_align {
+            options.row_align = Some(row_align);
+        }
+        if let Some(row_sums) = &self.row_sums {
+            options.row_sums = Some(row_sums.clone());
+        }
+        if let Some(col_sums) = &self.col_sums {
+            options.col_sums = Some(col_sums.clone());
+        }
+        if let Some(diagonal) = &self.diagonal {
+            options.diagonal = Some(diagonal.clone());
+        }
+        if self.force {
+            options.force = true;
+        }
+        if self.no_force {
+            options.force = false;
+        }
+        if self.low_memory {
+            options.low_memory = true;
+        }
+        if self.no_low_memory {
+            options.low_memory = false;
+        }
+        if self.fsync {
+            options.fsync = true;
+        }
+        if self.no_fsync {
+            options.fsync = false;
+        }
+        if self.stamp_metadata {
+            options.stamp_metadata = true;
+        }
+        if self.no_stamp_metadata {
+            options.stamp_metadata = false;
+        }
+        if self.reproducible {
+            options.reproducible = true;
+        }
+        if self.no_reproducible {
+            options.reproducible = false;
+        }
+        if self.canonical_order {
+            options.canonical_order = true;
+        }
+        if self.no_canonical_order {
+            options.canonical_order = false;
+        }
+        if let Some(fill) = self.fill {
+            options.fill = fill;
+        }
+        if let Some(min_value) = self.min_value {
+            options.min_value = Some(min_value);
+        }
+        if let Some(max_value) = self.max_value {
+            options.max_value = Some(max_value);
+        }
+        if self.count_only {
+            options.count_only = true;
+        }
+        if self.no_count_only {
+            options.count_only = false;
+        }
+        if self.explain {
+            options.explain = true;
+        }
+        if self.no_explain {
+            options.explain = false;
+        }
+        if self.tolerate_truncation {
+            options.tolerate_truncation = true;
+        }
+        if self.no_tolerate_truncation {
+            options.tolerate_truncation = false;
+        }
+        if self.multi_block {
+            options.multi_block = true;
+        }
+        if self.no_multi_block {
+            options.multi_block = false;
+        }
+        if let Some(pivot_col) = &self.pivot_col {
+            options.pivot_col = Some(pivot_col.clone());
+        }
+        if let Some(partition_file) = &self.partition_file {
+            options.partition_file = Some(partition_file.clone());
+        }
+        if let Some(symmetrize) = self.symmetrize {
+            options.symmetrize = symmetrize;
+        }
+        if let Some(triangle) = self.triangle {
+            options.triangle = triangle;
+        }
+        if let Some(normalize) = self.normalize {
+            options.normalize = normalize;
+        }
+        if let Some(top_percentile) = self.top_percentile {
+            options.top_percentile = Some(top_percentile);
+        }
+        if self.pad_to_pow2 {
+            options.pad_to_pow2 = true;
+        }
+        if self.no_pad_to_pow2 {
+            options.pad_to_pow2 = false;
+        }
+        if let Some(value_expr) = &self.value_expr {
+            options.value_expr = Some(value_expr.clone());
+        }
+        if let Some(value_weights) = &self.value_weights {
+            options.value_weights = Some(value_weights.clone());
+        }
+        if self.implicit_origins {
+            options.implicit_origins = true;
+        }
+        if self.no_implicit_origins {
+            options.implicit_origins = false;
+        }
+        if let Some(rect_orientation) = self.rect_orientation {
+            options.rect_orientation = rect_orientation;
+        }
+        if !self.na_values.is_empty() {
+            options.na_values = self.na_values.clone();
+        }
+        if self.lenient_zone_ids {
+            options.lenient_zone_ids = true;
+        }
+        if self.no_lenient_zone_ids {
+            options.lenient_zone_ids = false;
+        }
+        if self.interactive {
+            options.interactive = true;
+        }
+        if self.no_interactive {
+            options.interactive = false;
+        }
+        if let Some(zone_prefix) = &self.zone_prefix {
+            options.zone_prefix = Some(zone_prefix.clone());
+        }
+        if let Some(zone_suffix) = &self.zone_suffix {
+            options.zone_suffix = Some(zone_suffix.clone());
+        }
+        if let Some(zone_map) = &self.zone_map {
+            options.zone_map = Some(zone_map.clone());
+        }
+        if let Some(aggregate) = &self.aggregate {
+            options.aggregate = Some(aggregate.clone());
+        }
+        if let Some(value_transform) = self.value_transform {
+            options.value_transform = Some(value_transform);
+        }
+        if let Some(time_unit) = self.time_unit {
+            options.time_unit = time_unit;
+        }
+        if let Some(clamp_negative) = self.clamp_negative {
+            options.clamp_negative = clamp_negative;
+        }
+        if let Some(value_range) = &self.value_range {
+            options.value_range = Some(value_range.clone());
+        }
+        if let Some(cell_split) = &self.cell_split {
+            options.cell_split = Some(cell_split.clone());
+        }
+        if let Some(cell_agg) = self.cell_agg {
+            options.cell_agg = cell_agg;
+        }
+        if let Some(skip_rows) = self.skip_rows {
+            options.skip_rows = skip_rows;
+        }
+        if self.require_square {
+            options.require_square = true;
+        }
+        if self.no_require_square {
+            options.require_square = false;
+        }
+        if self.drop_zero_rows {
+            options.drop_zero_rows = true;
+        }
+        if self.no_drop_zero_rows {
+            options.drop_zero_rows = false;
+        }
+        if let Some(columns) = &self.columns {
+            options.columns = Some(columns.clone());
+        }
+        if self.header {
+            options.header = true;
+        }
+        if self.no_header {
+            options.header = false;
+        }
+        if let Some(renumber) = &self.renumber {
+            options.renumber = Some(renumber.clone());
+        }
+        if self.auto_promote {
+            options.auto_promote = true;
+        }
+        if self.no_auto_promote {
+            options.auto_promote = false;
+        }
+        if let Some(float_format) = self.float_format {
+            options.float_format = float_format;
+        }
+        if !self.quantiles.is_empty() {
+            options.quantiles = self.quantiles.clone();
+        }
+        if let Some(sample_cells) = self.sample_cells {
+            options.sample_cells = Some(sample_cells);
+        }
+        if let Some(top_flows) = self.top_flows {
+            options.top_flows = Some(top_flows);
+        }
+        if let Some(verify_sample) = self.verify_sample {
+            options.verify_sample = Some(verify_sample);
+        }
+        if self.profile_memory {
+            options.profile_memory = true;
+        }
+        if self.no_profile_memory {
+            options.profile_memory = false;
+        }
+        if self.dual_endian {
+            options.dual_endian = true;
+        }
+        if self.no_dual_endian {
+            options.dual_endian = false;
+        }
+        if let Some(also_transpose) = &self.also_transpose {
+            options.also_transpose = Some(also_transpose.clone());
+        }
+        if let Some(top_flows_file) = &self.top_flows_file {
+            options.top_flows_file = Some(top_flows_file.clone());
+        }
+        if let Some(reject_log) = &self.reject_log {
+            options.reject_log = Some(reject_log.clone());
+        }
+        if let Some(max_zones) = self.max_zones {
+            options.max_zones = Some(max_zones);
+        }
+        if let Some(relative_to) = &self.relative_to {
+            options.relative_to = Some(relative_to.clone());
+        }
+        if let Some(compare_zones) = self.compare_zones {
+            options.compare_zones = compare_zones;
+        }
+        if let Some(read_threads) = self.read_threads {
+            options.read_threads = Some(read_threads);
+        }
+        if let Some(write_threads) = self.write_threads {
+            options.write_threads = Some(write_threads);
+        }
+        if self.strict {
+            options.strict = true;
+        }
+        if self.no_strict {
+            options.strict = false;
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::parse_with_legacy(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    fn convert_args(args: &[&str]) -> ConvertArgs {
+        match parse(args).command {
+            Command::Convert(args) => *args,
+            command => panic!("Expected the convert command, found {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_legacy_and_subcommand_forms() {
+        let legacy = convert_args(&["csv_to_mtx", "in.csv", "out.mtx", "zones.csv"]);
+        let subcommand = convert_args(&["csv_to_mtx", "convert", "in.csv", "out.mtx", "zones.csv"]);
+        assert_eq!(legacy, subcommand);
+        assert_eq!(legacy.input, "in.csv");
+        assert_eq!(legacy.zones.as_deref(), Some("zones.csv"));
+
+        // Flags before the positional arguments still use the legacy form
+        let legacy = convert_args(&["csv_to_mtx", "--delimiter", ";", "in.csv", "out.mtx"]);
+        assert_eq!(legacy.delimiter, Some(';'));
+
+        assert_eq!(
+            parse(&["csv_to_mtx", "transpose", "in.mtx", "out.mtx"]).command,
+            Command::Transpose(TransposeArgs {
+                input: "in.mtx".to_string(),
+                output: "out.mtx".to_string(),
+                read: MtxInputArgs {
+                    input_endianness: None,
+                    tolerate_truncation: false,
+                    index_check: None,
+                    expect_magic: None,
+                },
+            })
+        );
+        let args = convert_args(&["csv_to_mtx", "convert", "in.csv", "out.mtx", "--magic", "0x1234abcd"]);
+        assert_eq!(args.magic, Some(0x1234ABCD));
+        assert!(matches!(
+            parse(&["csv_to_mtx", "export", "in.mtx", "--to-rectangular", "out.csv"]).command,
+            Command::Export(ExportArgs { to_rectangular: Some(ref file), .. }) if file == "out.csv"
+        ));
+        assert!(matches!(
+            parse(&["csv_to_mtx", "generate", "synthetic.csv", "--zones", "10"]).command,
+            Command::Generate(GenerateArgs { zones: 10, .. })
+        ));
+        assert_eq!(
+            parse(&["csv_to_mtx", "selftest", "--seed", "7"]).command,
+            Command::Selftest(SelftestArgs { seed: Some(7) })
+        );
+    }
+
+    #[test]
+    fn test_config_file_delimiter() -> std::io::Result<()> {
+        let args = convert_args(&[
+            "csv_to_mtx", "test/test_semicolon.csv", "test/test_semicolon_output.mtx",
+            "--config", "test/semicolon.toml", "--force",
+        ]);
+        let options = args.options()?;
+        assert_eq!(options.delimiter, ';');
+        csv_to_mtx::convert::convert_csv_to_mtx(&args.input, &args.output, &options)?;
+        let output_data = std::fs::read(&args.output)?;
+        let expected_data = std::fs::read("test/test_expected.mtx")?;
+        assert_eq!(output_data, expected_data);
+
+        // A flag on the command line takes precedence over the config file
+        let args = convert_args(&[
+            "csv_to_mtx", "in.csv", "out.mtx",
+            "--config", "test/semicolon.toml", "--delimiter", ",",
+        ]);
+        assert_eq!(args.options()?.delimiter, ',');
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_file_flags_turned_off() -> std::io::Result<()> {
+        let config_file = std::env::temp_dir().join("csv_to_mtx_test_flags_turned_off.toml");
+        std::fs::write(&config_file, "force = true\nstrict = true\nstamp_metadata = true\npad_to_pow2 = true\n")?;
+        let config_file = config_file.to_string_lossy().to_string();
+        let args = convert_args(&["csv_to_mtx", "in.csv", "out.mtx", "--config", &config_file]);
+        let options = args.options()?;
+        assert!(options.force && options.strict && options.stamp_metadata && options.pad_to_pow2);
+
+        // The `--no-` form of a flag turns off what the config file turned on
+        let args = convert_args(&[
+            "csv_to_mtx", "in.csv", "out.mtx", "--config", &config_file, "--no-force", "--no-strict", "--no-pad-to-pow2",
+        ]);
+        let options = args.options()?;
+        assert!(!options.force && !options.strict && !options.pad_to_pow2 && options.stamp_metadata);
+
+        // The last of a flag and its `--no-` form wins
+        let args = convert_args(&["csv_to_mtx", "in.csv", "out.mtx", "--no-force", "--force", "--strict", "--no-strict"]);
+        let options = args.options()?;
+        assert!(options.force && !options.strict);
+
+        // Every flag of the convert command has a `--no-` form
+        let command = <Cli as clap::CommandFactory>::command();
+        let convert = command.find_subcommand("convert").unwrap();
+        for arg in convert.get_arguments() {
+            let id = arg.get_id().as_str();
+            if matches!(arg.get_action(), clap::ArgAction::SetTrue) && !id.starts_with("no_") {
+                let no_id = format!("no_{}", id);
+                assert!(
+                    convert.get_arguments().any(|other| other.get_id() == no_id.as_str()),
+                    "--{} has no --no- form",
+                    id
+                );
+            }
+        }
+        std::fs::remove_file(config_file)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+/// A column of the input CSV file, given by its position or its header name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnRef {
+    /// The 0-based position of the column.
+    Index(usize),
+    /// The name of the column in the header row.
+    Name(String),
+}
+
+impl ColumnRef {
+    /// Parses a column, where a number is its 0-based position and anything else is
+    /// the name of the column in the header row.
+    pub fn parse(text: &str) -> ColumnRef {
+        match text.trim().parse::<usize>() {
+            Ok(index) => ColumnRef::Index(index),
+            Err(_) => ColumnRef::Name(text.trim().to_string()),
+        }
+    }
+
+    /// Finds the position of the column.
+    ///
+    /// # Arguments
+    /// * `header` - The header row, required if the column is given by name.
+    pub fn resolve(&self, header: Option<&csv::StringRecord>) -> std::io::Result<usize> {
+        match self {
+            ColumnRef::Index(index) => Ok(*index),
+            ColumnRef::Name(name) => {
+                let header = header.ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("The column '{}' is given by name, which requires --header", name),
+                    )
+                })?;
+                header.iter().position(|column| column.trim() == name).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("The column '{}' is not in the header", name),
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Which columns of the input CSV file hold the origin, destination and value, for
+/// files with extra columns or columns in a different order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub origin: ColumnRef,
+    pub destination: ColumnRef,
+    pub value: ColumnRef,
+}
+
+impl ColumnMapping {
+    /// Parses a mapping such as `origin=4,destination=3,value=2`. A number is the
+    /// 0-based position of the column, anything else is the name of the column in
+    /// the header row.
+    pub fn parse(text: &str) -> std::io::Result<ColumnMapping> {
+        let invalid = |problem: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid column mapping '{}': {}", text, problem),
+            )
+        };
+        let (mut origin, mut destination, mut value) = (None, None, None);
+        for part in text.split(',') {
+            let (key, column) = part
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("'{}' is not of the form key=column", part.trim())))?;
+            let column = ColumnRef::parse(column);
+            let slot = match key.trim() {
+                "origin" => &mut origin,
+                "destination" => &mut destination,
+                "value" => &mut value,
+                key => return Err(invalid(format!("unknown key '{}', expected origin, destination or value", key))),
+            };
+            if slot.replace(column).is_some() {
+                return Err(invalid(format!("'{}' is given more than once", key.trim())));
+            }
+        }
+        match (origin, destination, value) {
+            (Some(origin), Some(destination), Some(value)) => Ok(ColumnMapping {
+                origin,
+                destination,
+                value,
+            }),
+            _ => Err(invalid("origin, destination and value are all required".to_string())),
+        }
+    }
+
+    /// Finds the positions of the origin, destination and value columns.
+    ///
+    /// # Arguments
+    /// * `header` - The header row, required if any column is given by name.
+    pub fn resolve(&self, header: Option<&csv::StringRecord>) -> std::io::Result<[usize; 3]> {
+        Ok([self.origin.resolve(header)?, self.destination.resolve(header)?, self.value.resolve(header)?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_column_mapping() -> std::io::Result<()> {
+        let mapping = ColumnMapping::parse("origin=4, destination=dest,value=2")?;
+        assert_eq!(mapping.destination, ColumnRef::Name("dest".to_string()));
+        let header = csv::StringRecord::from(vec!["id", "time", "value", "dest", "origin", "flag"]);
+        assert_eq!(mapping.resolve(Some(&header))?, [4, 3, 2]);
+        assert!(mapping.resolve(None).is_err());
+
+        assert!(ColumnMapping::parse("origin=4,destination=3").is_err());
+        assert!(ColumnMapping::parse("origin=4,destination=3,value=2,origin=1").is_err());
+        assert!(ColumnMapping::parse("origin=4,dest=3,value=2").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,2246 @@
+use crate::csr::{write_csr_file, CsrMatrix};
+use crate::csv_output::{write_cells, write_zone_values, FloatFormat};
+use crate::columns::ColumnRef;
+use crate::explain::print_conversion_plan;
+use crate::input::{read_csv, read_csv_as, read_csv_blocks, read_csv_pivoted};
+use crate::low_memory::build_matrix_low_memory;
+use crate::matrix::{
+    build_aggregated_matrix, build_matrix, build_matrix_with_rejects, column_sums, count_non_zero_cells, diagonal, keep_triangle,
+    loses_precision, normalize, row_sums, sample_cells, symmetrize, top_flows, transpose, CellValue,
+};
+use crate::memory::MemoryProfile;
+use crate::mtx::{verify_mtx_sample, write_buffer_bytes, write_mtx_file, write_raw_file, Endianness, GzipFilter, IndexWidth, MtxReadOptions, MtxWriteOptions};
+use crate::options::ConversionOptions;
+use crate::quantiles::{approximate_quantiles, check_quantiles, exact_quantiles, keep_top_quantile, print_quantiles};
+use crate::rejects::RejectLog;
+use crate::zones::{check_zone_limit, get_all_zones, pad_zones_to_power_of_two, read_zone_aggregation, zone_differences, CompareZones, read_zone_partitions, suspicious_zone_numbers, ZonesFile, ZonesMode};
+use rayon::prelude::*; // For parallel processing
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The file format written by the conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// The binary MTX format.
+    #[default]
+    Mtx,
+    /// A Parquet file of the non-zero cells, requiring the `parquet` feature.
+    Parquet,
+    /// Only the values of the MTX format, without its header or zone numbers.
+    Raw,
+    /// The non-zero cells in compressed sparse row form, for fast access to any row.
+    Csr,
+}
+
+/// Converts the input CSV file to MTX format and writes it to the output file.
+/// An existing output file is only replaced when `options.force` is set.
+pub fn convert_csv_to_mtx(
+    input_file: &str,
+    output_file: &str,
+    options: &ConversionOptions,
+) -> std::io::Result<()> {
+    if let Err(e) = check_quantiles(&options.quantiles).and_then(|_| check_quantiles(options.top_percentile.as_slice())) {
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if options.index_width != IndexWidth::W32 && options.format != OutputFormat::Mtx {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--index-width is recorded in the header of an MTX file and cannot be used with other formats",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if options.gzip_filter != GzipFilter::None && options.format != OutputFormat::Mtx {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--gzip-filter is recorded in the header of an MTX file and cannot be used with other formats",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if let Some(align) = options.row_align {
+        let problem = if !align.is_power_of_two() {
+            Some(format!("--row-align needs a power of two rather than {}", align))
+        } else if options.format != OutputFormat::Mtx || options.gzip_filter != GzipFilter::None {
+            Some("--row-align pads the rows of an MTX file and needs --format mtx without --gzip-filter".to_string())
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            let e = std::io::Error::new(std::io::ErrorKind::InvalidInput, problem);
+            eprintln!("Error reading options: {}", e);
+            return Err(e);
+        }
+    }
+    if options.magic.is_some() && options.format != OutputFormat::Mtx {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--magic starts an MTX file and cannot be used with other formats",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if let Some(rows) = options.gzip_blocks {
+        let problem = if rows == 0 {
+            Some("--gzip-blocks needs at least one row per block")
+        } else if options.format != OutputFormat::Mtx || !output_file.ends_with(".gz") {
+            Some("--gzip-blocks splits a gzip-compressed MTX file and needs --format mtx and an output ending in .gz")
+        } else if options.gzip_filter != GzipFilter::None {
+            Some("--gzip-blocks cannot be combined with --gzip-filter, which spreads each row through the file")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            let e = std::io::Error::new(std::io::ErrorKind::InvalidInput, problem);
+            eprintln!("Error reading options: {}", e);
+            return Err(e);
+        }
+    }
+    if options.explain {
+        return print_conversion_plan(input_file, output_file, options).inspect_err(|e| {
+            eprintln!("Error reading options: {}", e);
+        });
+    }
+    let pools = match PhasePools::new(options) {
+        Ok(pools) => pools,
+        Err(e) => {
+            eprintln!("Error reading options: {}", e);
+            return Err(e);
+        }
+    };
+    if options.count_only {
+        return pools.read(|| count_cells(input_file, options));
+    }
+    // A file per block, pivot value, partition or byte order is checked as it is
+    // written instead, as the output file itself is never written
+    let splits_output = options.multi_block || options.pivot_col.is_some() || options.partition_file.is_some() || options.dual_endian;
+    if !splits_output && let Err(e) = check_output_file(output_file, options.force) {
+        eprintln!("Error writing MTX file: {}", e);
+        return Err(e);
+    }
+    if options.relative_to.is_some() && (options.multi_block || options.auto_promote || options.low_memory) {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--relative-to cannot be combined with --multi-block, --auto-promote or --low-memory",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if options.pivot_col.is_some() && (options.multi_block || options.auto_promote || options.low_memory) {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--pivot-col cannot be combined with --multi-block, --auto-promote or --low-memory",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if options.reject_log.is_some()
+        && (options.relative_to.is_some() || options.pivot_col.is_some() || options.multi_block || options.low_memory)
+    {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--reject-log cannot be combined with --relative-to, --pivot-col, --multi-block or --low-memory",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if options.dual_endian && (options.format != OutputFormat::Mtx || output_file == "-" || options.verify_sample.is_some()) {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--dual-endian writes two MTX files and cannot be used with other formats, standard output or --verify-sample",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if options.verify_sample.is_some()
+        && (options.format != OutputFormat::Mtx || output_file == "-" || options.partition_file.is_some())
+    {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--verify-sample reads back a single MTX file and cannot be used with other formats, standard output or --partition-file",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if let Some(transpose_file) = &options.also_transpose {
+        let problem = if options.partition_file.is_some() {
+            Some("--also-transpose cannot be combined with --partition-file".to_string())
+        } else if transpose_file == "-" || transpose_file == output_file {
+            Some(format!("--also-transpose needs a file of its own rather than {}", transpose_file))
+        } else {
+            None
+        };
+        let result = match problem {
+            Some(problem) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, problem)),
+            // A file per block or pivot value is checked as it is written
+            None if options.multi_block || options.pivot_col.is_some() => Ok(()),
+            None => check_output_file(transpose_file, options.force),
+        };
+        if let Err(e) = result {
+            eprintln!("Error reading options: {}", e);
+            return Err(e);
+        }
+    }
+    if options.aggregate.is_some()
+        && (options.relative_to.is_some()
+            || options.pivot_col.is_some()
+            || options.multi_block
+            || options.low_memory
+            || options.reject_log.is_some()
+            || options.partition_file.is_some()
+            || options.pad_to_pow2)
+    {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--aggregate cannot be combined with --relative-to, --pivot-col, --multi-block, --low-memory, --reject-log, --partition-file or --pad-to-pow2",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = options.value_range() {
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if options.value_range.is_some() && options.low_memory {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--value-range checks the rows read into memory and cannot be combined with --low-memory",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if options.pad_to_pow2 && options.low_memory {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--pad-to-pow2 cannot be combined with --low-memory",
+        );
+        eprintln!("Error reading options: {}", e);
+        return Err(e);
+    }
+    if let Some(pivot_col) = &options.pivot_col {
+        return convert_pivoted(input_file, output_file, &ColumnRef::parse(pivot_col), options, &pools);
+    }
+    if options.multi_block {
+        return convert_blocks(input_file, output_file, options, &pools);
+    }
+    if options.auto_promote {
+        return convert_promoting(input_file, output_file, options, &pools);
+    }
+    let (all_zones, matrix) = pools.read(|| read_zones_and_matrix(input_file, options))?;
+    pools.write(|| write_matrix(input_file, output_file, &all_zones, matrix, options))
+}
+
+/// The thread pools that reading the input and building the matrix, and writing the
+/// matrix and its other outputs, run on. Either phase without its own pool runs on
+/// rayon's global pool.
+struct PhasePools {
+    read: Option<rayon::ThreadPool>,
+    write: Option<rayon::ThreadPool>,
+}
+
+impl PhasePools {
+    fn new(options: &ConversionOptions) -> std::io::Result<PhasePools> {
+        let pool = |threads: Option<usize>| {
+            threads
+                .map(|threads| {
+                    rayon::ThreadPoolBuilder::new()
+                        .num_threads(threads)
+                        .build()
+                        .map_err(|e| std::io::Error::other(format!("Cannot start {} threads: {}", threads, e)))
+                })
+                .transpose()
+        };
+        Ok(PhasePools {
+            read: pool(options.read_threads)?,
+            write: pool(options.write_threads)?,
+        })
+    }
+
+    /// Runs part of the read phase.
+    fn read<T: Send>(&self, phase: impl FnOnce() -> T + Send) -> T {
+        match &self.read {
+            Some(pool) => pool.install(phase),
+            None => phase(),
+        }
+    }
+
+    /// Runs part of the write phase.
+    fn write<T: Send>(&self, phase: impl FnOnce() -> T + Send) -> T {
+        match &self.write {
+            Some(pool) => pool.install(phase),
+            None => phase(),
+        }
+    }
+}
+
+/// Applies the symmetry and normalization options to a built `size * size` matrix.
+fn shape_matrix<V: CellValue>(matrix: &mut [V], size: usize, options: &ConversionOptions) {
+    if let Some(top_percentile) = options.top_percentile {
+        keep_top_quantile(matrix, top_percentile);
+    }
+    symmetrize(matrix, size, options.symmetrize);
+    keep_triangle(matrix, size, options.triangle);
+    normalize(matrix, size, options.normalize);
+}
+
+/// Applies the symmetry and normalization options to the matrix and writes it with
+/// its other outputs.
+fn write_matrix<V: CellValue>(
+    input_file: &str,
+    output_file: &str,
+    all_zones: &[i32],
+    mut matrix: Vec<V>,
+    options: &ConversionOptions,
+) -> std::io::Result<()> {
+    shape_matrix(&mut matrix, all_zones.len(), options);
+    if output_file == "-" {
+        // Standard output is carrying the matrix itself
+        eprintln!("Found {} zones", all_zones.len());
+    } else {
+        println!("Found {} zones", all_zones.len());
+    }
+    if all_zones.is_empty() {
+        eprintln!("Warning: no zones were found in {}, writing an empty matrix", input_file);
+    }
+    write_outputs(input_file, output_file, all_zones, &matrix, options)
+}
+
+/// Reads the values at double precision and writes a double-precision MTX file when
+/// any of them cannot be stored exactly as a 32-bit float, otherwise a normal one.
+fn convert_promoting(
+    input_file: &str,
+    output_file: &str,
+    options: &ConversionOptions,
+    pools: &PhasePools,
+) -> std::io::Result<()> {
+    if matches!(options.format, OutputFormat::Parquet | OutputFormat::Csr) {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--auto-promote only applies to MTX and raw output",
+        );
+        eprintln!("Error writing output file: {}", e);
+        return Err(e);
+    }
+    let read = options.read_options().and_then(|read_options| {
+        let data = pools.read(|| read_csv_as::<f64>(input_file, &read_options))?;
+        Ok((data, read_options.reject_log))
+    });
+    let (data, rejects) = match read {
+        Ok(read) => read,
+        Err(e) => {
+            eprintln!("Error reading CSV file: {}", e);
+            return Err(e);
+        }
+    };
+    check_value_range(input_file, &data, options)?;
+    if options.aggregate.is_some() {
+        // The district sums rather than the rows decide the precision, as rows that are
+        // each exact can add up to a sum that is not
+        let (districts, sums) = pools.read(|| zones_and_matrix(&data, options, rejects.as_ref()))?;
+        if pools.read(|| sums.par_iter().any(|&sum| loses_precision::<f32>(sum))) {
+            eprintln!(
+                "Warning: district sums of {} cannot be stored exactly as 32-bit floats, writing a double-precision matrix",
+                input_file
+            );
+            return pools.write(|| write_matrix(input_file, output_file, &districts, sums, options));
+        }
+        let matrix: Vec<f32> = pools.read(|| sums.into_par_iter().map(f32::from_f64).collect());
+        return pools.write(|| write_matrix(input_file, output_file, &districts, matrix, options));
+    }
+    let promote = pools.read(|| data.par_iter().any(|&(_, _, value)| loses_precision::<f32>(value)));
+    if promote {
+        eprintln!(
+            "Warning: values in {} cannot be stored exactly as 32-bit floats, writing a double-precision matrix",
+            input_file
+        );
+        let (all_zones, matrix) = pools.read(|| zones_and_matrix(&data, options, rejects.as_ref()))?;
+        pools.write(|| write_matrix(input_file, output_file, &all_zones, matrix, options))
+    } else {
+        let (all_zones, matrix) = pools.read(|| {
+            let data: Vec<(i32, i32, f32)> = data
+                .into_par_iter()
+                .map(|(origin, destination, value)| (origin, destination, f32::from_f64(value)))
+                .collect();
+            zones_and_matrix(&data, options, rejects.as_ref())
+        })?;
+        pools.write(|| write_matrix(input_file, output_file, &all_zones, matrix, options))
+    }
+}
+
+/// Writes the matrix in the requested format along with any sums, diagonal or zone
+/// mapping that were asked for. Renumbering only changes the zones written with the
+/// matrix, the other files keep the original zone numbers.
+fn write_outputs<V: CellValue>(
+    input_file: &str,
+    output_file: &str,
+    all_zones: &[i32],
+    matrix: &[V],
+    options: &ConversionOptions,
+) -> std::io::Result<()> {
+    if !options.quantiles.is_empty() {
+        // The t-digest avoids copying the values when memory is short
+        let values = if options.low_memory {
+            approximate_quantiles(matrix, &options.quantiles)
+        } else {
+            exact_quantiles(matrix, &options.quantiles)
+        };
+        print_quantiles(&options.quantiles, &values);
+    }
+    if let Some(count) = options.sample_cells {
+        for (origin, destination, value) in sample_cells(matrix, all_zones, count) {
+            eprintln!("Cell {},{},{}", origin, destination, value);
+        }
+    }
+    if let Some(count) = options.top_flows {
+        let flows = top_flows(matrix, all_zones, count);
+        match &options.top_flows_file {
+            Some(top_flows_file) => {
+                if let Err(e) = write_cells(top_flows_file, &flows, options.float_format) {
+                    eprintln!("Error writing top flows: {}", e);
+                    return Err(e);
+                }
+            }
+            None => {
+                for (origin, destination, value) in flows {
+                    eprintln!("Flow {},{},{}", origin, destination, options.float_format.format(value));
+                }
+            }
+        }
+    }
+    if let Some(row_sums_file) = &options.row_sums {
+        let sums = row_sums(matrix, all_zones.len());
+        if let Err(e) = write_zone_values(row_sums_file, "sum", all_zones, &sums, options.float_format) {
+            eprintln!("Error writing row sums: {}", e);
+            return Err(e);
+        }
+    }
+    if let Some(col_sums_file) = &options.col_sums {
+        let sums = column_sums(matrix, all_zones.len());
+        if let Err(e) = write_zone_values(col_sums_file, "sum", all_zones, &sums, options.float_format) {
+            eprintln!("Error writing column sums: {}", e);
+            return Err(e);
+        }
+    }
+    if let Some(diagonal_file) = &options.diagonal {
+        let (zones, values) = diagonal(matrix, all_zones, all_zones);
+        if let Err(e) = write_zone_values(diagonal_file, "value", &zones, &values, options.float_format) {
+            eprintln!("Error writing diagonal: {}", e);
+            return Err(e);
+        }
+    }
+    let renumbered_zones: Vec<i32>;
+    let output_zones = match &options.renumber {
+        Some(mapping_file) => {
+            renumbered_zones = (1..=all_zones.len() as i32).collect();
+            if let Err(e) = write_zone_values(mapping_file, "new_id", all_zones, &renumbered_zones, FloatFormat::Shortest) {
+                eprintln!("Error writing zone mapping: {}", e);
+                return Err(e);
+            }
+            &renumbered_zones
+        }
+        None => all_zones,
+    };
+    if let Some(partition_file) = &options.partition_file {
+        return write_partitions(input_file, output_file, all_zones, output_zones, matrix, partition_file, options);
+    }
+    write_format(input_file, output_file, output_zones, matrix, options)?;
+    if let Some(count) = options.verify_sample {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        let read_options = MtxReadOptions {
+            expect_magic: options.magic,
+            ..MtxReadOptions::default()
+        };
+        match verify_mtx_sample(output_file, output_zones, output_zones, matrix, &read_options, count, seed) {
+            Ok(checked) => println!("Verified {} cells read back from {}", checked, output_file),
+            Err(e) => {
+                eprintln!("Error verifying MTX file: {}", e);
+                return Err(e);
+            }
+        }
+    }
+    if let Some(transpose_file) = &options.also_transpose {
+        let transposed = transpose(matrix, all_zones.len(), all_zones.len());
+        write_format(input_file, transpose_file, output_zones, &transposed, options)?;
+    }
+    Ok(())
+}
+
+/// Writes the matrix in the requested format, over the same zones for its rows and columns.
+fn write_format<V: CellValue>(
+    input_file: &str,
+    output_file: &str,
+    output_zones: &[i32],
+    matrix: &[V],
+    options: &ConversionOptions,
+) -> std::io::Result<()> {
+    if options.dual_endian && options.format == OutputFormat::Mtx {
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let endian_output_file = endian_file(output_file, endianness);
+            let write_options = MtxWriteOptions {
+                endianness,
+                ..write_options(input_file, options)
+            };
+            check_output_file(&endian_output_file, options.force)
+                .and_then(|_| write_mtx_file(&endian_output_file, output_zones, output_zones, matrix, &write_options))
+                .inspect_err(|e| eprintln!("Error writing MTX file: {}", e))?;
+        }
+        return Ok(());
+    }
+    let (result, format_name) = match options.format {
+        OutputFormat::Mtx => (
+            write_mtx_file(output_file, output_zones, output_zones, matrix, &write_options(input_file, options)),
+            "MTX",
+        ),
+        OutputFormat::Parquet => (write_parquet(output_file, output_zones, &V::to_f32_values(matrix)), "Parquet"),
+        OutputFormat::Raw => (write_raw_file(output_file, matrix, &write_options(input_file, options)), "raw"),
+        OutputFormat::Csr => (
+            write_csr_file(
+                output_file,
+                &CsrMatrix::from_dense(output_zones, output_zones, &V::to_f32_values(matrix)),
+                options.fsync,
+            ),
+            "CSR",
+        ),
+    };
+    result.inspect_err(|e| eprintln!("Error writing {} file: {}", format_name, e))
+}
+
+/// Writes an MTX file per partition of the zones, holding the rows of the origins the
+/// partition file assigns to it and every destination. The partition's name is inserted
+/// before the extension, so `od.mtx` becomes `od_north.mtx`. Origins without a
+/// partition are left out with a warning.
+///
+/// # Arguments
+/// * `all_zones` - The zones of the matrix's rows and columns, as listed in the partition file.
+/// * `output_zones` - The zone numbers written with the matrix, which may be renumbered.
+fn write_partitions<V: CellValue>(
+    input_file: &str,
+    output_file: &str,
+    all_zones: &[i32],
+    output_zones: &[i32],
+    matrix: &[V],
+    partition_file: &str,
+    options: &ConversionOptions,
+) -> std::io::Result<()> {
+    if options.format != OutputFormat::Mtx || output_file == "-" {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--partition-file writes an MTX file per partition and cannot write other formats or to standard output",
+        );
+        eprintln!("Error writing MTX file: {}", e);
+        return Err(e);
+    }
+    let zones_file = ZonesFile {
+        delimiter: options.delimiter_byte()?,
+        ..ZonesFile::new(partition_file)
+    };
+    let partitions = match read_zone_partitions(&zones_file) {
+        Ok(partitions) => partitions,
+        Err(e) => {
+            eprintln!("Error reading partition file: {}", e);
+            return Err(e);
+        }
+    };
+    let zone_partitions: std::collections::HashMap<i32, &str> =
+        partitions.iter().map(|(zone, partition)| (*zone, partition.as_str())).collect();
+    let mut names: Vec<&str> = Vec::new();
+    for (_, partition) in &partitions {
+        if !names.contains(&partition.as_str()) {
+            names.push(partition);
+        }
+    }
+    if let Some(name) = names.iter().find(|name| !is_file_name_part(name)) {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("The partition '{}' cannot be used in a file name", name),
+        );
+        eprintln!("Error reading partition file: {}", e);
+        return Err(e);
+    }
+    let unassigned = all_zones.iter().filter(|zone| !zone_partitions.contains_key(zone)).count();
+    if unassigned > 0 {
+        eprintln!(
+            "Warning: {} of {} zones are in no partition of {}, leaving their rows out",
+            unassigned,
+            all_zones.len(),
+            partition_file
+        );
+    }
+
+    // The partitions are independent, so they are written concurrently on the write
+    // pool, and the error reported is that of the first failing partition in the file
+    let size = all_zones.len();
+    let written: Vec<std::io::Result<()>> = names
+        .par_iter()
+        .map(|&name| {
+            let rows: Vec<usize> = (0..size)
+                .filter(|&row| zone_partitions.get(&all_zones[row]) == Some(&name))
+                .collect();
+            let origins: Vec<i32> = rows.iter().map(|&row| output_zones[row]).collect();
+            let values: Vec<V> = rows
+                .iter()
+                .flat_map(|&row| matrix[row * size..(row + 1) * size].iter().copied())
+                .collect();
+            let partition_output_file = suffixed_file(output_file, name);
+            check_output_file(&partition_output_file, options.force)
+                .and_then(|_| write_mtx_file(&partition_output_file, &origins, output_zones, &values, &write_options(input_file, options)))
+        })
+        .collect();
+    written.into_iter().collect::<std::io::Result<()>>().inspect_err(|e| eprintln!("Error writing MTX file: {}", e))
+}
+
+/// Converts each block of a rectangular CSV file that stacks several matrices into
+/// its own numbered output file, so `od.mtx` becomes `od_1.mtx`, `od_2.mtx` and so on.
+/// The sums and diagonal files are numbered the same way. Every block uses the zones
+/// of the whole file so that the matrices line up.
+fn convert_blocks(
+    input_file: &str,
+    output_file: &str,
+    options: &ConversionOptions,
+    pools: &PhasePools,
+) -> std::io::Result<()> {
+    if output_file == "-" {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--multi-block writes a file per block and cannot write to standard output",
+        );
+        eprintln!("Error writing MTX file: {}", e);
+        return Err(e);
+    }
+    let (data, blocks) = match pools.read(|| read_csv_blocks(input_file, &options.read_options()?)) {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            eprintln!("Error reading CSV file: {}", e);
+            return Err(e);
+        }
+    };
+    check_value_range(input_file, &data, options)?;
+    let all_zones = find_zones(&data, options)?;
+    println!("Found {} zones in {} blocks", all_zones.len(), blocks.len());
+    profile_memory::<f32>(data.len(), all_zones.len(), 1, options);
+    let rules = options.cell_rules();
+    for (index, block) in blocks.into_iter().enumerate() {
+        let number = index + 1;
+        let block_output_file = suffixed_file(output_file, number);
+        let block_transpose_file = options.also_transpose.as_deref().map(|file| suffixed_file(file, number));
+        let checked = check_output_file(&block_output_file, options.force)
+            .and_then(|_| block_transpose_file.as_deref().map_or(Ok(()), |file| check_output_file(file, options.force)));
+        if let Err(e) = checked {
+            eprintln!("Error writing MTX file: {}", e);
+            return Err(e);
+        }
+        let block_options = ConversionOptions {
+            also_transpose: block_transpose_file,
+            row_sums: options.row_sums.as_deref().map(|file| suffixed_file(file, number)),
+            col_sums: options.col_sums.as_deref().map(|file| suffixed_file(file, number)),
+            diagonal: options.diagonal.as_deref().map(|file| suffixed_file(file, number)),
+            top_flows_file: options.top_flows_file.as_deref().map(|file| suffixed_file(file, number)),
+            ..options.clone()
+        };
+        let mut matrix = pools.read(|| build_matrix(&data[block], &all_zones, &rules));
+        pools.write(|| {
+            shape_matrix(&mut matrix, all_zones.len(), options);
+            write_outputs(input_file, &block_output_file, &all_zones, &matrix, &block_options)
+        })?;
+    }
+    Ok(())
+}
+
+/// Converts the rows of a long-format CSV file into an output file per value of the
+/// pivot column. A `{pivot}` in the output path is replaced by the value, otherwise
+/// the value is inserted before the extension, so `od.mtx` becomes `od_AM.mtx`. The
+/// sums and diagonal files are named the same way. Every matrix uses the zones of the
+/// whole file so that they line up.
+fn convert_pivoted(
+    input_file: &str,
+    output_file: &str,
+    pivot: &ColumnRef,
+    options: &ConversionOptions,
+    pools: &PhasePools,
+) -> std::io::Result<()> {
+    if output_file == "-" {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--pivot-col writes a file per pivot value and cannot write to standard output",
+        );
+        eprintln!("Error writing MTX file: {}", e);
+        return Err(e);
+    }
+    let (data, groups) = match pools.read(|| read_csv_pivoted(input_file, pivot, &options.read_options()?)) {
+        Ok(groups) => groups,
+        Err(e) => {
+            eprintln!("Error reading CSV file: {}", e);
+            return Err(e);
+        }
+    };
+    if let Some((pivot_value, _)) = groups.iter().find(|(pivot_value, _)| !is_file_name_part(pivot_value)) {
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("The pivot value '{}' cannot be used in a file name", pivot_value),
+        );
+        eprintln!("Error reading CSV file: {}", e);
+        return Err(e);
+    }
+    check_value_range(input_file, &data, options)?;
+    let all_zones = find_zones(&data, options)?;
+    println!("Found {} zones in {} pivot values", all_zones.len(), groups.len());
+    profile_memory::<f32>(data.len(), all_zones.len(), 1, options);
+    let rules = options.cell_rules();
+    for (pivot_value, rows) in groups {
+        let pivot_output_file = pivot_file(output_file, &pivot_value);
+        let pivot_transpose_file = options.also_transpose.as_deref().map(|file| pivot_file(file, &pivot_value));
+        let checked = check_output_file(&pivot_output_file, options.force)
+            .and_then(|_| pivot_transpose_file.as_deref().map_or(Ok(()), |file| check_output_file(file, options.force)));
+        if let Err(e) = checked {
+            eprintln!("Error writing MTX file: {}", e);
+            return Err(e);
+        }
+        let pivot_options = ConversionOptions {
+            also_transpose: pivot_transpose_file,
+            row_sums: options.row_sums.as_deref().map(|file| pivot_file(file, &pivot_value)),
+            col_sums: options.col_sums.as_deref().map(|file| pivot_file(file, &pivot_value)),
+            diagonal: options.diagonal.as_deref().map(|file| pivot_file(file, &pivot_value)),
+            top_flows_file: options.top_flows_file.as_deref().map(|file| pivot_file(file, &pivot_value)),
+            ..options.clone()
+        };
+        let mut matrix = pools.read(|| build_matrix(&data[rows], &all_zones, &rules));
+        pools.write(|| {
+            shape_matrix(&mut matrix, all_zones.len(), options);
+            write_outputs(input_file, &pivot_output_file, &all_zones, &matrix, &pivot_options)
+        })?;
+    }
+    Ok(())
+}
+
+/// Whether a pivot value can be placed in a file name without leaving its directory.
+fn is_file_name_part(pivot_value: &str) -> bool {
+    !pivot_value.is_empty() && !pivot_value.contains(['/', '\\']) && pivot_value != "." && pivot_value != ".."
+}
+
+/// Names the output file for a pivot value, replacing `{pivot}` in the path or else
+/// inserting the value before its extension.
+fn pivot_file(file: &str, pivot_value: &str) -> String {
+    if file.contains("{pivot}") {
+        file.replace("{pivot}", pivot_value)
+    } else {
+        suffixed_file(file, pivot_value)
+    }
+}
+
+/// Inserts the block number or pivot value before the file's extension, keeping a
+/// trailing `.gz` with the extension it compresses.
+fn suffixed_file(file: &str, suffix: impl std::fmt::Display) -> String {
+    insert_before_extension(file, &format!("_{}", suffix))
+}
+
+/// Names the file of one byte order written by `--dual-endian`, so `od.mtx` becomes
+/// `od.le.mtx` or `od.be.mtx`.
+fn endian_file(file: &str, endianness: Endianness) -> String {
+    match endianness {
+        Endianness::Little => insert_before_extension(file, ".le"),
+        Endianness::Big => insert_before_extension(file, ".be"),
+    }
+}
+
+/// Inserts text before the file's extension, keeping a trailing `.gz` with the
+/// extension it compresses.
+fn insert_before_extension(file: &str, text: &str) -> String {
+    let (file, gz) = match file.strip_suffix(".gz") {
+        Some(file) => (file, ".gz"),
+        None => (file, ""),
+    };
+    match Path::new(file).extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => {
+            let stem = &file[..file.len() - extension.len() - 1];
+            format!("{}{}.{}{}", stem, text, extension, gz)
+        }
+        None => format!("{}{}{}", file, text, gz),
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(output_file: &str, all_zones: &[i32], matrix: &[f32]) -> std::io::Result<()> {
+    crate::parquet_output::write_parquet_file(output_file, all_zones, all_zones, matrix)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_output_file: &str, _all_zones: &[i32], _matrix: &[f32]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Writing Parquet files requires csv_to_mtx to be built with the `parquet` feature",
+    ))
+}
+
+/// Reads the input CSV file and builds its matrix, either in memory or, with
+/// `options.low_memory`, by streaming a 3-column file straight into the matrix.
+///
+/// # Returns
+/// The sorted zones and the flattened matrix.
+fn read_zones_and_matrix(
+    input_file: &str,
+    options: &ConversionOptions,
+) -> std::io::Result<(Vec<i32>, Vec<f32>)> {
+    let read_options = options.read_options()?;
+    let zones_file = options.zones_file()?;
+    if options.low_memory {
+        let rules = options.cell_rules();
+        match build_matrix_low_memory(input_file, &read_options, zones_file.as_ref(), &rules, options.max_zones) {
+            Ok(Some((all_zones, matrix))) => {
+                check_zone_numbers(&all_zones, options)?;
+                // The rows are streamed into the matrix rather than held in memory
+                profile_memory::<f32>(0, all_zones.len(), 1, options);
+                return Ok((all_zones, matrix));
+            }
+            Ok(None) => {
+                eprintln!("Warning: --low-memory only applies to 3-column input, reading {} into memory", input_file);
+            }
+            Err(e) => {
+                eprintln!("Error reading CSV file: {}", e);
+                return Err(e);
+            }
+        }
+    }
+
+    let data = match read_csv(input_file, &read_options) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error reading CSV file: {}", e);
+            return Err(e);
+        }
+    };
+    check_value_range(input_file, &data, options)?;
+    match &options.relative_to {
+        Some(base_file) => relative_zones_and_matrix(input_file, data, base_file, options),
+        None => zones_and_matrix(&data, options, read_options.reject_log.as_ref()),
+    }
+}
+
+/// Checks the values read against `options.value_range`, listing the cells outside it
+/// with a warning, or failing under `options.strict`.
+fn check_value_range<V: CellValue>(input_file: &str, data: &[(i32, i32, V)], options: &ConversionOptions) -> std::io::Result<()> {
+    let Some(range) = options.value_range()? else {
+        return Ok(());
+    };
+    let outside: Vec<&(i32, i32, V)> = data.par_iter().filter(|(_, _, value)| !range.contains(value.to_f64())).collect();
+    if outside.is_empty() {
+        return Ok(());
+    }
+    let mut cells: Vec<String> = outside
+        .iter()
+        .take(10)
+        .map(|(origin, destination, value)| format!("zone {} to zone {}: {}", origin, destination, value))
+        .collect();
+    if outside.len() > 10 {
+        cells.push("...".to_string());
+    }
+    let problem = format!(
+        "{} values in {} are outside the range {}: [{}]",
+        outside.len(),
+        input_file,
+        range,
+        cells.join(", ")
+    );
+    if options.strict {
+        let e = std::io::Error::new(std::io::ErrorKind::InvalidData, problem);
+        eprintln!("Error checking values: {}", e);
+        return Err(e);
+    }
+    eprintln!("Warning: {}", problem);
+    Ok(())
+}
+
+/// Builds the matrices of the input and a base CSV file over the zones of both, and
+/// subtracts the base from the input cell by cell.
+///
+/// # Returns
+/// The sorted zones and the flattened difference matrix.
+fn relative_zones_and_matrix(
+    input_file: &str,
+    mut data: Vec<(i32, i32, f32)>,
+    base_file: &str,
+    options: &ConversionOptions,
+) -> std::io::Result<(Vec<i32>, Vec<f32>)> {
+    let base = match read_csv(base_file, &options.read_options()?) {
+        Ok(base) => base,
+        Err(e) => {
+            eprintln!("Error reading base CSV file: {}", e);
+            return Err(e);
+        }
+    };
+    check_zones_match(input_file, &data, base_file, &base, options)?;
+    // The zones are found over both inputs at once, then the rows are split apart again
+    let rows = data.len();
+    data.extend(base);
+    let all_zones = find_zones(&data, options)?;
+    profile_memory::<f32>(data.len(), all_zones.len(), 2, options);
+    let rules = options.cell_rules();
+    let base_matrix = build_matrix(&data[rows..], &all_zones, &rules);
+    let mut matrix = build_matrix(&data[..rows], &all_zones, &rules);
+    matrix
+        .par_iter_mut()
+        .zip(&base_matrix)
+        .for_each(|(value, base_value)| *value -= base_value);
+    Ok((all_zones, matrix))
+}
+
+/// Compares the zones of the input and the base file, each found as they would be for
+/// its own matrix, failing if they differ unless `options.compare_zones` takes the union.
+fn check_zones_match(
+    input_file: &str,
+    data: &[(i32, i32, f32)],
+    base_file: &str,
+    base: &[(i32, i32, f32)],
+    options: &ConversionOptions,
+) -> std::io::Result<()> {
+    let zones_file = options.zones_file()?;
+    let zones = get_all_zones(zones_file.as_ref(), data).and_then(|zones| {
+        get_all_zones(zones_file.as_ref(), base).map(|base_zones| (zones, base_zones))
+    });
+    let (input_zones, base_zones) = match zones {
+        Ok(zones) => zones,
+        Err(e) => {
+            eprintln!("Error reading zones file: {}", e);
+            return Err(e);
+        }
+    };
+    let (only_input, only_base) = zone_differences(&input_zones, &base_zones);
+    if only_input.is_empty() && only_base.is_empty() {
+        eprintln!("The {} zones of {} and {} match", input_zones.len(), input_file, base_file);
+        return Ok(());
+    }
+    let list = |zones: &[i32]| {
+        let mut listed: Vec<String> = zones.iter().take(10).map(i32::to_string).collect();
+        if zones.len() > 10 {
+            listed.push("...".to_string());
+        }
+        listed.join(",")
+    };
+    let difference = format!(
+        "{} zones are only in {}: [{}], and {} zones are only in {}: [{}]",
+        only_input.len(),
+        input_file,
+        list(&only_input),
+        only_base.len(),
+        base_file,
+        list(&only_base)
+    );
+    match options.compare_zones {
+        CompareZones::Match => {
+            let e = std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("The zones of the input and base file differ. {}. Use --compare-zones union to compare them over the zones of both", difference),
+            );
+            eprintln!("Error comparing zones: {}", e);
+            Err(e)
+        }
+        CompareZones::Union => {
+            eprintln!("Warning: comparing over the zones of both files, which differ. {}", difference);
+            Ok(())
+        }
+    }
+}
+
+/// Finds the zones of the rows read from the input and builds their matrix. With a
+/// reject log, the rows left out of the matrix are added to the records the reader
+/// rejected and the log is written to `options.reject_log`.
+///
+/// # Returns
+/// The sorted zones and the flattened matrix.
+fn zones_and_matrix<V: CellValue>(
+    data: &[(i32, i32, V)],
+    options: &ConversionOptions,
+    rejects: Option<&RejectLog>,
+) -> std::io::Result<(Vec<i32>, Vec<V>)> {
+    let all_zones = find_zones(data, options)?;
+    if options.aggregate.is_none() {
+        profile_memory::<V>(data.len(), all_zones.len(), 1, options);
+    }
+    if let Some(aggregate_file) = &options.aggregate {
+        return aggregated_zones_and_matrix(data, &all_zones, aggregate_file, options);
+    }
+    let (Some(rejects), Some(reject_log)) = (rejects, &options.reject_log) else {
+        let matrix = build_matrix(data, &all_zones, &options.cell_rules());
+        return Ok((all_zones, matrix));
+    };
+    let matrix = build_matrix_with_rejects(data, &all_zones, &options.cell_rules(), |row, reason, detail| {
+        rejects.reject(rejects.row_line(row).unwrap_or(0), reason, detail);
+    });
+    match rejects.write_csv(reject_log) {
+        Ok(count) => eprintln!("Logged {} rejected records to {}", count, reject_log),
+        Err(e) => {
+            eprintln!("Error writing reject log: {}", e);
+            return Err(e);
+        }
+    }
+    Ok((all_zones, matrix))
+}
+
+/// Sums the flows between the zones of the input into the districts listed in
+/// `aggregate_file`. Zones without a district are left out with a warning, or fail
+/// under `options.strict`.
+///
+/// # Returns
+/// The sorted districts and the flattened matrix over them.
+fn aggregated_zones_and_matrix<V: CellValue>(
+    data: &[(i32, i32, V)],
+    all_zones: &[i32],
+    aggregate_file: &str,
+    options: &ConversionOptions,
+) -> std::io::Result<(Vec<i32>, Vec<V>)> {
+    let district_of = match options
+        .delimiter_byte()
+        .and_then(|delimiter| read_zone_aggregation(&ZonesFile { delimiter, ..ZonesFile::new(aggregate_file) }))
+    {
+        Ok(district_of) => district_of,
+        Err(e) => {
+            eprintln!("Error reading aggregation file: {}", e);
+            return Err(e);
+        }
+    };
+    let unmapped: Vec<i32> = all_zones.iter().copied().filter(|zone| !district_of.contains_key(zone)).collect();
+    if !unmapped.is_empty() {
+        let mut listed: Vec<String> = unmapped.iter().take(10).map(i32::to_string).collect();
+        if unmapped.len() > 10 {
+            listed.push("...".to_string());
+        }
+        let problem = format!(
+            "{} zones are not assigned a district in {}: [{}]",
+            unmapped.len(),
+            aggregate_file,
+            listed.join(",")
+        );
+        if options.strict {
+            let e = std::io::Error::new(std::io::ErrorKind::InvalidData, problem);
+            eprintln!("Error building matrix: {}", e);
+            return Err(e);
+        }
+        eprintln!("Warning: {}, and their flows are left out", problem);
+    }
+    let mut districts: Vec<i32> = district_of.values().copied().collect();
+    districts.sort_unstable();
+    districts.dedup();
+    if let Err(e) = check_zone_limit(&districts, options.max_zones) {
+        eprintln!("Error building matrix: {}", e);
+        return Err(e);
+    }
+    println!("Aggregating {} zones into {} districts", all_zones.len() - unmapped.len(), districts.len());
+    profile_memory::<V>(data.len(), districts.len(), 1, options);
+    let sums = build_aggregated_matrix(data, all_zones, &district_of, &districts, &options.cell_rules());
+    check_aggregated_precision::<V>(&sums, &districts, options)?;
+    Ok((districts, sums.into_par_iter().map(V::from_f64).collect()))
+}
+
+/// Checks that the district sums can be stored exactly as `V`, as values that can each
+/// be stored may still add up to one that cannot, such as trip counts summing past 2^24 in
+/// a 32-bit float. The sums that would be rounded are counted in a warning naming the
+/// first of them, or fail under `options.strict`.
+fn check_aggregated_precision<V: CellValue>(sums: &[f64], districts: &[i32], options: &ConversionOptions) -> std::io::Result<()> {
+    let imprecise: Vec<usize> = (0..sums.len()).filter(|&cell| loses_precision::<V>(sums[cell])).collect();
+    let Some(&first) = imprecise.first() else {
+        return Ok(());
+    };
+    let size = districts.len();
+    let problem = format!(
+        "{} district sums cannot be stored exactly as {}-bit floats, the first being district {} to district {}: {}",
+        imprecise.len(),
+        std::mem::size_of::<V>() * 8,
+        districts[first / size],
+        districts[first % size],
+        sums[first]
+    );
+    if options.strict {
+        let e = std::io::Error::new(std::io::ErrorKind::InvalidData, problem);
+        eprintln!("Error building matrix: {}", e);
+        return Err(e);
+    }
+    eprintln!("Warning: {}. They are rounded, use --auto-promote to write a double-precision matrix", problem);
+    Ok(())
+}
+
+/// Prints the estimated memory of holding `rows` rows of the input and building and
+/// writing a matrix of `V` over `zone_count` zones, when `options.profile_memory` is set.
+///
+/// # Arguments
+/// * `built_matrices` - The dense matrices held at once while building, such as the
+///   scenario and the base of `--relative-to`.
+fn profile_memory<V: CellValue>(rows: usize, zone_count: usize, built_matrices: usize, options: &ConversionOptions) {
+    if !options.profile_memory {
+        return;
+    }
+    let value_size = std::mem::size_of::<V>();
+    let buffer_bytes = match options.format {
+        OutputFormat::Mtx | OutputFormat::Raw => {
+            let write_options = MtxWriteOptions {
+                endianness: options.output_endianness,
+                gzip_filter: options.gzip_filter,
+                index_width: options.index_width,
+                ..MtxWriteOptions::default()
+            };
+            write_buffer_bytes(zone_count, value_size, &write_options)
+        }
+        // These are written from a single-precision copy of the matrix
+        OutputFormat::Parquet | OutputFormat::Csr => zone_count * zone_count * std::mem::size_of::<f32>(),
+    };
+    MemoryProfile {
+        rows,
+        row_size: std::mem::size_of::<(i32, i32, V)>(),
+        zones: zone_count,
+        value_size,
+        matrices: built_matrices + options.also_transpose.is_some() as usize,
+        buffer_bytes,
+    }
+    .print();
+}
+
+/// Finds the zones of the rows read from the input, adding padding zones when the
+/// matrix is padded to a power of two, and checks them.
+fn find_zones<V: Sync>(data: &[(i32, i32, V)], options: &ConversionOptions) -> std::io::Result<Vec<i32>> {
+    let mut all_zones = match get_all_zones(options.zones_file()?.as_ref(), data) {
+        Ok(zones) => zones,
+        Err(e) => {
+            eprintln!("Error reading zones file: {}", e);
+            return Err(e);
+        }
+    };
+    if options.pad_to_pow2
+        && let Err(e) = pad_zones_to_power_of_two(&mut all_zones)
+    {
+        eprintln!("Error building matrix: {}", e);
+        return Err(e);
+    }
+    check_zone_numbers(&all_zones, options)?;
+    Ok(all_zones)
+}
+
+/// Fails when there are more zones than `options.max_zones`, and warns about zone
+/// numbers that look spurious, or fails under `options.strict`. Zones that come only
+/// from a zones file are trusted to be real, but still count towards the limit.
+fn check_zone_numbers(all_zones: &[i32], options: &ConversionOptions) -> std::io::Result<()> {
+    if let Err(e) = check_zone_limit(all_zones, options.max_zones) {
+        eprintln!("Error building matrix: {}", e);
+        return Err(e);
+    }
+    let trusted_zones_file = options
+        .zones_file()?
+        .is_some_and(|zones_file| zones_file.ordered || zones_file.mode != ZonesMode::Union);
+    if trusted_zones_file {
+        return Ok(());
+    }
+    match suspicious_zone_numbers(all_zones) {
+        Some(problem) if options.strict => {
+            let e = std::io::Error::new(std::io::ErrorKind::InvalidData, problem);
+            eprintln!("Error reading CSV file: {}", e);
+            Err(e)
+        }
+        Some(problem) => {
+            eprintln!("Warning: {}", problem);
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Prints the number of non-zero cells the conversion would produce without
+/// building the dense matrix or writing the output file.
+fn count_cells(input_file: &str, options: &ConversionOptions) -> std::io::Result<()> {
+    let data = match read_csv(input_file, &options.read_options()?) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error reading CSV file: {}", e);
+            return Err(e);
+        }
+    };
+    let all_zones = find_zones(&data, options)?;
+    println!("{}", count_non_zero_cells(&data, &all_zones, &options.cell_rules()));
+    Ok(())
+}
+
+/// Determines how the MTX file is written, including the provenance metadata when
+/// it has been requested.
+fn write_options(input_file: &str, options: &ConversionOptions) -> MtxWriteOptions {
+    let metadata = options.stamp_metadata.then(|| {
+        // The creation time is left out of reproducible files so that every run matches
+        let created = if options.reproducible || options.canonical_order {
+            String::new()
+        } else {
+            let seconds = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or(0);
+            format!("created: {} (seconds since the Unix epoch)\n", seconds)
+        };
+        format!(
+            "source: {}\n{}tool: {} {}",
+            input_file,
+            created,
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        )
+    });
+    MtxWriteOptions {
+        endianness: options.output_endianness,
+        metadata,
+        fsync: options.fsync,
+        gzip_filter: options.gzip_filter,
+        index_width: options.index_width,
+        gzip_blocks: options.gzip_blocks,
+        magic: options.magic,
+        row_align: options.row_align,
+    }
+}
+
+/// Refuses to replace an existing output file unless forced to. Writing to
+/// standard output (`-`) is always allowed.
+fn check_output_file(output_file: &str, force: bool) -> std::io::Result<()> {
+    if output_file != "-" && !force && Path::new(output_file).exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("The output file {} already exists, use --force to overwrite it", output_file),
+        ));
+    }
+    Ok(())
+}
+
+// Write a test using test.csv to make sure that it converts to an mtx file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ReadOptions;
+    use crate::matrix::{Symmetrize, Triangle};
+    use crate::zones::ZoneSource;
+    use crate::value_transform::ClampNegative;
+    #[test]
+    fn test_csv_to_mtx() -> std::io::Result<()> {
+        let input_file = "test/test.csv";
+        let output_file = "test/test_output.mtx";
+        let _ = std::fs::remove_file(output_file);
+
+        convert_csv_to_mtx(input_file, output_file, &ConversionOptions::default())?;
+
+        // Compare against a known good output file
+        let expected_output_file = "test/test_expected.mtx";
+        let output_data = std::fs::read(output_file)?;
+        let expected_data = std::fs::read(expected_output_file)?;
+        assert_eq!(output_data, expected_data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_thread_phases() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            read_threads: Some(1),
+            write_threads: Some(1),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", "test/test_threads_output.mtx", &options)?;
+        assert_eq!(std::fs::read("test/test_threads_output.mtx")?, std::fs::read("test/test_expected.mtx")?);
+
+        let multi_block = ConversionOptions {
+            multi_block: true,
+            ..options
+        };
+        convert_csv_to_mtx("test/test.csv", "test/test_threads_output.mtx", &multi_block)?;
+        assert_eq!(std::fs::read("test/test_threads_output_1.mtx")?, std::fs::read("test/test_expected.mtx")?);
+        Ok(())
+    }
+
+    /// Reads a `zone,value` CSV file written by the conversion.
+    fn read_zone_values(file: &str) -> std::io::Result<Vec<(i32, f64)>> {
+        let mut reader = csv::Reader::from_path(file)?;
+        Ok(reader
+            .records()
+            .map(|record| {
+                let record = record.unwrap();
+                (record[0].parse().unwrap(), record[1].parse().unwrap())
+            })
+            .collect())
+    }
+
+    fn assert_zone_values(actual: &[(i32, f64)], expected: &[(i32, f64)]) {
+        assert_eq!(actual.len(), expected.len());
+        for ((zone, value), (expected_zone, expected_value)) in actual.iter().zip(expected) {
+            assert_eq!(zone, expected_zone);
+            assert!((value - expected_value).abs() < 1e-6, "zone {}: {} != {}", zone, value, expected_value);
+        }
+    }
+
+    #[test]
+    fn test_row_and_column_sums() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            row_sums: Some("test/test_row_sums_output.csv".to_string()),
+            col_sums: Some("test/test_col_sums_output.csv".to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", "test/test_sums_output.mtx", &options)?;
+        assert_zone_values(
+            &read_zone_values("test/test_row_sums_output.csv")?,
+            &[(1, 0.6), (2, 6.0), (3, 0.0), (4, 0.1)],
+        );
+        assert_zone_values(
+            &read_zone_values("test/test_col_sums_output.csv")?,
+            &[(1, 1.1), (2, 2.2), (3, 3.3), (4, 0.1)],
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diagonal() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            diagonal: Some("test/test_diagonal_output.csv".to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", "test/test_diagonal_output.mtx", &options)?;
+        let matrix = crate::mtx::read_mtx_file("test/test_diagonal_output.mtx", &crate::mtx::MtxReadOptions::default())?;
+        let n = matrix.origins.len();
+        let expected: Vec<(i32, f64)> = (0..n)
+            .map(|i| (matrix.origins[i], matrix.values[i * n + i] as f64))
+            .collect();
+        assert_zone_values(&read_zone_values("test/test_diagonal_output.csv")?, &expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reject_log() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_reject_log.csv");
+        std::fs::write(&input_file, "1,1,5\n1,2,abc\n2,1,3\n2,2,4,9\n2,2,0.5\n")?;
+        let reject_log = "test/test_reject_log_output.csv";
+        let options = ConversionOptions {
+            reject_log: Some(reject_log.to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), "test/test_reject_log_output.mtx", &options)?;
+        let read_log = || -> std::io::Result<Vec<(u64, String)>> {
+            let mut reader = csv::Reader::from_path(reject_log)?;
+            reader
+                .records()
+                .map(|record| {
+                    let record = record?;
+                    Ok((record[0].parse().unwrap(), record[1].to_string()))
+                })
+                .collect()
+        };
+        assert_eq!(read_log()?, vec![(2, "parse-error".to_string()), (4, "malformed".to_string())]);
+
+        // Rows left out while building the matrix are traced back to their lines
+        let options = ConversionOptions {
+            min_value: Some(1.0),
+            ..options
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), "test/test_reject_log_output.mtx", &options)?;
+        assert_eq!(read_log()?[2], (5, "out-of-range".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_flows_file() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            top_flows: Some(3),
+            top_flows_file: Some("test/test_top_flows_output.csv".to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", "test/test_top_flows_output.mtx", &options)?;
+        let matrix = crate::mtx::read_mtx_file("test/test_expected.mtx", &crate::mtx::MtxReadOptions::default())?;
+        let mut values = matrix.values.clone();
+        values.sort_by(|a, b| b.total_cmp(a));
+
+        let mut reader = csv::Reader::from_path("test/test_top_flows_output.csv")?;
+        let flows: Vec<(i32, i32, f32)> = reader.deserialize().collect::<Result<_, _>>()?;
+        assert_eq!(flows.iter().map(|&(_, _, value)| value).collect::<Vec<_>>(), values[..3]);
+        let n = matrix.origins.len();
+        for (origin, destination, value) in flows {
+            let row = matrix.origins.iter().position(|&zone| zone == origin).unwrap();
+            let column = matrix.destinations.iter().position(|&zone| zone == destination).unwrap();
+            assert_eq!(matrix.values[row * n + column], value);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_value() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            min_value: Some(0.5),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        let output_file = "test/test_min_value_output.mtx";
+        convert_csv_to_mtx("test/test_rectangular.csv", output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, vec![0.0, 0.0, 0.0, 0.0, 0.5, 0.6, 0.7, 0.8, 0.9]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_keeps_zero_cells() -> std::io::Result<()> {
+        let directory = std::env::temp_dir();
+        let square_file = directory.join("csv_to_mtx_test_fill_square.csv");
+        let column_file = directory.join("csv_to_mtx_test_fill_column.csv");
+        std::fs::write(&square_file, ",1,2,3\n1,0,0.2,0.3\n2,0.4,0,\n3,0.7,0.8,0.1\n")?;
+        std::fs::write(
+            &column_file,
+            "origin,destination,value\n1,1,0\n1,2,0.2\n1,3,0.3\n2,1,0.4\n2,2,0\n3,1,0.7\n3,2,0.8\n3,3,0.1\n",
+        )?;
+        let options = ConversionOptions {
+            fill: -1.0,
+            min_value: Some(0.15),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        let convert_both = |options: &ConversionOptions| -> std::io::Result<Vec<Vec<f32>>> {
+            let mut outputs = Vec::new();
+            for (input_file, output_file) in [
+                (&square_file, "test/test_fill_square_output.mtx"),
+                (&column_file, "test/test_fill_column_output.mtx"),
+            ] {
+                convert_csv_to_mtx(&input_file.to_string_lossy(), output_file, options)?;
+                outputs.push(crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?.values);
+            }
+            Ok(outputs)
+        };
+        let outputs = convert_both(&options)?;
+        // The zero cells and 0.1 are below the minimum, and the empty cell has no data
+        assert_eq!(outputs[0], vec![-1.0, 0.2, 0.3, 0.4, -1.0, -1.0, 0.7, 0.8, -1.0]);
+        assert_eq!(outputs[0], outputs[1]);
+
+        // Without a minimum the zero cells stay zero in both formats
+        let options = ConversionOptions {
+            min_value: None,
+            ..options
+        };
+        let outputs = convert_both(&options)?;
+        assert_eq!(outputs[0], vec![0.0, 0.2, 0.3, 0.4, 0.0, -1.0, 0.7, 0.8, 0.1]);
+        assert_eq!(outputs[0], outputs[1]);
+        std::fs::remove_file(square_file)?;
+        std::fs::remove_file(column_file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_stamp_metadata() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            stamp_metadata: true,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        let output_file = "test/test_stamp_output.mtx";
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        let metadata = matrix.metadata.unwrap();
+        assert!(metadata.starts_with("source: test/test.csv\n"));
+        assert!(metadata.ends_with(concat!("tool: csv_to_mtx ", env!("CARGO_PKG_VERSION"))));
+        let expected = crate::mtx::read_mtx_file("test/test_expected.mtx", &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, expected.values);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reproducible() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            stamp_metadata: true,
+            reproducible: true,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        let output_file = "test/test_reproducible_output.mtx.gz";
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+        let first = std::fs::read(output_file)?;
+        // Past the next second, when a time stamp would differ
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+        assert_eq!(std::fs::read(output_file)?, first);
+        // The gzip header has no time stamp and an unknown operating system
+        assert_eq!(&first[4..8], &[0, 0, 0, 0]);
+        assert_eq!(first[9], 255);
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert!(!matrix.metadata.unwrap().contains("created"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_order() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_canonical_order.csv");
+        let input_file = input_file.to_string_lossy();
+        // Sums of values of very different sizes depend on the order they are added in
+        let mut csv = String::from("origin,destination,value\n");
+        for origin in 1..=300 {
+            for destination in 1..=300 {
+                let value = if (origin * destination) % 11 == 0 { 1e7 } else { 0.013 * destination as f64 };
+                csv.push_str(&format!("{},{},{}\n", origin, destination, value));
+            }
+        }
+        std::fs::write(&*input_file, csv)?;
+
+        let output_file = "test/test_canonical_order_output.mtx.gz";
+        let mut outputs = Vec::new();
+        for threads in [1, 4, 1, 7] {
+            let options = ConversionOptions {
+                canonical_order: true,
+                stamp_metadata: true,
+                normalize: crate::matrix::Normalize::Col,
+                read_threads: Some(threads),
+                write_threads: Some(threads),
+                force: true,
+                ..ConversionOptions::default()
+            };
+            convert_csv_to_mtx(&input_file, output_file, &options)?;
+            outputs.push(std::fs::read(output_file)?);
+        }
+        // The hash maps of each run are seeded differently, as every new hash map is
+        assert!(outputs.iter().all(|output| *output == outputs[0]));
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert!(!matrix.metadata.unwrap().contains("created"));
+        std::fs::remove_file(&*input_file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_also_transpose() -> std::io::Result<()> {
+        let output_file = "test/test_also_transpose_output.mtx";
+        let transpose_file = "test/test_also_transpose_transposed_output.mtx";
+        for auto_promote in [false, true] {
+            let options = ConversionOptions {
+                force: true,
+                auto_promote,
+                also_transpose: Some(transpose_file.to_string()),
+                ..ConversionOptions::default()
+            };
+            convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+            let original = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+            let transposed = crate::mtx::read_mtx_file(transpose_file, &crate::mtx::MtxReadOptions::default())?;
+            assert_eq!(transposed.origins, original.destinations);
+            assert_eq!(transposed.destinations, original.origins);
+            let n = original.origins.len();
+            for i in 0..n {
+                for j in 0..n {
+                    assert_eq!(original.values[i * n + j], transposed.values[j * n + i]);
+                }
+            }
+            assert_ne!(original.values, transposed.values);
+        }
+
+        let options = ConversionOptions {
+            also_transpose: Some(transpose_file.to_string()),
+            ..ConversionOptions::default()
+        };
+        assert!(convert_csv_to_mtx("test/test.csv", "test/test_also_transpose_refused_output.mtx", &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_outputs_ignore_existing_output_file() -> std::io::Result<()> {
+        let stacked_file = std::env::temp_dir().join("csv_to_mtx_test_split_stacked.csv");
+        std::fs::write(&stacked_file, ",1,2,3\n1,1,2,0\n2,3,4,0\n3,0,0,1\n\n1,5,6,0\n2,7,8,0\n3,0,0,1\n")?;
+        let pivot_file = std::env::temp_dir().join("csv_to_mtx_test_split_pivot.csv");
+        std::fs::write(&pivot_file, "origin,destination,period,value\n1,2,AM,5\n2,1,PM,7\n")?;
+        let partition_file = std::env::temp_dir().join("csv_to_mtx_test_split_partitions.csv");
+        std::fs::write(&partition_file, "zone,partition\n1,A\n2,B\n3,A\n4,B\n")?;
+        let output_file = "test/test_split_existing_output.mtx";
+        let remove_split_outputs = || -> std::io::Result<()> {
+            for entry in std::fs::read_dir("test")? {
+                let path = entry?.path();
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                if name.starts_with("test_split_existing_output") && path != Path::new(output_file) {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            Ok(())
+        };
+        // The output file exists, but only files named after it are written
+        std::fs::write(output_file, "not a matrix")?;
+        let cases = [
+            (
+                "test/test.csv".to_string(),
+                ConversionOptions {
+                    dual_endian: true,
+                    ..ConversionOptions::default()
+                },
+            ),
+            (
+                "test/test.csv".to_string(),
+                ConversionOptions {
+                    partition_file: Some(partition_file.to_string_lossy().to_string()),
+                    ..ConversionOptions::default()
+                },
+            ),
+            (
+                stacked_file.to_string_lossy().to_string(),
+                ConversionOptions {
+                    multi_block: true,
+                    ..ConversionOptions::default()
+                },
+            ),
+            (
+                pivot_file.to_string_lossy().to_string(),
+                ConversionOptions {
+                    pivot_col: Some("period".to_string()),
+                    ..ConversionOptions::default()
+                },
+            ),
+        ];
+        for (input_file, options) in cases {
+            remove_split_outputs()?;
+            convert_csv_to_mtx(&input_file, output_file, &options)?;
+            // The files written are still refused once they exist
+            assert!(convert_csv_to_mtx(&input_file, output_file, &options).is_err());
+        }
+        assert_eq!(std::fs::read(output_file)?, b"not a matrix");
+        remove_split_outputs()?;
+        for file in [stacked_file, pivot_file, partition_file] {
+            std::fs::remove_file(file)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dual_endian() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            force: true,
+            dual_endian: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", "test/test_dual_endian_output.mtx", &options)?;
+        let little = std::fs::read("test/test_dual_endian_output.le.mtx")?;
+        let big = std::fs::read("test/test_dual_endian_output.be.mtx")?;
+        assert_eq!(little, std::fs::read("test/test_expected.mtx")?);
+        // Every field of the file is four bytes wide, so each is reversed in the other
+        assert_eq!(little.len(), big.len());
+        for (little, big) in little.chunks(4).zip(big.chunks(4)) {
+            assert_eq!(little.iter().rev().collect::<Vec<_>>(), big.iter().collect::<Vec<_>>());
+        }
+        assert!(!Path::new("test/test_dual_endian_output.mtx").exists());
+        assert_eq!(endian_file("od.mtx.gz", Endianness::Big), "od.be.mtx.gz");
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_block() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_multi_block.csv");
+        std::fs::write(&input_file, ",1,2,3\n1,1,2,0\n2,3,4,0\n3,0,0,1\n\n1,5,6,0\n2,7,8,0\n3,0,0,1\n")?;
+        let options = ConversionOptions {
+            multi_block: true,
+            row_sums: Some("test/test_multi_block_sums_output.csv".to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), "test/test_multi_block_output.mtx.gz", &options)?;
+        let first = crate::mtx::read_mtx_file("test/test_multi_block_output_1.mtx.gz", &crate::mtx::MtxReadOptions::default())?;
+        let second = crate::mtx::read_mtx_file("test/test_multi_block_output_2.mtx.gz", &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(first.origins, vec![1, 2, 3]);
+        assert_eq!(first.values, vec![1.0, 2.0, 0.0, 3.0, 4.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(second.values, vec![5.0, 6.0, 0.0, 7.0, 8.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_zone_values(&read_zone_values("test/test_multi_block_sums_output_2.csv")?, &[(1, 11.0), (2, 15.0), (3, 1.0)]);
+        let options = ConversionOptions {
+            also_transpose: Some("test/test_multi_block_transposed_output.mtx".to_string()),
+            ..options
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), "test/test_multi_block_output.mtx.gz", &options)?;
+        let transposed = crate::mtx::read_mtx_file("test/test_multi_block_transposed_output_2.mtx", &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(transposed.values, vec![5.0, 7.0, 0.0, 6.0, 8.0, 0.0, 0.0, 0.0, 1.0]);
+
+        assert_eq!(suffixed_file("dir.v2/od.mtx", 3), "dir.v2/od_3.mtx");
+        assert_eq!(suffixed_file("od", 1), "od_1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lenient_rectangular_origins() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_lenient_rectangular.csv");
+        let input = "zone,100,200,300\n100.0,1,2,0\n200.0,3,4,0\n300.0,0,0,1\n";
+        std::fs::write(&input_file, input)?;
+        let output_file = "test/test_lenient_rectangular_output.mtx";
+        let options = ConversionOptions {
+            lenient_zone_ids: true,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.origins, vec![100, 200, 300]);
+        assert_eq!(matrix.values, vec![1.0, 2.0, 0.0, 3.0, 4.0, 0.0, 0.0, 0.0, 1.0]);
+
+        // The labels also tell the rows from a repeated header when splitting blocks
+        std::fs::write(&input_file, format!("{}{}", input, input.replace("1,2,0", "5,6,0")))?;
+        let options = ConversionOptions {
+            multi_block: true,
+            ..options
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), "test/test_lenient_rectangular_output.mtx", &options)?;
+        let second = crate::mtx::read_mtx_file("test/test_lenient_rectangular_output_2.mtx", &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(second.origins, vec![100, 200, 300]);
+        assert_eq!(second.values, vec![5.0, 6.0, 0.0, 3.0, 4.0, 0.0, 0.0, 0.0, 1.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_directory_of_parts() -> std::io::Result<()> {
+        use std::io::Write;
+        let directory = std::env::temp_dir().join("csv_to_mtx_test_parts");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir(&directory)?;
+        std::fs::write(directory.join("part-00000.csv"), ",1,2,3\n1,1,2,0\n")?;
+        // The last part has no final line ending, and the empty part and marker file are ignored
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b",1,2,3\n2,3,4,0\n3,0,0,1")?;
+        std::fs::write(directory.join("part-00001.csv.gz"), encoder.finish()?)?;
+        std::fs::write(directory.join("part-00002.csv"), "")?;
+        std::fs::write(directory.join("_SUCCESS"), "")?;
+        let output_file = "test/test_parts_output.mtx";
+        let options = ConversionOptions {
+            force: true,
+            strict: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(directory.to_str().unwrap(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.origins, vec![1, 2, 3]);
+        assert_eq!(matrix.values, vec![1.0, 2.0, 0.0, 3.0, 4.0, 0.0, 0.0, 0.0, 1.0]);
+
+        // A part without the header row of the others is ambiguous
+        std::fs::write(directory.join("part-00003.csv"), "1,5,6,0\n")?;
+        let e = convert_csv_to_mtx(directory.to_str().unwrap(), output_file, &options).unwrap_err();
+        assert!(e.to_string().contains("do not agree on having a header row"), "{}", e);
+
+        // Parts without a header row that start with the same row of data keep it
+        std::fs::remove_dir_all(&directory)?;
+        std::fs::create_dir(&directory)?;
+        std::fs::write(directory.join("part-00000.csv"), "1,1,5\n1,2,2\n")?;
+        std::fs::write(directory.join("part-00001.csv"), "1,1,5\n2,1,3\n")?;
+        std::fs::write(directory.join("part-00002.csv"), "1,1,5\n2,2,4\n")?;
+        convert_csv_to_mtx(directory.to_str().unwrap(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, vec![5.0, 2.0, 3.0, 4.0]);
+        let read_options = ReadOptions::default();
+        assert_eq!(crate::input::read_csv(directory.to_str().unwrap(), &read_options)?.len(), 6);
+
+        // The header rows of 3-column parts are still left out of the later parts
+        for (part, row) in ["1,1,5", "2,1,3", "2,2,4"].iter().enumerate() {
+            std::fs::write(directory.join(format!("part-0000{}.csv", part)), format!("origin,destination,value\n{}\n", row))?;
+        }
+        assert_eq!(crate::input::read_csv(directory.to_str().unwrap(), &read_options)?.len(), 3);
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_range() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_value_range.csv");
+        std::fs::write(&input_file, "origin,destination,value\n1,2,15\n2,1,10000000\n2,2,30\n")?;
+        let output_file = "test/test_value_range_output.mtx";
+        let mut options = ConversionOptions {
+            value_range: Some("0:1440".to_string()),
+            strict: true,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        let e = convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options).unwrap_err();
+        assert!(e.to_string().contains("1 values"), "{}", e);
+        assert!(e.to_string().contains("zone 2 to zone 1: 10000000"), "{}", e);
+
+        // Without --strict the cell is only reported, and the value is kept
+        options.strict = false;
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, vec![0.0, 15.0, 1e7, 30.0]);
+
+        options.strict = true;
+        options.value_range = Some("0:1e8".to_string());
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        options.value_range = Some("1e8:0".to_string());
+        assert!(convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pivot_col() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_pivot_col.csv");
+        std::fs::write(&input_file, "origin,destination,period,value\n1,2,AM,5\n2,1,PM,7\n3,3,AM,1\n1,2,PM,2\n")?;
+        let options = ConversionOptions {
+            pivot_col: Some("period".to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), "test/test_pivot_{pivot}_output.mtx", &options)?;
+        let am = crate::mtx::read_mtx_file("test/test_pivot_AM_output.mtx", &crate::mtx::MtxReadOptions::default())?;
+        let pm = crate::mtx::read_mtx_file("test/test_pivot_PM_output.mtx", &crate::mtx::MtxReadOptions::default())?;
+        // Both matrices have the zones of every period
+        assert_eq!(am.origins, vec![1, 2, 3]);
+        assert_eq!(pm.origins, vec![1, 2, 3]);
+        assert_eq!(am.values, vec![0.0, 5.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(pm.values, vec![0.0, 2.0, 0.0, 7.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        assert_eq!(pivot_file("od.mtx.gz", "AM"), "od_AM.mtx.gz");
+        assert!(!is_file_name_part("../AM"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition_file() -> std::io::Result<()> {
+        let partition_file = std::env::temp_dir().join("csv_to_mtx_test_partition.csv");
+        std::fs::write(&partition_file, "zone,partition\n1,A\n2,B\n3,A # north\n4,B\n")?;
+        let options = ConversionOptions {
+            partition_file: Some(partition_file.to_str().unwrap().to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", "test/test_partition_output.mtx", &options)?;
+        let expected = crate::mtx::read_mtx_file("test/test_expected.mtx", &crate::mtx::MtxReadOptions::default())?;
+        let a = crate::mtx::read_mtx_file("test/test_partition_output_A.mtx", &crate::mtx::MtxReadOptions::default())?;
+        let b = crate::mtx::read_mtx_file("test/test_partition_output_B.mtx", &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(a.origins, vec![1, 3]);
+        assert_eq!(b.origins, vec![2, 4]);
+        assert_eq!(a.destinations, expected.destinations);
+        assert_eq!(a.values[4..], expected.values[8..12]);
+        assert_eq!(b.values[..4], expected.values[4..8]);
+
+        // Writing the partitions concurrently gives the same files as one at a time
+        std::fs::write(&partition_file, "zone,partition\n1,A\n2,B\n3,C\n4,D\n")?;
+        let written = |threads: usize| -> std::io::Result<Vec<Vec<u8>>> {
+            let options = ConversionOptions {
+                write_threads: Some(threads),
+                ..options.clone()
+            };
+            convert_csv_to_mtx("test/test.csv", "test/test_partition_output.mtx.gz", &options)?;
+            ["A", "B", "C", "D"]
+                .iter()
+                .map(|name| std::fs::read(format!("test/test_partition_output_{}.mtx.gz", name)))
+                .collect()
+        };
+        assert_eq!(written(4)?, written(1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate() -> std::io::Result<()> {
+        let aggregate_file = std::env::temp_dir().join("csv_to_mtx_test_aggregate.csv");
+        std::fs::write(&aggregate_file, "zone,district\n1,10\n2,10\n3,20\n4,20\n")?;
+        let output_file = "test/test_aggregate_output.mtx";
+        let mut options = ConversionOptions {
+            aggregate: Some(aggregate_file.to_str().unwrap().to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.origins, vec![10, 20]);
+        assert_eq!(matrix.destinations, vec![10, 20]);
+        let expected = [0.1 + 0.2 + 1.0 + 2.0, 0.3 + 3.0, 0.0, 0.1];
+        for (value, expected) in matrix.values.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-6, "{} != {}", value, expected);
+        }
+
+        // Zone 4 has no district, which only fails under --strict
+        std::fs::write(&aggregate_file, "zone,district\n1,10\n2,10\n3,20\n")?;
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values[3], 0.0);
+        options.strict = true;
+        assert!(convert_csv_to_mtx("test/test.csv", output_file, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_precision() -> std::io::Result<()> {
+        let aggregate_file = std::env::temp_dir().join("csv_to_mtx_test_aggregate_precision.csv");
+        std::fs::write(&aggregate_file, "zone,district\n1,10\n2,10\n3,20\n")?;
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_aggregate_precision_input.csv");
+        // Each value is exact as a 32-bit float, but 16777217 is not
+        std::fs::write(&input_file, "1,1,16777216\n2,2,1\n3,3,5\n")?;
+        let output_file = "test/test_aggregate_precision_output.mtx";
+        let mut options = ConversionOptions {
+            aggregate: Some(aggregate_file.to_string_lossy().to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(&input_file.to_string_lossy(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, vec![16777216.0, 0.0, 0.0, 5.0]);
+
+        // The sums rather than the rows decide whether the matrix is promoted
+        options.auto_promote = true;
+        convert_csv_to_mtx(&input_file.to_string_lossy(), output_file, &options)?;
+        let bytes = std::fs::read(output_file)?;
+        assert_eq!(bytes[8..12], 2i32.to_le_bytes());
+        let values: Vec<f64> = bytes[40..]
+            .chunks(8)
+            .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, vec![16777217.0, 0.0, 0.0, 5.0]);
+
+        // Sums a 32-bit float holds exactly keep the usual format
+        std::fs::write(&input_file, "1,1,16777215\n2,2,1\n3,3,5\n")?;
+        convert_csv_to_mtx(&input_file.to_string_lossy(), output_file, &options)?;
+        assert_eq!(std::fs::read(output_file)?[8..12], 1i32.to_le_bytes());
+
+        std::fs::write(&input_file, "1,1,16777216\n2,2,1\n3,3,5\n")?;
+        options.auto_promote = false;
+        options.strict = true;
+        let e = convert_csv_to_mtx(&input_file.to_string_lossy(), output_file, &options).unwrap_err();
+        assert!(e.to_string().contains("district 10 to district 10: 16777217"), "{}", e);
+        std::fs::remove_file(aggregate_file)?;
+        std::fs::remove_file(input_file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_rows() -> std::io::Result<()> {
+        let output_file = "test/test_normalize_output.mtx";
+        let options = ConversionOptions {
+            normalize: crate::matrix::Normalize::Row,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        for row in matrix.values.chunks(matrix.destinations.len()) {
+            let sum: f32 = row.iter().sum();
+            assert!(sum == 0.0 || (sum - 1.0).abs() < 1e-6, "row sums to {}", sum);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_zone_numbers() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_outlier_zone.csv");
+        std::fs::write(&input_file, "1,2,1.0\n2,1,2.0\n1,1700000000,3.0\n")?;
+        let input_file = input_file.to_str().unwrap();
+        let output_file = "test/test_outlier_zone_output.mtx";
+        let options = ConversionOptions {
+            force: true,
+            ..ConversionOptions::default()
+        };
+        // Only a warning by default
+        convert_csv_to_mtx(input_file, output_file, &options)?;
+        let strict = ConversionOptions {
+            strict: true,
+            ..options.clone()
+        };
+        let e = convert_csv_to_mtx(input_file, output_file, &strict).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+        // A zones file replacing the data's zones is trusted
+        let zones = ConversionOptions {
+            zones_file: Some("test/test_zones.csv".to_string()),
+            zones_header: true,
+            ..strict
+        };
+        convert_csv_to_mtx(input_file, output_file, &zones)
+    }
+
+    #[test]
+    fn test_renumber() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_renumber.csv");
+        std::fs::write(&input_file, "10,30,1.0\n30,200,2.0\n")?;
+        let options = ConversionOptions {
+            renumber: Some("test/test_renumber_mapping_output.csv".to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), "test/test_renumber_output.mtx", &options)?;
+        let matrix = crate::mtx::read_mtx_file("test/test_renumber_output.mtx", &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.origins, vec![1, 2, 3]);
+        assert_eq!(matrix.destinations, vec![1, 2, 3]);
+        assert_eq!(matrix.values, vec![0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0]);
+        assert_eq!(
+            std::fs::read_to_string("test/test_renumber_mapping_output.csv")?,
+            "zone,new_id\n10,1\n30,2\n200,3\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_zones_file() -> std::io::Result<()> {
+        let zones_file = std::env::temp_dir().join("csv_to_mtx_test_select_zones.csv");
+        std::fs::write(&zones_file, "3\n2\n1\n")?;
+        let output_file = "test/test_select_zones_output.mtx";
+        let options = ConversionOptions {
+            select_zones_file: Some(zones_file.to_str().unwrap().to_string()),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test_rectangular.csv", output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.origins, vec![3, 2, 1]);
+        assert_eq!(matrix.values, vec![0.9, 0.8, 0.7, 0.6, 0.5, 0.4, 0.3, 0.2, 0.1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zone_source() -> std::io::Result<()> {
+        let output_file = "test/test_zone_source_output.mtx";
+        let read_zones = || -> std::io::Result<Vec<i32>> {
+            Ok(crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?.origins)
+        };
+        // The zones file is not read, so it need not exist
+        let data = ConversionOptions {
+            zones_file: Some("test/missing_zones.csv".to_string()),
+            zone_source: Some(ZoneSource::Data),
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test_rectangular.csv", output_file, &data)?;
+        assert_eq!(read_zones()?, vec![1, 2, 3]);
+
+        let mtx = ConversionOptions {
+            zones_file: Some("test/test_expected.mtx".to_string()),
+            zone_source: Some(ZoneSource::Mtx),
+            ..data.clone()
+        };
+        convert_csv_to_mtx("test/test_rectangular.csv", output_file, &mtx)?;
+        assert_eq!(read_zones()?, vec![1, 2, 3, 4]);
+
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_zone_range.csv");
+        std::fs::write(&input_file, "2,5,1.0\n5,2,2.0\n")?;
+        let range = ConversionOptions {
+            zone_source: Some(ZoneSource::Range),
+            ..data.clone()
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &range)?;
+        assert_eq!(read_zones()?, vec![1, 2, 3, 4, 5]);
+
+        let file = ConversionOptions {
+            zones_file: None,
+            zone_source: Some(ZoneSource::File),
+            ..data
+        };
+        assert!(convert_csv_to_mtx("test/test_rectangular.csv", output_file, &file).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pad_to_pow2() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_pad_to_pow2.csv");
+        std::fs::write(&input_file, "1,2,1.0\n3,4,2.0\n5,1,3.0\n")?;
+        let output_file = "test/test_pad_to_pow2_output.mtx";
+        let options = ConversionOptions {
+            pad_to_pow2: true,
+            fill: -1.0,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.origins, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(matrix.destinations, matrix.origins);
+        assert_eq!(matrix.values.len(), 8 * 8);
+        assert_eq!(matrix.values[1], 1.0);
+        assert_eq!(matrix.values[4 * 8], 3.0);
+        // The rows and columns of the padding zones are the fill value
+        assert!(matrix.values[5 * 8..].iter().all(|&value| value == -1.0));
+        assert!(matrix.values.chunks(8).all(|row| row[5..].iter().all(|&value| value == -1.0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_triangle() -> std::io::Result<()> {
+        let output_file = "test/test_triangle_output.mtx";
+        let options = ConversionOptions {
+            triangle: Triangle::Upper,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test_rectangular.csv", output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, vec![0.1, 0.2, 0.3, 0.0, 0.5, 0.6, 0.0, 0.0, 0.9]);
+
+        let options = ConversionOptions {
+            symmetrize: Symmetrize::Max,
+            ..options
+        };
+        convert_csv_to_mtx("test/test_rectangular.csv", output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, vec![0.1, 0.4, 0.7, 0.0, 0.5, 0.8, 0.0, 0.0, 0.9]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_promote() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_auto_promote.csv");
+        let output_file = "test/test_auto_promote_output.mtx";
+        let options = ConversionOptions {
+            auto_promote: true,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        // 16777217 is the first whole number a 32-bit float cannot hold
+        std::fs::write(&input_file, "1,1,16777217\n1,2,0.5\n")?;
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        let bytes = std::fs::read(output_file)?;
+        assert_eq!(bytes[8..12], 2i32.to_le_bytes());
+        let values: Vec<f64> = bytes[40..]
+            .chunks(8)
+            .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, vec![16777217.0, 0.5, 0.0, 0.0]);
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, vec![16777216.0, 0.5, 0.0, 0.0]);
+
+        // Values a 32-bit float holds exactly keep the usual format
+        std::fs::write(&input_file, "1,1,16777216\n1,2,0.5\n")?;
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        assert_eq!(std::fs::read(output_file)?[8..12], 1i32.to_le_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_format() -> std::io::Result<()> {
+        let output_file = "test/test_raw_output.bin";
+        let options = ConversionOptions {
+            format: OutputFormat::Raw,
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+        let raw = std::fs::read(output_file)?;
+        assert_eq!(raw.len(), 4 * 4 * std::mem::size_of::<f32>());
+        let expected = std::fs::read("test/test_expected.mtx")?;
+        assert_eq!(raw[..], expected[expected.len() - raw.len()..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_zone_and_empty_inputs() -> std::io::Result<()> {
+        let options = ConversionOptions {
+            force: true,
+            ..ConversionOptions::default()
+        };
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_single_zone.csv");
+        let output_file = "test/test_single_zone_convert_output.mtx";
+        std::fs::write(&input_file, "7,7,2.5\n")?;
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!((matrix.origins, matrix.destinations, matrix.values), (vec![7], vec![7], vec![2.5]));
+
+        // An input without any rows is written as a valid matrix with no zones
+        std::fs::write(&input_file, "")?;
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert!(matrix.origins.is_empty() && matrix.values.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_na_values_take_the_fill_value() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_na_fill.csv");
+        std::fs::write(&input_file, ",1,2,3\n1,0.5,NA,1\n2,null,1.5,1\n3,1,1,NA\n")?;
+        let output_file = "test/test_na_values_output.mtx";
+        let options = ConversionOptions {
+            force: true,
+            fill: -1.0,
+            na_values: vec!["NA".to_string(), "null".to_string()],
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+        let matrix = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        assert_eq!(matrix.values, vec![0.5, -1.0, 1.0, -1.0, 1.5, 1.0, 1.0, 1.0, -1.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clamp_negative() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_clamp_negative.csv");
+        std::fs::write(&input_file, ",1,2,3\n1,0.5,-3,1\n2,1,2,1\n3,1,1,1\n")?;
+        let output_file = "test/test_clamp_negative_output.mtx";
+        let convert = |clamp_negative| -> std::io::Result<Vec<f32>> {
+            let options = ConversionOptions {
+                force: true,
+                fill: -1.0,
+                clamp_negative,
+                ..ConversionOptions::default()
+            };
+            convert_csv_to_mtx(input_file.to_str().unwrap(), output_file, &options)?;
+            Ok(crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?.values)
+        };
+        assert_eq!(convert(ClampNegative::Keep)?[..3], [0.5, -3.0, 1.0]);
+        assert_eq!(convert(ClampNegative::Zero)?[..3], [0.5, -1.0, 1.0]);
+        assert_eq!(convert(ClampNegative::Abs)?[..3], [0.5, 3.0, 1.0]);
+        assert!(convert(ClampNegative::Error).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_to() -> std::io::Result<()> {
+        let base_file = std::env::temp_dir().join("csv_to_mtx_test_relative_base.csv");
+        std::fs::write(&base_file, "1,1,0.5\n2,2,4\n5,1,1\n")?;
+        let output_file = "test/test_relative_output.mtx";
+        let _ = std::fs::remove_file(output_file);
+        let mut options = ConversionOptions {
+            force: true,
+            relative_to: Some(base_file.to_str().unwrap().to_string()),
+            ..ConversionOptions::default()
+        };
+        // The base has zone 5 but not 3 or 4, which is refused before any matrix is built
+        assert!(convert_csv_to_mtx("test/test.csv", output_file, &options).is_err());
+        assert!(!Path::new(output_file).exists());
+        assert_eq!(zone_differences(&[1, 2, 3, 4], &[1, 2, 5]), (vec![3, 4], vec![5]));
+
+        options.compare_zones = CompareZones::Union;
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+
+        let difference = crate::mtx::read_mtx_file(output_file, &crate::mtx::MtxReadOptions::default())?;
+        let zones = vec![1, 2, 3, 4, 5];
+        assert_eq!(difference.origins, zones);
+        let scenario = build_matrix(&read_csv("test/test.csv", &ReadOptions::default())?, &zones, &Default::default());
+        let base = build_matrix(&read_csv(base_file.to_str().unwrap(), &ReadOptions::default())?, &zones, &Default::default());
+        for ((value, scenario), base) in difference.values.iter().zip(&scenario).zip(&base) {
+            assert_eq!(*value, scenario - base);
+        }
+        assert_eq!(difference.values[0], 0.1 - 0.5);
+
+        let low_memory = ConversionOptions { low_memory: true, ..options };
+        assert!(convert_csv_to_mtx("test/test.csv", output_file, &low_memory).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_zones() -> std::io::Result<()> {
+        // test.csv has 4 zones
+        let output_file = "test/test_max_zones_output.mtx";
+        for low_memory in [false, true] {
+            let options = ConversionOptions {
+                force: true,
+                low_memory,
+                max_zones: Some(3),
+                ..ConversionOptions::default()
+            };
+            let _ = std::fs::remove_file(output_file);
+            assert!(convert_csv_to_mtx("test/test.csv", output_file, &options).is_err());
+            assert!(!Path::new(output_file).exists());
+
+            let options = ConversionOptions {
+                max_zones: Some(4),
+                ..options
+            };
+            convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+            assert_eq!(std::fs::read(output_file)?, std::fs::read("test/test_expected.mtx")?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse_to_overwrite() -> std::io::Result<()> {
+        let output_file = "test/test_overwrite_output.mtx";
+        let _ = std::fs::remove_file(output_file);
+        convert_csv_to_mtx("test/test.csv", output_file, &ConversionOptions::default())?;
+
+        let error = convert_csv_to_mtx("test/test.csv", output_file, &ConversionOptions::default())
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(error.to_string().contains("--force"));
+
+        let options = ConversionOptions {
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", output_file, &options)?;
+        assert_eq!(std::fs::read(output_file)?, std::fs::read("test/test_expected.mtx")?);
+        Ok(())
+    }
+}
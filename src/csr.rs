@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::MultiGzDecoder;
+
+use crate::mtx::{write_output, MtxMatrix};
+
+/// The magic number at the start of every compressed sparse row file.
+pub const CSR_MAGIC: [u8; 4] = *b"MTXS";
+
+/// The version of the layout written by `write_csr_file`.
+const CSR_VERSION: u32 = 1;
+
+/// The non-zero cells of a matrix in compressed sparse row form, so the cells of any
+/// row can be found without reading the rows before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsrMatrix {
+    pub origins: Vec<i32>,
+    pub destinations: Vec<i32>,
+    /// Where the cells of each row start in `columns` and `values`, followed by the
+    /// number of cells, so row `i` is `row_offsets[i]..row_offsets[i + 1]`.
+    pub row_offsets: Vec<u64>,
+    /// The position in `destinations` of each cell's destination.
+    pub columns: Vec<u32>,
+    /// The value of each cell.
+    pub values: Vec<f32>,
+}
+
+impl CsrMatrix {
+    /// Collects the non-zero cells of a flattened `|origins| * |destinations|` matrix.
+    pub fn from_dense(origins: &[i32], destinations: &[i32], matrix: &[f32]) -> CsrMatrix {
+        let mut row_offsets = Vec::with_capacity(origins.len() + 1);
+        let mut columns = Vec::new();
+        let mut values = Vec::new();
+        row_offsets.push(0);
+        if !destinations.is_empty() {
+            for row in matrix.chunks(destinations.len()) {
+                for (column, &value) in row.iter().enumerate() {
+                    if value != 0.0 {
+                        columns.push(column as u32);
+                        values.push(value);
+                    }
+                }
+                row_offsets.push(values.len() as u64);
+            }
+        }
+        row_offsets.resize(origins.len() + 1, values.len() as u64);
+        CsrMatrix {
+            origins: origins.to_vec(),
+            destinations: destinations.to_vec(),
+            row_offsets,
+            columns,
+            values,
+        }
+    }
+
+    /// Gets the destination positions and values of the non-zero cells of a row.
+    pub fn row(&self, row: usize) -> (&[u32], &[f32]) {
+        let cells = self.row_offsets[row] as usize..self.row_offsets[row + 1] as usize;
+        (&self.columns[cells.clone()], &self.values[cells])
+    }
+
+    /// Expands the cells back into a dense matrix, with zeros for the cells not stored.
+    pub fn to_dense(&self) -> MtxMatrix {
+        let width = self.destinations.len();
+        let mut values = vec![0.0f32; self.origins.len() * width];
+        for row in 0..self.origins.len() {
+            let (columns, row_values) = self.row(row);
+            for (&column, &value) in columns.iter().zip(row_values) {
+                values[row * width + column as usize] = value;
+            }
+        }
+        MtxMatrix {
+            origins: self.origins.clone(),
+            destinations: self.destinations.clone(),
+            values,
+            metadata: None,
+        }
+    }
+}
+
+/// Writes a matrix in compressed sparse row form. The file starts with `CSR_MAGIC`, its
+/// version, the numbers of origins, destinations and cells, then holds the origins, the
+/// destinations, the row offsets, the column of each cell and the value of each cell,
+/// all little-endian. Like an MTX file, it is written through a temporary file and
+/// compressed if the name ends with `.gz`.
+pub fn write_csr_file(output_file_name: &str, matrix: &CsrMatrix, fsync: bool) -> std::io::Result<()> {
+    write_output(output_file_name, fsync, |writer| write_csr(writer, matrix))
+}
+
+/// Writes the layout of `write_csr_file`.
+fn write_csr(writer: &mut impl Write, matrix: &CsrMatrix) -> std::io::Result<()> {
+    writer.write_all(&CSR_MAGIC)?;
+    writer.write_u32::<LittleEndian>(CSR_VERSION)?;
+    writer.write_u32::<LittleEndian>(matrix.origins.len() as u32)?;
+    writer.write_u32::<LittleEndian>(matrix.destinations.len() as u32)?;
+    writer.write_u64::<LittleEndian>(matrix.values.len() as u64)?;
+    for &zone in matrix.origins.iter().chain(&matrix.destinations) {
+        writer.write_i32::<LittleEndian>(zone)?;
+    }
+    for &offset in &matrix.row_offsets {
+        writer.write_u64::<LittleEndian>(offset)?;
+    }
+    for &column in &matrix.columns {
+        writer.write_u32::<LittleEndian>(column)?;
+    }
+    for &value in &matrix.values {
+        writer.write_f32::<LittleEndian>(value)?;
+    }
+    Ok(())
+}
+
+/// Reads a file written by `write_csr_file`, checking that its offsets and columns
+/// are within the matrix.
+pub fn read_csr_file(input_file_name: &str) -> std::io::Result<CsrMatrix> {
+    let invalid = |reason: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not a valid CSR file: {}", input_file_name, reason),
+        )
+    };
+    let input_file = File::open(input_file_name)?;
+    let input: Box<dyn Read> = if input_file_name.ends_with(".gz") {
+        Box::new(MultiGzDecoder::new(input_file))
+    } else {
+        Box::new(input_file)
+    };
+    let mut reader = BufReader::new(input);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != CSR_MAGIC {
+        return Err(invalid("bad magic number".to_string()));
+    }
+    let version = reader.read_u32::<LittleEndian>()?;
+    if version != CSR_VERSION {
+        return Err(invalid(format!("unsupported version {}", version)));
+    }
+    let origin_count = reader.read_u32::<LittleEndian>()? as usize;
+    let destination_count = reader.read_u32::<LittleEndian>()? as usize;
+    let cell_count = reader.read_u64::<LittleEndian>()?;
+    if cell_count > origin_count as u64 * destination_count as u64 {
+        return Err(invalid(format!("{} cells do not fit in the matrix", cell_count)));
+    }
+    let cell_count = cell_count as usize;
+
+    let mut origins = vec![0i32; origin_count];
+    reader.read_i32_into::<LittleEndian>(&mut origins)?;
+    let mut destinations = vec![0i32; destination_count];
+    reader.read_i32_into::<LittleEndian>(&mut destinations)?;
+    let mut row_offsets = vec![0u64; origin_count + 1];
+    reader.read_u64_into::<LittleEndian>(&mut row_offsets)?;
+    let mut columns = vec![0u32; cell_count];
+    reader.read_u32_into::<LittleEndian>(&mut columns)?;
+    let mut values = vec![0f32; cell_count];
+    reader.read_f32_into::<LittleEndian>(&mut values)?;
+
+    if row_offsets[0] != 0 || row_offsets[origin_count] != cell_count as u64 || row_offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+        return Err(invalid("the row offsets are out of order".to_string()));
+    }
+    if columns.iter().any(|&column| column as usize >= destination_count) {
+        return Err(invalid("a cell's column is past the last destination".to_string()));
+    }
+    Ok(CsrMatrix {
+        origins,
+        destinations,
+        row_offsets,
+        columns,
+        values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mtx::{read_mtx_file, MtxReadOptions};
+
+    #[test]
+    fn test_csr_round_trip() -> std::io::Result<()> {
+        let matrix = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        let csr = CsrMatrix::from_dense(&matrix.origins, &matrix.destinations, &matrix.values);
+        assert_eq!(csr.row_offsets.len(), matrix.origins.len() + 1);
+        assert_eq!(csr.values.len(), matrix.values.iter().filter(|&&value| value != 0.0).count());
+
+        let output_file = "test/test_csr_output.csr";
+        write_csr_file(output_file, &csr, false)?;
+        let read = read_csr_file(output_file)?;
+        assert_eq!(read, csr);
+        assert!(!std::path::Path::new("test/test_csr_output.csr.tmp").exists());
+        write_csr_file("test/test_csr_output.csr.gz", &csr, true)?;
+        assert_eq!(read_csr_file("test/test_csr_output.csr.gz")?, csr);
+        assert_eq!(read.to_dense(), matrix);
+        let (columns, values) = read.row(0);
+        assert_eq!(columns, &[0, 1, 2]);
+        assert_eq!(values, &matrix.values[..3]);
+
+        assert!(read_csr_file("test/test_expected.mtx").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt::{Display, LowerExp};
+use std::fs::File;
+use std::str::FromStr;
+
+/// How numbers are written in text outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum FloatFormat {
+    /// The shortest text that reads back as the same value, so an `f32` of 0.3 is
+    /// written as `0.3` rather than `0.30000001`.
+    #[default]
+    Shortest,
+    /// A fixed number of decimal places, written as `{:.N}`.
+    Fixed(usize),
+    /// Scientific notation, written as `{:e}`, or `{:.Ne}` for N decimal places.
+    Scientific(Option<usize>),
+}
+
+impl FromStr for FloatFormat {
+    type Err = String;
+
+    /// Parses `shortest`, `{:.N}`, `{:e}` or `{:.Ne}`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid float format '{}', expected shortest, {{:.N}}, {{:e}} or {{:.Ne}}", text);
+        if text == "shortest" {
+            return Ok(FloatFormat::Shortest);
+        }
+        let spec = text
+            .strip_prefix("{:")
+            .and_then(|spec| spec.strip_suffix('}'))
+            .ok_or_else(invalid)?;
+        if spec == "e" {
+            return Ok(FloatFormat::Scientific(None));
+        }
+        let precision = spec.strip_prefix('.').ok_or_else(invalid)?;
+        match precision.strip_suffix('e') {
+            Some(precision) => precision.parse().map(|precision| FloatFormat::Scientific(Some(precision))),
+            None => precision.parse().map(FloatFormat::Fixed),
+        }
+        .map_err(|_| invalid())
+    }
+}
+
+impl TryFrom<String> for FloatFormat {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl FloatFormat {
+    /// Writes a value in this format.
+    pub fn format<T: Display + LowerExp>(self, value: T) -> String {
+        match self {
+            FloatFormat::Shortest => value.to_string(),
+            FloatFormat::Fixed(precision) => format!("{:.*}", precision, value),
+            FloatFormat::Scientific(None) => format!("{:e}", value),
+            FloatFormat::Scientific(Some(precision)) => format!("{:.*e}", precision, value),
+        }
+    }
+}
+
+/// Writes a two-column CSV file pairing each zone with a value, such as the
+/// row or column sums of a matrix.
+///
+/// # Arguments
+/// * `output_file` - The path to the CSV file to write.
+/// * `value_header` - The header for the value column.
+/// * `zones` - The zone numbers.
+/// * `values` - The value for each zone.
+/// * `float_format` - How the values are written.
+pub fn write_zone_values<T: Display + LowerExp + Copy>(
+    output_file: &str,
+    value_header: &str,
+    zones: &[i32],
+    values: &[T],
+    float_format: FloatFormat,
+) -> std::io::Result<()> {
+    let mut writer = csv::Writer::from_writer(File::create(output_file)?);
+    writer.write_record(["zone", value_header])?;
+    for (zone, &value) in zones.iter().zip(values) {
+        writer.write_record([zone.to_string(), float_format.format(value)])?;
+    }
+    writer.flush()
+}
+
+/// Writes an `origin,destination,value` CSV file of individual cells, such as the
+/// largest flows of a matrix.
+pub fn write_cells<T: Display + LowerExp + Copy>(
+    output_file: &str,
+    cells: &[(i32, i32, T)],
+    float_format: FloatFormat,
+) -> std::io::Result<()> {
+    let mut writer = csv::Writer::from_writer(File::create(output_file)?);
+    writer.write_record(["origin", "destination", "value"])?;
+    for &(origin, destination, value) in cells {
+        writer.write_record([origin.to_string(), destination.to_string(), float_format.format(value)])?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_float_format() {
+        assert_eq!(FloatFormat::default().format(0.3f32), "0.3");
+        assert_eq!("{:.2}".parse::<FloatFormat>().unwrap().format(0.304f32), "0.30");
+        assert_eq!("{:e}".parse::<FloatFormat>().unwrap().format(1500.0f64), "1.5e3");
+        assert_eq!("{:.1e}".parse::<FloatFormat>().unwrap().format(1234.0f64), "1.2e3");
+        assert_eq!("shortest".parse::<FloatFormat>(), Ok(FloatFormat::Shortest));
+        assert!("{:x}".parse::<FloatFormat>().is_err());
+        assert!("%.2f".parse::<FloatFormat>().is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::mtx::{read_mtx_file, write_mtx_file, MtxReadOptions, MtxWriteOptions};
+
+/// Compares two MTX files cell by cell and reports how many cells differ by more
+/// than the tolerance. Both files must use the same zone systems.
+///
+/// # Arguments
+/// * `first_file` - The path to the first MTX file.
+/// * `second_file` - The path to the second MTX file.
+/// * `output_file` - Optional path to write the difference (first - second) to as an MTX file.
+/// * `tolerance` - The largest absolute difference for two cells to be considered equal.
+/// * `read_options` - The byte order of the input files and how to handle truncation.
+///
+/// # Returns
+/// The number of cells that differ.
+pub fn diff_mtx_files(
+    first_file: &str,
+    second_file: &str,
+    output_file: Option<&str>,
+    tolerance: f32,
+    read_options: &MtxReadOptions,
+) -> std::io::Result<usize> {
+    let first = read_mtx_file(first_file, read_options)?;
+    let second = read_mtx_file(second_file, read_options)?;
+    if first.origins != second.origins || first.destinations != second.destinations {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} and {} do not use the same zone system", first_file, second_file),
+        ));
+    }
+
+    let difference: Vec<f32> = first
+        .values
+        .iter()
+        .zip(&second.values)
+        .map(|(a, b)| a - b)
+        .collect();
+    let different_cells = difference.iter().filter(|d| d.abs() > tolerance).count();
+    let max_difference = difference.iter().fold(0.0f32, |max, d| max.max(d.abs()));
+    println!("Cells differing: {} of {}", different_cells, difference.len());
+    println!("Largest absolute difference: {}", max_difference);
+
+    if let Some(output_file) = output_file {
+        write_mtx_file(output_file, &first.origins, &first.destinations, &difference, &MtxWriteOptions::default())?;
+    }
+    Ok(different_cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_mtx_files() -> std::io::Result<()> {
+        let transposed = "test/test_diff_transpose_output.mtx";
+        crate::transpose::transpose_mtx_file("test/test_expected.mtx", transposed, &MtxReadOptions::default())?;
+        assert_eq!(diff_mtx_files("test/test_expected.mtx", "test/test_expected.mtx", None, 0.0, &MtxReadOptions::default())?, 0);
+        // Only the symmetric cells of the test matrix are unchanged by a transpose
+        assert_eq!(diff_mtx_files("test/test_expected.mtx", transposed, None, 0.0, &MtxReadOptions::default())?, 6);
+        Ok(())
+    }
+}
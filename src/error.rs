@@ -0,0 +1,87 @@
+use std::fmt;
+
+/// The kinds of failure that callers of the library may want to handle differently.
+///
+/// The public functions return `std::io::Result`, with these errors carried inside the
+/// `std::io::Error`, so `Error::from` recovers them for matching. Any other failure,
+/// such as a missing file, becomes `Error::Io`.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// A field of the input could not be parsed.
+    Parse {
+        /// The line of the input the field is on, counting from 1.
+        line: u64,
+        /// The position of the field in its row, counting from 1.
+        field: usize,
+        /// Why the field could not be parsed.
+        message: String,
+    },
+    /// A file is a version or kind of its format that cannot be read.
+    UnknownFormat { file: String, reason: String },
+    /// There are more zones than the reader of the output can load.
+    ZoneOverflow { zones: usize, limit: usize },
+    /// A zone is listed more than once with different entries, such as two partitions.
+    DuplicateZone { zone: i32, file: String },
+    /// A file does not start with the magic number of its format.
+    MagicMismatch { file: String, found: u32 },
+}
+
+impl Error {
+    /// The exit code of the command line tool for the error, following the BSD
+    /// `sysexits.h` convention so scripts can tell bad input from a failing disk.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io(e) => match e.kind() {
+                std::io::ErrorKind::InvalidInput => 64,
+                std::io::ErrorKind::NotFound => 66,
+                std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => 65,
+                std::io::ErrorKind::Unsupported => 69,
+                _ => 74,
+            },
+            Error::Parse { .. } | Error::DuplicateZone { .. } | Error::ZoneOverflow { .. } => 65,
+            Error::UnknownFormat { .. } | Error::MagicMismatch { .. } => 65,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Parse { line, field, message } => write!(f, "Line {}, field {}: {}", line, field, message),
+            Error::UnknownFormat { file, reason } => write!(f, "{} is not a valid MTX file: {}", file, reason),
+            Error::ZoneOverflow { zones, limit } => write!(f, "Found {} zones, more than the limit of {}", zones, limit),
+            Error::DuplicateZone { zone, file } => write!(f, "The zone {} is listed more than once in {} with different entries", zone, file),
+            Error::MagicMismatch { file, found } => {
+                write!(f, "{} is not a valid MTX file: bad magic number {:#X}", file, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        error.downcast::<Error>().unwrap_or_else(Error::Io)
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(e) => e,
+            // Every other error is about the contents of a file
+            error => std::io::Error::new(std::io::ErrorKind::InvalidData, error),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::convert::OutputFormat;
+use crate::input::{peek_dimensions, RectOrientation};
+use crate::options::ConversionOptions;
+use crate::zones::{read_zone_aggregation, read_zones_file, ZoneSource, ZonesFile, ZonesMode};
+
+/// Works out how a conversion would run with the given options, reading no more of the
+/// input than its header row and the zones file, if there is one.
+///
+/// # Arguments
+/// * `input_file` - The path to the input CSV file.
+/// * `output_file` - The path the matrix would be written to.
+/// * `options` - The resolved conversion options.
+///
+/// # Returns
+/// Each decision, such as the input's format or the zones, with a description of it.
+pub fn conversion_plan(
+    input_file: &str,
+    output_file: &str,
+    options: &ConversionOptions,
+) -> std::io::Result<Vec<(&'static str, String)>> {
+    let read_options = options.read_options()?;
+    let mut plan = vec![("Input", input_file.to_string())];
+
+    let header_zones = if options.pivot_col.is_some() || read_options.value_expression.is_some() || options.columns.is_some() {
+        None
+    } else {
+        peek_dimensions(input_file, &read_options)?.map(|dimensions| dimensions.destinations)
+    };
+    let format = if let Some(pivot_col) = &options.pivot_col {
+        format!("long-format CSV with a matrix per value of column {}", pivot_col)
+    } else if let Some(value_expr) = &read_options.value_expression {
+        format!("column CSV with a header row, taking each value from {}", value_expr)
+    } else if let Some(columns) = &options.columns {
+        let header = if options.header { ", after a header row" } else { "" };
+        format!("column CSV reading {}{}", columns, header)
+    } else if let Some(zones) = header_zones {
+        let listed = match options.rect_orientation {
+            RectOrientation::DestHeader => "destinations",
+            RectOrientation::OriginHeader => "origins",
+        };
+        format!("square CSV with {} zones in its header row, which lists the {}", zones, listed)
+    } else {
+        "column CSV of origin, destination and value".to_string()
+    };
+    plan.push(("Format", format));
+    let delimiter = match read_options.delimiter {
+        b'\t' => "tab".to_string(),
+        delimiter => format!("'{}'", delimiter as char),
+    };
+    plan.push(("Delimiter", delimiter));
+    let value_type = if options.auto_promote {
+        "64-bit float if a value cannot be stored exactly as a 32-bit float, otherwise 32-bit float"
+    } else {
+        "32-bit float"
+    };
+    plan.push(("Value type", value_type.to_string()));
+    plan.push(("Fill", options.fill.to_string()));
+    let duplicates = if options.aggregate.is_some() {
+        "every row between the zones of two districts is summed into their cell"
+    } else {
+        "the last row for a cell replaces the earlier ones"
+    };
+    plan.push(("Duplicates", duplicates.to_string()));
+    let districts = match &options.aggregate {
+        Some(aggregate_file) => {
+            let zones_file = ZonesFile {
+                delimiter: options.delimiter_byte()?,
+                ..ZonesFile::new(aggregate_file)
+            };
+            let mut districts: Vec<i32> = read_zone_aggregation(&zones_file)?.into_values().collect();
+            districts.sort_unstable();
+            districts.dedup();
+            Some((aggregate_file, districts.len()))
+        }
+        None => None,
+    };
+
+    let zones_file = options.zones_file()?;
+    let listed_zones = match &zones_file {
+        Some(zones_file) if zones_file.source != ZoneSource::Range => Some(read_zones_file(zones_file)?.len()),
+        _ => None,
+    };
+    let zones = match (&zones_file, listed_zones, districts) {
+        (_, _, Some((aggregate_file, count))) => {
+            format!("the {} districts listed in {}, into which the zones of the input are summed", count, aggregate_file)
+        }
+        (Some(zones_file), None, _) if zones_file.source == ZoneSource::Range => {
+            "every zone number from 1 to the largest zone of the input".to_string()
+        }
+        (Some(zones_file), Some(count), _) if zones_file.ordered => {
+            format!("the {} zones listed in {}, in their order", count, zones_file.path)
+        }
+        (Some(zones_file), Some(count), _) => match zones_file.mode {
+            ZonesMode::Replace => format!("the {} zones listed in {}", count, zones_file.path),
+            ZonesMode::Union => format!("the zones of the input and the {} listed in {}", count, zones_file.path),
+            ZonesMode::Intersect => format!("the zones of the input among the {} listed in {}", count, zones_file.path),
+        },
+        _ => "the zones of the input".to_string(),
+    };
+    plan.push(("Zones", zones));
+
+    let format_name = match options.format {
+        OutputFormat::Mtx => "MTX",
+        OutputFormat::Parquet => "Parquet",
+        OutputFormat::Raw => "raw",
+        OutputFormat::Csr => "CSR",
+    };
+    let destination = if output_file == "-" {
+        "standard output".to_string()
+    } else if output_file.ends_with(".gz") {
+        format!("{}, gzip-compressed", output_file)
+    } else {
+        output_file.to_string()
+    };
+    let split = if options.pivot_col.is_some() {
+        ", a file per pivot value"
+    } else if options.multi_block {
+        ", a numbered file per block"
+    } else if options.partition_file.is_some() {
+        ", a file per partition"
+    } else {
+        ""
+    };
+    plan.push(("Output", format!("{} to {}{}", format_name, destination, split)));
+
+    // Aggregating replaces the zones with the districts, whichever zones are kept
+    let replaced_zones = match districts {
+        Some((_, count)) => Some(count),
+        None => listed_zones.filter(|_| zones_file.is_some_and(|file| file.ordered || file.mode == ZonesMode::Replace)),
+    };
+    let size = match (replaced_zones, header_zones) {
+        (Some(count), _) => format!("{} x {}", count, count),
+        (None, Some(zones)) if zones_file.is_none() => {
+            format!("at least {} x {}, from the header row", zones, zones)
+        }
+        _ => "known once every row has been read".to_string(),
+    };
+    plan.push(("Matrix", size));
+    Ok(plan)
+}
+
+/// Prints how a conversion would run, without reading the input's rows or writing anything.
+pub fn print_conversion_plan(input_file: &str, output_file: &str, options: &ConversionOptions) -> std::io::Result<()> {
+    for (decision, description) in conversion_plan(input_file, output_file, options)? {
+        println!("{}: {}", decision, description);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::convert_csv_to_mtx;
+
+    #[test]
+    fn test_explain() -> std::io::Result<()> {
+        let output_file = "test/test_explain_output.mtx.gz";
+        let _ = std::fs::remove_file(output_file);
+        let options = ConversionOptions {
+            explain: true,
+            fill: -1.0,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test_rectangular.csv", output_file, &options)?;
+        assert!(!std::path::Path::new(output_file).exists());
+
+        let plan = conversion_plan("test/test_rectangular.csv", output_file, &options)?;
+        let decision = |name: &str| plan.iter().find(|(decision, _)| *decision == name).map(|(_, description)| description.clone());
+        assert!(decision("Format").unwrap().starts_with("square CSV with 3 zones"));
+        assert_eq!(decision("Delimiter").as_deref(), Some("','"));
+        assert_eq!(decision("Fill").as_deref(), Some("-1"));
+        assert_eq!(decision("Output").as_deref(), Some("MTX to test/test_explain_output.mtx.gz, gzip-compressed"));
+        assert_eq!(decision("Matrix").as_deref(), Some("at least 3 x 3, from the header row"));
+
+        let plan = conversion_plan("test/test.csv", "-", &ConversionOptions::default())?;
+        assert_eq!(plan[1], ("Format", "column CSV of origin, destination and value".to_string()));
+
+        let aggregate_file = std::env::temp_dir().join("csv_to_mtx_test_explain_aggregate.csv");
+        std::fs::write(&aggregate_file, "zone,district\n1,10\n2,10\n3,20\n4,20\n")?;
+        let aggregate_file = aggregate_file.to_string_lossy().to_string();
+        let options = ConversionOptions {
+            aggregate: Some(aggregate_file.clone()),
+            ..ConversionOptions::default()
+        };
+        let plan = conversion_plan("test/test.csv", "-", &options)?;
+        let decision = |name: &str| plan.iter().find(|(decision, _)| *decision == name).map(|(_, description)| description.clone());
+        assert_eq!(
+            decision("Duplicates").as_deref(),
+            Some("every row between the zones of two districts is summed into their cell")
+        );
+        assert_eq!(
+            decision("Zones"),
+            Some(format!("the 2 districts listed in {}, into which the zones of the input are summed", aggregate_file))
+        );
+        assert_eq!(decision("Matrix").as_deref(), Some("2 x 2"));
+        std::fs::remove_file(aggregate_file)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::csv_output::FloatFormat;
+use crate::matrix::{column_sums, row_sums};
+use crate::mtx::{read_mtx_file, MtxMatrix, MtxReadOptions};
+
+/// The text files an MTX file is exported to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExportOptions {
+    /// Optional path to write the whole matrix to as a rectangular CSV file.
+    pub to_rectangular: Option<String>,
+    /// Optional path to write the non-zero cells to as a Matrix Market file.
+    pub to_matrix_market: Option<String>,
+    /// Append a `Total` column of row sums and a `Total` row of column sums to the
+    /// rectangular CSV file, with the grand total in the corner.
+    pub with_totals: bool,
+    /// How the values are written.
+    pub float_format: FloatFormat,
+}
+
+/// Reads an MTX file and writes it to each of the requested text formats.
+///
+/// # Arguments
+/// * `input_file` - The path to the MTX file to export.
+/// * `options` - The files to write.
+/// * `read_options` - The byte order of the input file and how to handle truncation.
+pub fn export_mtx_file(
+    input_file: &str,
+    options: &ExportOptions,
+    read_options: &MtxReadOptions,
+) -> std::io::Result<()> {
+    if options.to_rectangular.is_none() && options.to_matrix_market.is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Nothing to export, use --to-rectangular or --to-matrix-market to choose an output file",
+        ));
+    }
+    let matrix = read_mtx_file(input_file, read_options)?;
+    if let Some(output_file) = &options.to_rectangular {
+        write_rectangular_csv(output_file, &matrix, options.float_format, options.with_totals)?;
+    }
+    if let Some(output_file) = &options.to_matrix_market {
+        write_matrix_market(output_file, &matrix, options.float_format)?;
+    }
+    Ok(())
+}
+
+/// The text format every MTX file in a directory is converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BatchFormat {
+    /// Matrix Market coordinate files, written next to each input with a `.mm` extension.
+    #[value(name = "matrixmarket")]
+    MatrixMarket,
+}
+
+/// Converts every MTX file directly inside a directory, recognised by its `.mtx` or
+/// `.mtx.gz` extension, writing each output next to its input. A file that fails is
+/// reported and skipped, unless `strict` is set, so one bad file does not stop the batch.
+///
+/// # Arguments
+/// * `directory` - The directory holding the MTX files.
+/// * `format` - The format to convert them to.
+/// * `float_format` - How the values are written.
+/// * `read_options` - The byte order of the input files and how to handle truncation.
+/// * `strict` - Stop at the first file that fails instead of skipping it.
+///
+/// # Returns
+/// The number of files converted and the number that failed.
+pub fn batch_convert(
+    directory: &str,
+    format: BatchFormat,
+    float_format: FloatFormat,
+    read_options: &MtxReadOptions,
+    strict: bool,
+) -> std::io::Result<(usize, usize)> {
+    let mut input_files: Vec<PathBuf> = std::fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    input_files.retain(|path| mtx_stem(path).is_some() && path.is_file());
+    input_files.sort();
+
+    let (mut converted, mut failed) = (0, 0);
+    for input_file in &input_files {
+        let output_file = match format {
+            BatchFormat::MatrixMarket => input_file.with_file_name(format!("{}.mm", mtx_stem(input_file).unwrap())),
+        };
+        let result = read_mtx_file(&input_file.to_string_lossy(), read_options)
+            .and_then(|matrix| write_matrix_market(&output_file.to_string_lossy(), &matrix, float_format));
+        match result {
+            Ok(()) => {
+                println!("Wrote {}", output_file.display());
+                converted += 1;
+            }
+            Err(e) if strict => {
+                eprintln!("Error converting {}: {}", input_file.display(), e);
+                return Err(e);
+            }
+            Err(e) => {
+                eprintln!("Error converting {}, skipping it: {}", input_file.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    Ok((converted, failed))
+}
+
+/// The file name of an MTX file without its `.mtx` or `.mtx.gz` extension, or `None`
+/// if it is not an MTX file.
+fn mtx_stem(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    name.strip_suffix(".mtx.gz").or_else(|| name.strip_suffix(".mtx"))
+}
+
+/// Writes the non-zero cells of the matrix as a Matrix Market coordinate file, which
+/// numbers its rows and columns from 1. The zone numbers of the rows and columns are
+/// listed in comments, as the format has nowhere else to keep them.
+fn write_matrix_market(output_file: &str, matrix: &MtxMatrix, float_format: FloatFormat) -> std::io::Result<()> {
+    let columns = matrix.destinations.len();
+    let non_zero = matrix.values.iter().filter(|&&value| value != 0.0).count();
+    let mut writer = BufWriter::new(File::create(output_file)?);
+    writeln!(writer, "%%MatrixMarket matrix coordinate real general")?;
+    writeln!(writer, "% origins: {}", join_zones(&matrix.origins))?;
+    writeln!(writer, "% destinations: {}", join_zones(&matrix.destinations))?;
+    writeln!(writer, "{} {} {}", matrix.origins.len(), columns, non_zero)?;
+    for (cell, &value) in matrix.values.iter().enumerate() {
+        if value != 0.0 {
+            writeln!(writer, "{} {} {}", cell / columns + 1, cell % columns + 1, float_format.format(value))?;
+        }
+    }
+    writer.flush()
+}
+
+fn join_zones(zones: &[i32]) -> String {
+    zones.iter().map(i32::to_string).collect::<Vec<_>>().join(" ")
+}
+
+/// Writes every cell of the matrix as a rectangular CSV file, the inverse of the
+/// rectangular input format. The header row lists the destinations after an empty
+/// corner cell, and each row starts with its origin. With `with_totals`, each row
+/// ends with its sum and a last row holds the column sums and the grand total.
+fn write_rectangular_csv(
+    output_file: &str,
+    matrix: &MtxMatrix,
+    float_format: FloatFormat,
+    with_totals: bool,
+) -> std::io::Result<()> {
+    let mut writer = csv::Writer::from_writer(File::create(output_file)?);
+    let totals_header = with_totals.then(|| "Total".to_string());
+    let header = std::iter::once(String::new())
+        .chain(matrix.destinations.iter().map(i32::to_string))
+        .chain(totals_header.clone());
+    writer.write_record(header)?;
+    let columns = matrix.destinations.len();
+    // The sums are accumulated at double precision but written at the matrix's precision
+    let format_sum = |sum: f64| float_format.format(sum as f32);
+    let row_totals = if with_totals { row_sums(&matrix.values, columns) } else { Vec::new() };
+    for (index, origin) in matrix.origins.iter().enumerate() {
+        let row = &matrix.values[index * columns..(index + 1) * columns];
+        let record = std::iter::once(origin.to_string())
+            .chain(row.iter().map(|&value| float_format.format(value)))
+            .chain(row_totals.get(index).map(|&sum| format_sum(sum)));
+        writer.write_record(record)?;
+    }
+    if let Some(totals_header) = totals_header {
+        let column_totals = column_sums(&matrix.values, columns);
+        let grand_total: f64 = column_totals.iter().sum();
+        let record = std::iter::once(totals_header)
+            .chain(column_totals.into_iter().map(format_sum))
+            .chain(std::iter::once(format_sum(grand_total)));
+        writer.write_record(record)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::convert_csv_to_mtx;
+    use crate::options::ConversionOptions;
+
+    #[test]
+    fn test_rectangular_round_trip() -> std::io::Result<()> {
+        let mtx_file = "test/test_export_output.mtx";
+        let options = ConversionOptions {
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test_rectangular.csv", mtx_file, &options)?;
+        let export = ExportOptions {
+            to_rectangular: Some("test/test_export_output.csv".to_string()),
+            ..ExportOptions::default()
+        };
+        export_mtx_file(mtx_file, &export, &MtxReadOptions::default())?;
+        assert_eq!(
+            std::fs::read_to_string("test/test_export_output.csv")?,
+            std::fs::read_to_string("test/test_rectangular.csv")?
+        );
+
+        // Empty cells are written as zeros
+        convert_csv_to_mtx("test/test.csv", mtx_file, &options)?;
+        export_mtx_file(mtx_file, &export, &MtxReadOptions::default())?;
+        let exported = std::fs::read_to_string("test/test_export_output.csv")?;
+        assert_eq!(exported.lines().nth(3), Some("3,0,0,0,0"));
+
+        let two_decimals = ExportOptions {
+            float_format: "{:.2}".parse().unwrap(),
+            ..export.clone()
+        };
+        export_mtx_file(mtx_file, &two_decimals, &MtxReadOptions::default())?;
+        let exported = std::fs::read_to_string("test/test_export_output.csv")?;
+        assert_eq!(exported.lines().nth(1), Some("1,0.10,0.20,0.30,0.00"));
+
+        assert!(export_mtx_file(mtx_file, &ExportOptions::default(), &MtxReadOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rectangular_with_totals() -> std::io::Result<()> {
+        let mtx_file = "test/test_totals_output.mtx";
+        let options = ConversionOptions {
+            force: true,
+            ..ConversionOptions::default()
+        };
+        convert_csv_to_mtx("test/test.csv", mtx_file, &options)?;
+        let export = ExportOptions {
+            to_rectangular: Some("test/test_totals_output.csv".to_string()),
+            with_totals: true,
+            ..ExportOptions::default()
+        };
+        export_mtx_file(mtx_file, &export, &MtxReadOptions::default())?;
+        let exported = std::fs::read_to_string("test/test_totals_output.csv")?;
+        let lines: Vec<&str> = exported.lines().collect();
+        assert_eq!(lines[0], ",1,2,3,4,Total");
+        assert_eq!(lines[1], "1,0.1,0.2,0.3,0,0.6");
+        assert_eq!(lines[3], "3,0,0,0,0,0");
+
+        let matrix = read_mtx_file(mtx_file, &MtxReadOptions::default())?;
+        let column_totals: Vec<String> = column_sums(&matrix.values, 4).iter().map(|&sum| (sum as f32).to_string()).collect();
+        let grand_total: f64 = row_sums(&matrix.values, 4).iter().sum();
+        assert_eq!(lines[5], format!("Total,{},{}", column_totals.join(","), grand_total as f32));
+        assert_eq!(lines.len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_convert_to_matrix_market() -> std::io::Result<()> {
+        let directory = std::env::temp_dir().join("csv_to_mtx_test_batch_convert");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir(&directory)?;
+        std::fs::copy("test/test_expected.mtx", directory.join("first.mtx"))?;
+        let matrix = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        let big_endian = crate::mtx::MtxWriteOptions {
+            endianness: crate::mtx::Endianness::Big,
+            ..crate::mtx::MtxWriteOptions::default()
+        };
+        crate::mtx::write_mtx_file(
+            &directory.join("second.mtx.gz").to_string_lossy(),
+            &matrix.origins,
+            &matrix.destinations,
+            &matrix.values,
+            &big_endian,
+        )?;
+        std::fs::write(directory.join("notes.txt"), "not a matrix")?;
+        let directory_name = directory.to_str().unwrap();
+
+        let (converted, failed) = batch_convert(
+            directory_name,
+            BatchFormat::MatrixMarket,
+            FloatFormat::Shortest,
+            &MtxReadOptions::default(),
+            false,
+        )?;
+        assert_eq!((converted, failed), (2, 0));
+        let first = std::fs::read_to_string(directory.join("first.mm"))?;
+        assert_eq!(first, std::fs::read_to_string(directory.join("second.mm"))?);
+        let mut lines = first.lines();
+        assert_eq!(lines.next(), Some("%%MatrixMarket matrix coordinate real general"));
+        assert_eq!(lines.next(), Some("% origins: 1 2 3 4"));
+        assert_eq!(lines.nth(1), Some("4 4 7"));
+        assert_eq!(lines.next(), Some("1 1 0.1"));
+        assert_eq!(first.lines().count(), 4 + 7);
+
+        // A broken file is skipped, or stops the batch when strict
+        std::fs::write(directory.join("broken.mtx"), [0u8; 8])?;
+        let (converted, failed) = batch_convert(
+            directory_name,
+            BatchFormat::MatrixMarket,
+            FloatFormat::Shortest,
+            &MtxReadOptions::default(),
+            false,
+        )?;
+        assert_eq!((converted, failed), (2, 1));
+        assert!(batch_convert(directory_name, BatchFormat::MatrixMarket, FloatFormat::Shortest, &MtxReadOptions::default(), true).is_err());
+        Ok(())
+    }
+}
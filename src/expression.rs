@@ -0,0 +1,130 @@
+use evalexpr::{ContextWithMutableVariables, DefaultNumericTypes, HashMapContext, Node, Value};
+
+/// An arithmetic expression computing each row's value from the named columns of a
+/// CSV file, such as `distance / time`.
+pub struct ValueExpression {
+    node: Node<DefaultNumericTypes>,
+    /// The name of each column the expression uses, with its position in the row.
+    columns: Vec<(String, usize)>,
+    context: HashMapContext<DefaultNumericTypes>,
+}
+
+impl ValueExpression {
+    /// Parses the expression and finds the columns it uses in the header row.
+    ///
+    /// # Arguments
+    /// * `expression` - The expression, using the header names as variables.
+    /// * `header` - The header row of the CSV file.
+    pub fn parse(expression: &str, header: &csv::StringRecord) -> std::io::Result<ValueExpression> {
+        let node = evalexpr::build_operator_tree::<DefaultNumericTypes>(expression).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid value expression '{}': {}", expression, e),
+            )
+        })?;
+        let mut columns: Vec<(String, usize)> = Vec::new();
+        for name in node.iter_read_variable_identifiers() {
+            if columns.iter().any(|(column, _)| column == name) {
+                continue;
+            }
+            let index = header.iter().position(|column| column.trim() == name).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("The value expression uses the column '{}', which is not in the header", name),
+                )
+            })?;
+            columns.push((name.to_string(), index));
+        }
+        Ok(ValueExpression {
+            node,
+            columns,
+            context: HashMapContext::new(),
+        })
+    }
+
+    /// Evaluates the expression for a row.
+    ///
+    /// # Returns
+    /// The value, or `None` if one of the columns fails to parse. Division by zero
+    /// gives an infinite or NaN value rather than an error.
+    pub fn evaluate(&mut self, record: &csv::StringRecord) -> std::io::Result<Option<f64>> {
+        for (name, index) in &self.columns {
+            let Some(Ok(value)) = record.get(*index).map(|field| field.trim().parse::<f64>()) else {
+                return Ok(None);
+            };
+            // Setting an existing variable can only fail if its type changes, and every column is a float
+            let _ = self.context.set_value(name.clone(), Value::Float(value));
+        }
+        self.node.eval_number_with_context(&self.context).map(Some).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unable to evaluate the value expression: {}", e),
+            )
+        })
+    }
+}
+
+/// Turns weights such as `auto=0.6,transit=0.4` into the expression summing each named
+/// column times its weight, here `auto * 0.6 + transit * 0.4`.
+pub fn weighted_sum_expression(weights: &str) -> std::io::Result<String> {
+    let invalid = |reason: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid value weights '{}': {}", weights, reason),
+        )
+    };
+    let mut terms = Vec::new();
+    for part in weights.split(',') {
+        let (name, weight) = part
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("'{}' is not a column=weight pair", part.trim())))?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(invalid(format!("'{}' is not a column name that can be weighted", name)));
+        }
+        let weight = weight
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|weight| weight.is_finite())
+            .ok_or_else(|| invalid(format!("the weight of {} is not a number", name)))?;
+        // Debug formatting keeps the decimal point, so every term is a float
+        terms.push(format!("{} * ({:?})", name, weight));
+    }
+    Ok(terms.join(" + "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_expression() -> std::io::Result<()> {
+        let header = csv::StringRecord::from(vec!["origin", "destination", "distance", "time"]);
+        let mut speed = ValueExpression::parse("distance / time", &header)?;
+        assert_eq!(speed.evaluate(&csv::StringRecord::from(vec!["1", "2", "10", "4"]))?, Some(2.5));
+        assert_eq!(speed.evaluate(&csv::StringRecord::from(vec!["1", "2", "10", "x"]))?, None);
+        assert!(speed.evaluate(&csv::StringRecord::from(vec!["1", "2", "10", "0"]))?.unwrap().is_infinite());
+
+        assert!(ValueExpression::parse("distance / cost", &header).is_err());
+        assert!(ValueExpression::parse("(distance * time", &header).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_weighted_sum_expression() -> std::io::Result<()> {
+        let expression = weighted_sum_expression("auto=0.6, transit=0.4")?;
+        assert_eq!(expression, "auto * (0.6) + transit * (0.4)");
+        let header = csv::StringRecord::from(vec!["origin", "destination", "auto", "transit"]);
+        let mut value = ValueExpression::parse(&expression, &header)?;
+        let result = value.evaluate(&csv::StringRecord::from(vec!["1", "2", "10", "5"]))?.unwrap();
+        assert!((result - 8.0).abs() < 1e-9);
+        assert_eq!(value.evaluate(&csv::StringRecord::from(vec!["1", "2", "10", ""]))?, None);
+
+        assert_eq!(weighted_sum_expression("walk=-1")?, "walk * (-1.0)");
+        assert!(weighted_sum_expression("auto").is_err());
+        assert!(weighted_sum_expression("auto=x").is_err());
+        assert!(weighted_sum_expression("auto time=1").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// The layout of a generated CSV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum GeneratedFormat {
+    /// One `origin,destination,value` row per non-zero cell.
+    #[default]
+    Column,
+    /// A rectangular matrix with a header row of destinations.
+    Square,
+}
+
+/// How the values of the generated cells are distributed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ValueDistribution {
+    /// Evenly spread between zero and twice the mean.
+    #[default]
+    Uniform,
+    /// Mostly small values with a long tail, like trip counts.
+    Exponential,
+}
+
+/// Settings for a synthetic input CSV file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerateOptions {
+    /// The number of zones, numbered from 1.
+    pub zones: usize,
+    /// The fraction of the cells that have a value.
+    pub density: f64,
+    /// The layout of the file.
+    pub format: GeneratedFormat,
+    /// How the values are distributed.
+    pub distribution: ValueDistribution,
+    /// The mean of the values.
+    pub mean: f64,
+    /// The seed for the random numbers, so the same file can be generated again.
+    pub seed: u64,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            zones: 100,
+            density: 0.1,
+            format: GeneratedFormat::Column,
+            distribution: ValueDistribution::Uniform,
+            mean: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+/// A small SplitMix64 generator, which is plenty for synthetic data and keeps the
+/// output identical across platforms for a given seed.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A uniform number in `(0, 1]`.
+    pub(crate) fn next_unit(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Writes a synthetic CSV file with exactly `density` of its cells filled in, chosen
+/// at random. The values are never zero, so every chosen cell is a non-zero cell of
+/// the converted matrix.
+///
+/// # Arguments
+/// * `output_file` - The path to the CSV file to write.
+/// * `options` - The size, density and values of the matrix.
+///
+/// # Returns
+/// The number of non-zero cells written.
+pub fn generate_csv(output_file: &str, options: &GenerateOptions) -> std::io::Result<usize> {
+    if !(0.0..=1.0).contains(&options.density) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("The density {} must be between 0 and 1", options.density),
+        ));
+    }
+    let cells = options.zones * options.zones;
+    let selected = (options.density * cells as f64).round() as usize;
+    let mut rng = SplitMix64(options.seed);
+    let mut writer = BufWriter::new(File::create(output_file)?);
+    if options.format == GeneratedFormat::Square {
+        let header: Vec<String> = (1..=options.zones).map(|zone| zone.to_string()).collect();
+        writeln!(writer, ",{}", header.join(","))?;
+    }
+
+    // Selection sampling visits the cells in order and picks exactly `selected` of them
+    let mut remaining = selected;
+    for cell in 0..cells {
+        let (origin, destination) = (cell / options.zones + 1, cell % options.zones + 1);
+        if options.format == GeneratedFormat::Square && destination == 1 {
+            write!(writer, "{}", origin)?;
+        }
+        let chosen = remaining > 0 && (rng.next_unit() * (cells - cell) as f64) <= remaining as f64;
+        let value = if chosen {
+            remaining -= 1;
+            let unit = rng.next_unit();
+            match options.distribution {
+                ValueDistribution::Uniform => 2.0 * options.mean * unit,
+                ValueDistribution::Exponential => (-options.mean * unit.ln()).max(f32::MIN_POSITIVE as f64),
+            }
+        } else {
+            0.0
+        };
+        match options.format {
+            GeneratedFormat::Column if chosen => writeln!(writer, "{},{},{}", origin, destination, value as f32)?,
+            GeneratedFormat::Column => {}
+            GeneratedFormat::Square => {
+                write!(writer, ",{}", value as f32)?;
+                if destination == options.zones {
+                    writeln!(writer)?;
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_csv() -> std::io::Result<()> {
+        let output_file = "test/test_generate_output.csv";
+        assert_eq!(generate_csv(output_file, &GenerateOptions::default())?, 1000);
+        let data = crate::input::read_csv(output_file, &crate::input::ReadOptions::default())?;
+        assert_eq!(data.len(), 1000);
+        assert!(data.iter().all(|&(origin, destination, value)| {
+            (1..=100).contains(&origin) && (1..=100).contains(&destination) && value > 0.0
+        }));
+
+        // The same seed gives the same cells in either layout
+        let options = GenerateOptions {
+            format: GeneratedFormat::Square,
+            distribution: ValueDistribution::Exponential,
+            ..GenerateOptions::default()
+        };
+        generate_csv(output_file, &options)?;
+        assert_eq!(std::fs::read_to_string(output_file)?.lines().count(), 101);
+        let square = crate::input::read_csv(output_file, &crate::input::ReadOptions::default())?;
+        let cells = |data: &[(i32, i32, f32)]| data.iter().map(|&(o, d, _)| (o, d)).collect::<Vec<_>>();
+        assert_eq!(cells(&square), cells(&data));
+        Ok(())
+    }
+}
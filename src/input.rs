@@ -0,0 +1,2009 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read};
+use std::ops::Range;
+use flate2::read::MultiGzDecoder;
+
+use crate::columns::{ColumnMapping, ColumnRef};
+use crate::error::Error;
+use crate::expression::ValueExpression;
+use crate::matrix::{loses_precision, CellValue};
+use crate::rejects::{RejectLog, RejectReason};
+use crate::truncation::{TolerantReader, TruncationReport};
+use crate::value_transform::{CellAggregate, ClampNegative, TimeUnit, ValueTransform};
+
+/// Which zones the header row of a rectangular CSV file lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RectOrientation {
+    /// The header row lists the destinations and the first column the origins.
+    #[default]
+    DestHeader,
+    /// The header row lists the origins and the first column the destinations, as
+    /// written by tools that store the transpose.
+    OriginHeader,
+}
+
+/// The origin, destination, and value of each row read from an input CSV file.
+type Rows<V = f32> = Vec<(i32, i32, V)>;
+
+/// The pivot value and range of the rows of each group of a long-format CSV file.
+type PivotGroups = Vec<(String, Range<usize>)>;
+
+/// Options controlling how input CSV files are read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadOptions {
+    /// The byte separating fields in the CSV file.
+    pub delimiter: u8,
+    /// Keep the records before a gzip decode error instead of failing, so the
+    /// valid prefix of a truncated file can be salvaged.
+    pub tolerate_truncation: bool,
+    /// Computes each row's value from the named columns of a file with a header row,
+    /// whose first two columns are the origin and destination.
+    pub value_expression: Option<String>,
+    /// Read a rectangular file whose rows have no origin column, taking each row's
+    /// origin from the destination in the same position of the header row.
+    pub implicit_origins: bool,
+    /// Skip the rows of a 3-column file whose value is exactly zero instead of storing them.
+    pub drop_zero_rows: bool,
+    /// Keep the zero cells of a rectangular file instead of skipping them, so they stay
+    /// zero when cells without any data take a fill value other than zero.
+    pub keep_zero_cells: bool,
+    /// The columns holding the origin, destination and value, skipping the detection
+    /// of the file's format.
+    pub columns: Option<ColumnMapping>,
+    /// The first row of a file read with `columns` is a header naming its columns.
+    pub has_header: bool,
+    /// Values such as `NA` that mark a missing value, leaving the cell as the fill value
+    /// without being reported as invalid.
+    pub na_values: Vec<String>,
+    /// Which zones the header row of a rectangular file lists.
+    pub rect_orientation: RectOrientation,
+    /// Accept zone labels written as integral decimals, such as `12.0`.
+    pub lenient_zone_ids: bool,
+    /// Ask at the terminal how to read a file whose first record could be either the
+    /// header row of a rectangular file or a row of a 3-column file with an extra column.
+    pub interactive: bool,
+    /// Turns the text of each value field into a number, instead of parsing it as one.
+    pub value_transform: Option<ValueTransform>,
+    /// The unit `HH:MM:SS` values are read in by `ValueTransform::Hms`.
+    pub time_unit: TimeUnit,
+    /// What is done with negative values, such as treating them as missing.
+    pub clamp_negative: ClampNegative,
+    /// The number of lines at the start of the file, such as a title or a date, to
+    /// discard before the format is detected.
+    pub skip_rows: usize,
+    /// Fail unless the row labels of a rectangular file are the same zones as its header.
+    pub require_square: bool,
+    /// Text before the number of every zone label, such as the `Z` of `Z00123`.
+    pub zone_prefix: String,
+    /// Text after the number of every zone label.
+    pub zone_suffix: String,
+    /// Fail on zone labels without the zone prefix and suffix instead of skipping their rows.
+    pub strict: bool,
+    /// The text separating the values of rectangular cells that hold several, such as `|`.
+    pub cell_split: Option<String>,
+    /// How the values of a split rectangular cell are combined.
+    pub cell_aggregate: CellAggregate,
+    /// The zone number of each zone name labelling the input, such as `Downtown`.
+    /// Labels that are not names in the map are parsed as zone numbers.
+    pub zone_map: HashMap<String, i32>,
+    /// Collects every record that is skipped, with its line and the reason.
+    pub reject_log: Option<RejectLog>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            delimiter: b',',
+            tolerate_truncation: false,
+            value_expression: None,
+            implicit_origins: false,
+            drop_zero_rows: false,
+            keep_zero_cells: false,
+            columns: None,
+            has_header: false,
+            na_values: Vec::new(),
+            rect_orientation: RectOrientation::DestHeader,
+            lenient_zone_ids: false,
+            interactive: false,
+            value_transform: None,
+            time_unit: TimeUnit::Minutes,
+            clamp_negative: ClampNegative::Keep,
+            skip_rows: 0,
+            require_square: false,
+            zone_prefix: String::new(),
+            zone_suffix: String::new(),
+            strict: false,
+            cell_split: None,
+            cell_aggregate: CellAggregate::Sum,
+            zone_map: HashMap::new(),
+            reject_log: None,
+        }
+    }
+}
+
+/// Reads the input CSV file and extracts the data as a vector of tuples containing
+/// origin, destination, and value. The file may be gzip-compressed, and with the
+/// `remote` feature it may be an `http://`, `https://` or `s3://` path.
+/// Automatically detects the CSV format:
+/// - 3-column format: origin, destination, value
+/// - Rectangular format: first row contains destinations, first column contains origins
+///
+/// # Arguments
+/// * `input_file` - The path to the input CSV file.
+/// * `read_options` - The delimiter and how to handle truncated input.
+///
+/// # Returns
+/// A vector of tuples `(i32, i32, f32)` representing the origin, destination, and value.
+pub fn read_csv(input_file: &str, read_options: &ReadOptions) -> std::io::Result<Vec<(i32, i32, f32)>> {
+    read_csv_as(input_file, read_options)
+}
+
+/// Reads the input CSV file like `read_csv`, storing the values as `V`. A warning is
+/// printed if any value cannot be stored exactly as `V`.
+pub fn read_csv_as<V: CellValue>(
+    input_file: &str,
+    read_options: &ReadOptions,
+) -> std::io::Result<Vec<(i32, i32, V)>> {
+    let (mut rdr, report) = open_csv(input_file, read_options)?;
+    let mut parser = ValueParser::new(read_options);
+    let data = match &read_options.value_expression {
+        Some(expression) => read_expression_csv(input_file, &mut rdr, expression, &mut parser)?,
+        None => read_csv_records(input_file, &mut rdr, read_options, &mut parser)?,
+    };
+    warn_if_truncated(input_file, &rdr, report.as_ref());
+    parser.finish(input_file)?;
+    Ok(data)
+}
+
+/// Converts the parsed values to the type the matrix is stored as, counting the
+/// values that it cannot hold exactly and the values that are not numbers.
+struct ValueParser<'a> {
+    imprecise: usize,
+    invalid: usize,
+    /// The first text counted as invalid, for the error when it must be a time.
+    first_invalid: Option<String>,
+    clamp_negative: ClampNegative,
+    /// The number of negative values, and the first of them, for the error when they
+    /// are not allowed.
+    negative: usize,
+    first_negative: Option<f64>,
+    na_values: &'a [String],
+    zones: ZoneLabels,
+    transform: Option<ValueTransform>,
+    time_unit: TimeUnit,
+    strict: bool,
+    cell_split: Option<&'a str>,
+    cell_aggregate: CellAggregate,
+    rejects: Option<&'a RejectLog>,
+    /// The lines discarded before the CSV reader starts, which its line numbers leave out.
+    skipped_lines: u64,
+}
+
+impl<'a> ValueParser<'a> {
+    fn new(read_options: &'a ReadOptions) -> Self {
+        ValueParser {
+            imprecise: 0,
+            invalid: 0,
+            first_invalid: None,
+            clamp_negative: read_options.clamp_negative,
+            negative: 0,
+            first_negative: None,
+            na_values: &read_options.na_values,
+            zones: ZoneLabels {
+                rejects: read_options.reject_log.clone(),
+                ..ZoneLabels::new(read_options)
+            },
+            transform: read_options.value_transform,
+            time_unit: read_options.time_unit,
+            strict: read_options.strict,
+            cell_split: read_options.cell_split.as_deref(),
+            cell_aggregate: read_options.cell_aggregate,
+            rejects: read_options.reject_log.as_ref(),
+            skipped_lines: read_options.skip_rows as u64,
+        }
+    }
+
+    /// Logs a record as rejected, if rejects are being logged.
+    fn reject(&self, record: &csv::StringRecord, reason: RejectReason, detail: impl FnOnce() -> String) {
+        self.reject_line(record_line(record), reason, detail);
+    }
+
+    /// Logs the record on a line of the CSV reader as rejected, if rejects are being logged.
+    fn reject_line(&self, line: u64, reason: RejectReason, detail: impl FnOnce() -> String) {
+        if let Some(rejects) = self.rejects {
+            rejects.reject(line + self.skipped_lines, reason, detail());
+        }
+    }
+
+    /// Logs the value in a field of a record as rejected if parsing it was counted as
+    /// invalid, given the count of invalid values from before it was parsed.
+    fn reject_if_invalid(&self, invalid_before: usize, record: &csv::StringRecord, field: usize) {
+        if self.invalid > invalid_before {
+            self.reject(record, RejectReason::ParseError, || {
+                format!("the value '{}' in field {} is not a number", record.get(field).unwrap_or(""), field + 1)
+            });
+        }
+    }
+
+    /// Notes the line of a row passed on to the matrix, if rejects are being logged.
+    fn record_row(&self, record: &csv::StringRecord) {
+        if let Some(rejects) = self.rejects {
+            rejects.record_row(record_line(record) + self.skipped_lines);
+        }
+    }
+
+    /// Parses a value, or `None` if it is missing or not a number. Empty cells and the
+    /// `na_values` are missing, anything else that is not a number is counted as invalid.
+    fn parse<V: CellValue>(&mut self, text: &str) -> Option<V> {
+        let value = self.number(text);
+        self.count(text, value)
+    }
+
+    /// Parses a cell of a rectangular file like `parse`, first splitting it at
+    /// `cell_split` and combining its values when cells hold several values.
+    fn parse_cell<V: CellValue>(&mut self, text: &str) -> Option<V> {
+        let Some(separator) = self.cell_split else {
+            return self.parse(text);
+        };
+        let value = text
+            .split(separator)
+            .map(|part| self.number(part.trim()))
+            .collect::<Option<Vec<f64>>>()
+            .and_then(|values| self.cell_aggregate.apply(&values));
+        self.count(text, value)
+    }
+
+    fn number(&self, text: &str) -> Option<f64> {
+        match self.transform {
+            Some(ValueTransform::Hms) => ValueTransform::Hms.apply(text).map(|minutes| self.time_unit.from_minutes(minutes)),
+            Some(transform) => transform.apply(text),
+            None => text.parse::<f64>().ok(),
+        }
+    }
+
+    /// Converts a parsed value, or counts the text as invalid if it did not parse.
+    fn count<V: CellValue>(&mut self, text: &str, value: Option<f64>) -> Option<V> {
+        match value {
+            Some(value) => {
+                if value < 0.0 {
+                    self.negative += 1;
+                    self.first_negative.get_or_insert(value);
+                }
+                self.clamp_negative.apply(value).map(|value| self.convert(value))
+            }
+            None => {
+                if !text.is_empty() && !self.na_values.iter().any(|na_value| na_value == text) {
+                    self.invalid += 1;
+                    if self.first_invalid.is_none() {
+                        self.first_invalid = Some(text.to_string());
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn convert<V: CellValue>(&mut self, value: f64) -> V {
+        if loses_precision::<V>(value) {
+            self.imprecise += 1;
+        }
+        V::from_f64(value)
+    }
+
+    /// Fails if a value that must be an `HH:MM:SS` time is malformed under `strict`, or
+    /// if negative values are an error, and otherwise prints the warnings.
+    fn finish(&self, input_file: &str) -> std::io::Result<()> {
+        if self.clamp_negative == ClampNegative::Error
+            && let Some(value) = self.first_negative
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} values in {} are negative, the first being {}", self.negative, input_file, value),
+            ));
+        }
+        if self.strict
+            && self.transform == Some(ValueTransform::Hms)
+            && let Some(text) = &self.first_invalid
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{} values in {} are not HH:MM:SS times, the first being '{}'",
+                    self.invalid, input_file, text
+                ),
+            ));
+        }
+        self.print_warnings(input_file);
+        Ok(())
+    }
+
+    /// Warns that values were rounded or overflowed when they were stored, or were
+    /// skipped because they are not numbers.
+    fn print_warnings(&self, input_file: &str) {
+        if self.imprecise > 0 {
+            eprintln!(
+                "Warning: {} values in {} cannot be stored exactly as 32-bit floats, use --auto-promote to write a double-precision matrix",
+                self.imprecise, input_file
+            );
+        }
+        if self.invalid > 0 {
+            let expected = if self.transform == Some(ValueTransform::Hms) { "HH:MM:SS times" } else { "numbers" };
+            eprintln!(
+                "Warning: {} values in {} are not {} and were skipped, use --na-values to mark missing values",
+                self.invalid, input_file, expected
+            );
+        }
+        let mismatched = self.zones.mismatched.get();
+        if mismatched > 0 {
+            eprintln!(
+                "Warning: {} zone labels in {} do not have the prefix '{}' and suffix '{}', skipping their rows",
+                mismatched, input_file, self.zones.prefix, self.zones.suffix
+            );
+        }
+        let unmapped = self.zones.unmapped.get();
+        if unmapped > 0 {
+            eprintln!(
+                "Warning: {} zone names in {} are not in the zone map, skipping their rows and columns",
+                unmapped, input_file
+            );
+        }
+    }
+}
+
+/// Turns the zone labels of an input CSV file into zone numbers, counting the labels
+/// that look like zones but do not have the zone prefix and suffix, and the names
+/// missing from the zone map.
+struct ZoneLabels {
+    lenient: bool,
+    prefix: String,
+    suffix: String,
+    names: HashMap<String, i32>,
+    strict: bool,
+    mismatched: std::cell::Cell<usize>,
+    unmapped: std::cell::Cell<usize>,
+    /// Where the rows skipped for their zone labels are logged, if anywhere.
+    rejects: Option<RejectLog>,
+    skipped_lines: u64,
+}
+
+impl ZoneLabels {
+    fn new(read_options: &ReadOptions) -> Self {
+        ZoneLabels {
+            lenient: read_options.lenient_zone_ids,
+            prefix: read_options.zone_prefix.clone(),
+            suffix: read_options.zone_suffix.clone(),
+            names: read_options.zone_map.clone(),
+            strict: read_options.strict,
+            mismatched: std::cell::Cell::new(0),
+            unmapped: std::cell::Cell::new(0),
+            rejects: None,
+            skipped_lines: read_options.skip_rows as u64,
+        }
+    }
+
+    /// Parses a zone label after removing the zone prefix and suffix, or `None` if it
+    /// is not a zone number. A label with digits but without the prefix and suffix is
+    /// counted, or an error when strict, while one without digits, such as in a header
+    /// row, is skipped quietly. With a zone map, a name in the map is its zone and any
+    /// other text is counted, or an error when strict.
+    fn parse(&self, text: &str) -> std::io::Result<Option<i32>> {
+        if let Some(&zone) = self.names.get(text.trim()) {
+            return Ok(Some(zone));
+        }
+        match text.strip_prefix(self.prefix.as_str()).and_then(|text| text.strip_suffix(self.suffix.as_str())) {
+            Some(number) => match parse_zone(number, self.lenient)? {
+                None if self.is_unmapped_name(text) => self.unmapped(text),
+                zone => Ok(zone),
+            },
+            None if text.bytes().any(|byte| byte.is_ascii_digit()) => {
+                if self.strict {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "The zone label {} does not have the prefix '{}' and suffix '{}'",
+                            text, self.prefix, self.suffix
+                        ),
+                    ));
+                }
+                self.mismatched.set(self.mismatched.get() + 1);
+                Ok(None)
+            }
+            None if self.is_unmapped_name(text) => self.unmapped(text),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks if a label that is not a zone number is a name missing from the zone
+    /// map, rather than a blank or a number such as a value.
+    fn is_unmapped_name(&self, text: &str) -> bool {
+        let text = text.trim();
+        !self.names.is_empty() && !text.is_empty() && text.parse::<f64>().is_err()
+    }
+
+    /// Counts a name missing from the zone map, or fails when strict.
+    fn unmapped(&self, text: &str) -> std::io::Result<Option<i32>> {
+        if self.strict {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("The zone name {} is not in the zone map", text),
+            ));
+        }
+        self.unmapped.set(self.unmapped.get() + 1);
+        Ok(None)
+    }
+
+    /// Parses the zone label in a field of a record like `parse`, giving an error the
+    /// line and position of the field. A missing field is not a zone number. A label
+    /// counted as mismatched or unmapped is logged as rejected.
+    fn parse_field(&self, record: &csv::StringRecord, field: usize) -> std::io::Result<Option<i32>> {
+        let skipped_before = self.mismatched.get() + self.unmapped.get();
+        let text = record.get(field).unwrap_or("");
+        let zone = self.parse(text).map_err(|e| -> std::io::Error {
+            Error::Parse {
+                line: record_line(record),
+                field: field + 1,
+                message: e.to_string(),
+            }
+            .into()
+        })?;
+        if let Some(rejects) = &self.rejects
+            && self.mismatched.get() + self.unmapped.get() > skipped_before
+        {
+            let detail = format!("the zone label '{}' in field {} is not a known zone", text, field + 1);
+            rejects.reject(record_line(record) + self.skipped_lines, RejectReason::UnknownZone, detail);
+        }
+        Ok(zone)
+    }
+}
+
+/// Reads a CSV file with a header row, computing each row's value with an expression
+/// over its columns. Rows where the expression is infinite or NaN, such as after a
+/// division by zero, are skipped so their cells take the fill value.
+fn read_expression_csv<R: Read, V: CellValue>(
+    input_file: &str,
+    rdr: &mut csv::Reader<R>,
+    expression: &str,
+    parser: &mut ValueParser,
+) -> std::io::Result<Vec<(i32, i32, V)>> {
+    let mut record = csv::StringRecord::new();
+    if !read_next_record(rdr, &mut record)? {
+        return Ok(Vec::new());
+    }
+    let mut value_expression = ValueExpression::parse(expression, &record)?;
+    let mut data = Vec::new();
+    let mut not_finite = 0;
+    while read_next_logged_record(rdr, &mut record, Some(parser))? {
+        let (Some(origin), Some(destination)) = (
+            parser.zones.parse_field(&record, 0)?,
+            parser.zones.parse_field(&record, 1)?,
+        ) else {
+            continue;
+        };
+        match value_expression.evaluate(&record)? {
+            Some(value) if value.is_finite() => {
+                data.push((origin, destination, parser.convert(value)));
+                parser.record_row(&record);
+            }
+            Some(_) => {
+                not_finite += 1;
+                parser.reject(&record, RejectReason::OutOfRange, || "the value expression is infinite or NaN".to_string());
+            }
+            None => parser.reject(&record, RejectReason::ParseError, || {
+                "a column of the value expression is not a number".to_string()
+            }),
+        }
+    }
+    if not_finite > 0 {
+        eprintln!(
+            "Warning: the value expression was infinite or NaN for {} rows of {}, leaving their cells as the fill value",
+            not_finite, input_file
+        );
+    }
+    Ok(data)
+}
+
+/// Reads the records of an opened CSV file, detecting its format from the first record
+/// unless the columns are given. Files without origin labels are always rectangular.
+fn read_csv_records<R: Read, V: CellValue>(
+    input_file: &str,
+    rdr: &mut csv::Reader<R>,
+    read_options: &ReadOptions,
+    parser: &mut ValueParser,
+) -> std::io::Result<Vec<(i32, i32, V)>> {
+    // A single record is reused for every row to avoid allocating per row
+    let mut record = csv::StringRecord::new();
+    
+    // Read the first record to determine the format
+    if read_next_record(rdr, &mut record)? {
+        let asked_columns = read_options.columns.is_none()
+            && read_options.interactive
+            && is_ambiguous_first_record(&record, read_options)
+            && ask_if_columns(input_file, &record, std::io::stdin().is_terminal(), &mut std::io::stdin().lock())?;
+        let columns = if asked_columns {
+            Some([0, 1, 2])
+        } else {
+            three_columns(rdr, &mut record, read_options)?
+        };
+        if let Some(columns) = columns {
+            // 3-column format - process this record and continue with the reader
+            let mut data = Vec::new();
+            let mut zero_rows = 0;
+            
+            loop {
+                if let Some(row) = parse_three_column_record::<V>(&record, columns, parser)? {
+                    let is_zero = row.2.to_f64() == 0.0;
+                    zero_rows += is_zero as usize;
+                    if !(is_zero && read_options.drop_zero_rows) {
+                        data.push(row);
+                        parser.record_row(&record);
+                    }
+                }
+                if !read_next_logged_record(rdr, &mut record, Some(parser))? {
+                    break;
+                }
+            }
+            report_zero_rows(input_file, zero_rows, read_options.drop_zero_rows);
+            
+            Ok(data)
+        } else {
+            // Rectangular format - pass the first record and the reader for the remaining rows
+            let (data, _) = read_rectangular_csv_from_records(&record, rdr, false, read_options, parser)?;
+            Ok(data)
+        }
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Reads an input CSV file like `read_csv`, but splits a rectangular file that stacks
+/// several matrices into blocks. A block ends at a blank line or a repeated header row,
+/// and every block shares the destinations of the first header row. A 3-column file
+/// is always a single block.
+///
+/// # Arguments
+/// * `input_file` - The path to the input CSV file.
+/// * `read_options` - The delimiter and how to handle truncated input.
+///
+/// # Returns
+/// The rows of every block, and the range of the rows belonging to each block.
+pub fn read_csv_blocks(
+    input_file: &str,
+    read_options: &ReadOptions,
+) -> std::io::Result<(Rows, Vec<Range<usize>>)> {
+    let (mut rdr, report) = open_csv(input_file, read_options)?;
+    let mut record = csv::StringRecord::new();
+    let mut parser = ValueParser::new(read_options);
+    let (data, block_starts) = if !read_next_record(&mut rdr, &mut record)? {
+        (Vec::new(), vec![0])
+    } else if (record.len() == 3 && !read_options.implicit_origins)
+        || read_options.value_expression.is_some()
+        || read_options.columns.is_some()
+    {
+        // Reread the file rather than duplicating the parsing of a single matrix
+        (read_csv(input_file, read_options)?, vec![0])
+    } else {
+        read_rectangular_csv_from_records(&record, &mut rdr, true, read_options, &mut parser)?
+    };
+    warn_if_truncated(input_file, &rdr, report.as_ref());
+    parser.finish(input_file)?;
+    let block_ends = block_starts.iter().skip(1).copied().chain([data.len()]);
+    let blocks = block_starts.iter().zip(block_ends).map(|(&start, end)| start..end).collect();
+    Ok((data, blocks))
+}
+
+/// Reads a long-format CSV file, such as `origin,destination,period,value`, grouping
+/// its rows by the value in a pivot column. A pivot column given by name is found in
+/// the header row. Unless `columns` is given, the origin, destination and value are
+/// the first three columns other than the pivot column.
+///
+/// # Arguments
+/// * `input_file` - The path to the input CSV file.
+/// * `pivot` - The column whose value each row is grouped by.
+/// * `read_options` - The delimiter and how to handle truncated input.
+///
+/// # Returns
+/// The rows of every group, and the pivot value and range of the rows of each group,
+/// in the order the pivot values first appear.
+pub fn read_csv_pivoted(
+    input_file: &str,
+    pivot: &ColumnRef,
+    read_options: &ReadOptions,
+) -> std::io::Result<(Rows, PivotGroups)> {
+    if read_options.value_expression.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "A pivot column cannot be combined with a value expression",
+        ));
+    }
+    let (mut rdr, report) = open_csv(input_file, read_options)?;
+    let mut record = csv::StringRecord::new();
+    if !read_next_record(&mut rdr, &mut record)? {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let has_header = read_options.has_header || matches!(pivot, ColumnRef::Name(_));
+    let header = has_header.then_some(&record);
+    let pivot_column = pivot.resolve(header)?;
+    let columns = match &read_options.columns {
+        Some(mapping) => mapping.resolve(header)?,
+        None => {
+            let mut others = (0..).filter(|&column| column != pivot_column);
+            [(); 3].map(|_| others.next().unwrap_or_default())
+        }
+    };
+    if has_header && !read_next_record(&mut rdr, &mut record)? {
+        record.clear();
+    }
+
+    let mut parser = ValueParser::new(read_options);
+    let mut groups: Vec<(String, Rows)> = Vec::new();
+    let mut group_indices = std::collections::HashMap::new();
+    let mut zero_rows = 0;
+    loop {
+        if let Some(pivot_value) = record.get(pivot_column)
+            && let Some(row) = parse_three_column_record::<f32>(&record, columns, &mut parser)?
+        {
+            let is_zero = row.2 == 0.0;
+            zero_rows += is_zero as usize;
+            if !(is_zero && read_options.drop_zero_rows) {
+                let index = *group_indices.entry(pivot_value.to_string()).or_insert_with(|| {
+                    groups.push((pivot_value.to_string(), Vec::new()));
+                    groups.len() - 1
+                });
+                groups[index].1.push(row);
+            }
+        }
+        if !read_next_record(&mut rdr, &mut record)? {
+            break;
+        }
+    }
+    warn_if_truncated(input_file, &rdr, report.as_ref());
+    parser.finish(input_file)?;
+    report_zero_rows(input_file, zero_rows, read_options.drop_zero_rows);
+
+    let mut data = Vec::with_capacity(groups.iter().map(|(_, rows)| rows.len()).sum());
+    let mut ranges = Vec::with_capacity(groups.len());
+    for (pivot_value, rows) in groups {
+        let start = data.len();
+        data.extend(rows);
+        ranges.push((pivot_value, start..data.len()));
+    }
+    Ok((data, ranges))
+}
+
+/// Streams the rows of a 3-column CSV file to `on_row` without buffering them, so
+/// files larger than memory can be processed.
+///
+/// # Arguments
+/// * `input_file` - The path to the input CSV file.
+/// * `read_options` - The delimiter and how to handle truncated input.
+/// * `on_row` - Called with the origin, destination, and value of each row.
+///
+/// # Returns
+/// `false`, without reading past the first record, if the file is not in the 3-column format.
+pub fn stream_three_column_csv(
+    input_file: &str,
+    read_options: &ReadOptions,
+    mut on_row: impl FnMut(i32, i32, f32),
+) -> std::io::Result<bool> {
+    let (mut rdr, report) = open_csv(input_file, read_options)?;
+    let mut record = csv::StringRecord::new();
+    if !read_next_record(&mut rdr, &mut record)? {
+        return Ok(true);
+    }
+    let Some(columns) = three_columns(&mut rdr, &mut record, read_options)? else {
+        return Ok(false);
+    };
+    let mut parser = ValueParser::new(read_options);
+    let mut zero_rows = 0;
+    loop {
+        if let Some((origin, destination, value)) = parse_three_column_record(&record, columns, &mut parser)? {
+            zero_rows += (value == 0.0) as usize;
+            if !(value == 0.0 && read_options.drop_zero_rows) {
+                on_row(origin, destination, value);
+            }
+        }
+        if !read_next_record(&mut rdr, &mut record)? {
+            warn_if_truncated(input_file, &rdr, report.as_ref());
+            parser.finish(input_file)?;
+            report_zero_rows(input_file, zero_rows, read_options.drop_zero_rows);
+            return Ok(true);
+        }
+    }
+}
+
+/// A CSV reader over a local or remote, possibly compressed, input file.
+type CsvReader = csv::Reader<Box<dyn Read>>;
+
+/// The size of a rectangular CSV file's matrix, found by reading only its header row.
+pub struct PeekedDimensions {
+    /// The number of destinations in the header row.
+    pub destinations: usize,
+    rdr: CsvReader,
+    implicit_origins: bool,
+    zones: ZoneLabels,
+}
+
+impl PeekedDimensions {
+    /// Counts the origins by reading the rest of the file, without parsing its values.
+    pub fn count_origins(mut self) -> std::io::Result<usize> {
+        let mut record = csv::ByteRecord::new();
+        let mut origins = 0;
+        while self.rdr.read_byte_record(&mut record)? {
+            if self.implicit_origins
+                || std::str::from_utf8(&record[0]).is_ok_and(|origin| {
+                    self.zones.parse(origin).is_ok_and(|origin| origin.is_some())
+                })
+            {
+                origins += 1;
+            }
+        }
+        Ok(origins)
+    }
+}
+
+/// Finds the size of a rectangular CSV file's matrix from its header row, without
+/// reading the rest of the file. The origins can then be counted if they are needed.
+///
+/// # Arguments
+/// * `input_file` - The path to the input CSV file.
+/// * `read_options` - The delimiter and how to handle truncated input.
+///
+/// # Returns
+/// The dimensions, or `None` if the file is empty or in the 3-column format, whose
+/// size is only known once every row has been read.
+pub fn peek_dimensions(input_file: &str, read_options: &ReadOptions) -> std::io::Result<Option<PeekedDimensions>> {
+    let (mut rdr, _) = open_csv(input_file, read_options)?;
+    let mut record = csv::StringRecord::new();
+    if !read_next_record(&mut rdr, &mut record)?
+        || (record.len() == 3 && !read_options.implicit_origins)
+        || read_options.columns.is_some()
+    {
+        return Ok(None);
+    }
+    let zones = ZoneLabels::new(read_options);
+    Ok(Some(PeekedDimensions {
+        destinations: parse_destinations(&record, read_options.implicit_origins, &zones)?.iter().flatten().count(),
+        rdr,
+        implicit_origins: read_options.implicit_origins,
+        zones,
+    }))
+}
+
+/// Opens a CSV file without treating the first row as a header.
+///
+/// # Returns
+/// The reader, and the report of any truncation when it is being tolerated.
+fn open_csv(
+    input_file: &str,
+    read_options: &ReadOptions,
+) -> std::io::Result<(CsvReader, Option<TruncationReport>)> {
+    let is_header = |line: &[u8]| is_header_line(line, read_options);
+    let (input, report) = open_input_parts(input_file, read_options.tolerate_truncation, &is_header)?;
+    let input = skip_lines(input, read_options.skip_rows)?;
+
+    // The csv reader places no limit on the number of fields in a record, so rectangular
+    // files with tens of thousands of destinations are read as a single record. Any of
+    // `\r\n`, `\n` or `\r` ends a record, and trimming the fields removes the spaces and
+    // stray carriage returns that would otherwise stop a value from parsing.
+    let rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(read_options.delimiter)
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    Ok((rdr, report))
+}
+
+/// Checks if the first line shared by the parts of a directory is a header row rather
+/// than a row of data, as the format detection of the first record would treat it:
+/// with `--columns` only `--header` makes it one, the first row of a 3-column file is
+/// data when its origin is a zone, and the first row of a rectangular file is always
+/// its header.
+fn is_header_line(line: &[u8], read_options: &ReadOptions) -> bool {
+    if read_options.value_expression.is_some() {
+        return true;
+    }
+    if read_options.columns.is_some() {
+        return read_options.has_header;
+    }
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(read_options.delimiter)
+        .trim(csv::Trim::All)
+        .from_reader(line);
+    let mut record = csv::StringRecord::new();
+    if !rdr.read_record(&mut record).unwrap_or(false) {
+        return false;
+    }
+    if record.len() != 3 || read_options.implicit_origins {
+        return true;
+    }
+    !ZoneLabels::new(read_options).parse(&record[0]).is_ok_and(|zone| zone.is_some())
+}
+
+/// Opens an input file, either locally or from a remote path, transparently
+/// decompressing it if it starts with the gzip magic bytes. A local directory is read
+/// as the concatenation of its CSV parts, as described for `PartsReader`.
+///
+/// # Arguments
+/// * `input_file` - The path to the input file, or to a directory of CSV parts.
+/// * `tolerate_truncation` - End a gzip stream at a decode error instead of failing,
+///   only passing on the complete lines before it.
+///
+/// # Returns
+/// The reader, and the report of any truncation when it is being tolerated.
+pub fn open_input(
+    input_file: &str,
+    tolerate_truncation: bool,
+) -> std::io::Result<(Box<dyn Read>, Option<TruncationReport>)> {
+    open_input_parts(input_file, tolerate_truncation, &|_| true)
+}
+
+/// Opens an input file like `open_input`, deciding with `is_header` whether the first
+/// line shared by the parts of a directory is a header row to leave out of the later
+/// parts.
+fn open_input_parts(
+    input_file: &str,
+    tolerate_truncation: bool,
+    is_header: &dyn Fn(&[u8]) -> bool,
+) -> std::io::Result<(Box<dyn Read>, Option<TruncationReport>)> {
+    let input: Box<dyn Read> = if is_remote_path(input_file) {
+        open_remote(input_file)?
+    } else if std::path::Path::new(input_file).is_dir() {
+        // Each part is decompressed as it is opened, so a truncated part ends the input
+        let parts = PartsReader::new(input_file, is_header)?;
+        if tolerate_truncation {
+            let (reader, report) = TolerantReader::new(parts, true);
+            return Ok((Box::new(reader), Some(report)));
+        }
+        return Ok((Box::new(parts), None));
+    } else {
+        Box::new(File::open(input_file)?)
+    };
+    let mut input = BufReader::new(input);
+    if !input.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        return Ok((Box::new(input), None));
+    }
+    let decoder = MultiGzDecoder::new(input);
+    if tolerate_truncation {
+        let (reader, report) = TolerantReader::new(decoder, true);
+        Ok((Box::new(reader), Some(report)))
+    } else {
+        Ok((Box::new(decoder), None))
+    }
+}
+
+/// Reads the `.csv` and `.csv.gz` files of a directory one after another as a single
+/// input, in the order of their names, as Spark writes `part-00000.csv` and so on.
+/// When every part starts with the same line and it is a header row rather than data,
+/// it is only kept from the first part. Empty parts are ignored, and each part is opened once the one
+/// before it has been read, so the parts need not all be open at once.
+struct PartsReader {
+    parts: std::vec::IntoIter<std::path::PathBuf>,
+    current: Option<Box<dyn BufRead>>,
+    /// Whether the parts after the first have their header line left out.
+    skip_headers: bool,
+    first_opened: bool,
+    /// Whether the last byte passed on ended a line, so that a part without a final
+    /// line ending is not joined to the first line of the next part.
+    at_line_start: bool,
+}
+
+impl PartsReader {
+    fn new(directory: &str, is_header: &dyn Fn(&[u8]) -> bool) -> std::io::Result<PartsReader> {
+        let mut parts: Vec<std::path::PathBuf> = std::fs::read_dir(directory)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        parts.retain(|path| {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+            (name.ends_with(".csv") || name.ends_with(".csv.gz")) && path.is_file()
+        });
+        parts.sort();
+        let mut first_lines = Vec::with_capacity(parts.len());
+        for part in &parts {
+            first_lines.push(Self::first_line(part)?);
+        }
+        let (parts, first_lines): (Vec<_>, Vec<_>) = parts
+            .into_iter()
+            .zip(first_lines)
+            .filter_map(|(part, line)| line.map(|line| (part, line)))
+            .unzip();
+        let Some(header) = first_lines.first() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("The directory {} has no .csv or .csv.gz files with any lines", directory),
+            ));
+        };
+        // A first row of data is kept in every part, even when the parts share it
+        let repeated = if is_header(header) {
+            first_lines.iter().skip(1).filter(|line| *line == header).count()
+        } else {
+            0
+        };
+        if repeated > 0 && repeated < first_lines.len() - 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Only {} of the {} other parts in {} start with the first line of {}, so the parts do not agree on having a header row",
+                    repeated,
+                    first_lines.len() - 1,
+                    directory,
+                    parts[0].display()
+                ),
+            ));
+        }
+        if repeated > 0 {
+            eprintln!(
+                "Skipping the header row repeated at the start of {} more parts of {}",
+                repeated, directory
+            );
+        }
+        Ok(PartsReader {
+            parts: parts.into_iter(),
+            current: None,
+            skip_headers: repeated > 0,
+            first_opened: false,
+            at_line_start: true,
+        })
+    }
+
+    /// Opens a part, decompressing it if it starts with the gzip magic bytes.
+    fn open(part: &std::path::Path) -> std::io::Result<Box<dyn BufRead>> {
+        let mut input = BufReader::new(File::open(part)?);
+        if input.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            Ok(Box::new(BufReader::new(MultiGzDecoder::new(input))))
+        } else {
+            Ok(Box::new(input))
+        }
+    }
+
+    /// Reads the first line of a part without its line ending, or `None` if it is empty.
+    fn first_line(part: &std::path::Path) -> std::io::Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        if Self::open(part)?.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        while line.last().is_some_and(|&byte| byte == b'\n' || byte == b'\r') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+}
+
+impl Read for PartsReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(current) = &mut self.current {
+                let read = current.read(buf)?;
+                if read > 0 {
+                    self.at_line_start = buf[read - 1] == b'\n';
+                    return Ok(read);
+                }
+                self.current = None;
+                if !self.at_line_start {
+                    self.at_line_start = true;
+                    buf[0] = b'\n';
+                    return Ok(1);
+                }
+            }
+            let Some(part) = self.parts.next() else {
+                return Ok(0);
+            };
+            let mut current = Self::open(&part)?;
+            if self.first_opened && self.skip_headers {
+                current.read_until(b'\n', &mut Vec::new())?;
+            }
+            self.first_opened = true;
+            self.current = Some(current);
+        }
+    }
+}
+
+/// Discards the first `lines` lines of the input, whatever they contain.
+fn skip_lines(input: Box<dyn Read>, lines: usize) -> std::io::Result<Box<dyn Read>> {
+    if lines == 0 {
+        return Ok(input);
+    }
+    let mut input = BufReader::new(input);
+    let mut line = Vec::new();
+    for _ in 0..lines {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+    }
+    Ok(Box::new(input))
+}
+
+/// Reports how many rows of a 3-column file have a value of exactly zero, which
+/// take a row's memory without changing the matrix unless the fill value is not zero.
+fn report_zero_rows(input_file: &str, zero_rows: usize, dropped: bool) {
+    if zero_rows == 0 {
+        return;
+    }
+    if dropped {
+        eprintln!("Skipped {} rows of {} with a value of exactly zero", zero_rows, input_file);
+    } else {
+        eprintln!(
+            "{} rows of {} have a value of exactly zero, use --drop-zero-rows to skip them",
+            zero_rows, input_file
+        );
+    }
+}
+
+/// Warns that the input was truncated and how much of it was recovered.
+fn warn_if_truncated<R: Read>(input_file: &str, rdr: &csv::Reader<R>, report: Option<&TruncationReport>) {
+    if let Some(recovered_bytes) = report.and_then(TruncationReport::recovered_bytes) {
+        eprintln!(
+            "Warning: {} is truncated, recovered {} records from the first {} bytes",
+            input_file,
+            rdr.position().record(),
+            recovered_bytes
+        );
+    }
+}
+
+/// Checks if the path refers to a file that needs to be downloaded.
+fn is_remote_path(input_file: &str) -> bool {
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|scheme| input_file.starts_with(scheme))
+}
+
+/// Streams a remote file over HTTP(S).
+#[cfg(feature = "remote")]
+fn open_remote(input_file: &str) -> std::io::Result<Box<dyn Read>> {
+    let url = match input_file.strip_prefix("s3://") {
+        Some(path) => s3_url(path, std::env::var("AWS_ENDPOINT_URL").ok().as_deref())?,
+        None => input_file.to_string(),
+    };
+    let response = ureq::get(&url).call().map_err(|e| {
+        std::io::Error::other(format!("Unable to download {}: {}", url, e))
+    })?;
+    Ok(Box::new(response.into_body().into_reader()))
+}
+
+#[cfg(not(feature = "remote"))]
+fn open_remote(input_file: &str) -> std::io::Result<Box<dyn Read>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("Reading {} requires csv_to_mtx to be built with the `remote` feature", input_file),
+    ))
+}
+
+/// Converts the `bucket/key` part of an `s3://` path into an HTTPS URL. The object
+/// is downloaded without signing the request, so it must be public or the endpoint
+/// must not require authentication. `AWS_ENDPOINT_URL` selects a custom endpoint,
+/// such as a MinIO server, using path-style addressing.
+#[cfg(feature = "remote")]
+fn s3_url(path: &str, endpoint: Option<&str>) -> std::io::Result<String> {
+    match path.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(match endpoint {
+            Some(endpoint) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), bucket, key),
+            None => format!("https://{}.s3.amazonaws.com/{}", bucket, key),
+        }),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("s3://{} is not of the form s3://bucket/key", path),
+        )),
+    }
+}
+
+/// Finds the columns of the origin, destination and value from the first record of
+/// a file, reading past it if it is a header row.
+///
+/// # Returns
+/// The positions of the columns, or `None` if the file is rectangular.
+fn three_columns<R: Read>(
+    rdr: &mut csv::Reader<R>,
+    record: &mut csv::StringRecord,
+    read_options: &ReadOptions,
+) -> std::io::Result<Option<[usize; 3]>> {
+    let Some(mapping) = &read_options.columns else {
+        let is_three_column = record.len() == 3 && !read_options.implicit_origins;
+        return Ok(is_three_column.then_some([0, 1, 2]));
+    };
+    let columns = mapping.resolve(read_options.has_header.then_some(&*record))?;
+    if read_options.has_header && !read_next_record(rdr, record)? {
+        // Leave nothing to parse when the file is only a header
+        record.clear();
+    }
+    Ok(Some(columns))
+}
+
+/// Whether the first record of a file could as well be the header row of a rectangular
+/// file with three zones as a row of a 3-column file with an extra column. The header
+/// row of a rectangular file usually starts with a blank or a label rather than a zone.
+fn is_ambiguous_first_record(record: &csv::StringRecord, read_options: &ReadOptions) -> bool {
+    let is_zone = |field: &str| parse_zone(field.trim(), read_options.lenient_zone_ids).is_ok_and(|zone| zone.is_some());
+    record.len() == 4 && !read_options.implicit_origins && is_zone(&record[0]) && is_zone(&record[1])
+}
+
+/// Asks whether an ambiguous first record is a row of a 3-column file or the header
+/// row of a rectangular file, for `--interactive`.
+///
+/// # Arguments
+/// * `input_file` - The path to the input CSV file, for the question.
+/// * `record` - The first record of the file.
+/// * `terminal` - Whether someone at a terminal can answer. Otherwise the ambiguity is
+///   an error explaining how to choose with the other options.
+/// * `answers` - Where the answer is read from, standard input outside of tests.
+///
+/// # Returns
+/// Whether to read the file as a 3-column file.
+fn ask_if_columns(
+    input_file: &str,
+    record: &csv::StringRecord,
+    terminal: bool,
+    answers: &mut impl BufRead,
+) -> std::io::Result<bool> {
+    let ambiguity = format!(
+        "The first row of {} ({}) could be the header row of a square CSV or a row of origin, destination and value with an extra column",
+        input_file,
+        record.iter().collect::<Vec<_>>().join(",")
+    );
+    if !terminal {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{}. Pass --columns origin=0,destination=1,value=2 to read it as columns, or leave out --interactive to read it as a square CSV",
+                ambiguity
+            ),
+        ));
+    }
+    loop {
+        eprint!("{}. Read it as a (s)quare CSV or as (c)olumns? ", ambiguity);
+        let mut answer = String::new();
+        if answers.read_line(&mut answer)? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("No answer was given to how {} should be read", input_file),
+            ));
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "s" | "square" => return Ok(false),
+            "c" | "columns" => return Ok(true),
+            _ => {}
+        }
+    }
+}
+
+/// Parses the origin, destination, and value from the given columns of a record, or
+/// `None` if any of them are missing or fail to parse.
+fn parse_three_column_record<V: CellValue>(
+    record: &csv::StringRecord,
+    [origin, destination, value_column]: [usize; 3],
+    parser: &mut ValueParser,
+) -> std::io::Result<Option<(i32, i32, V)>> {
+    let Some(value) = record.get(value_column) else {
+        return Ok(None);
+    };
+    // The names of a header row are not zone names missing from the zone map
+    let is_first_line = record.position().is_some_and(|position| position.line() == 1);
+    if !parser.zones.names.is_empty() && is_first_line && parser.number(value).is_none() {
+        return Ok(None);
+    }
+    // Only rows with zones, unlike a header row, have their value parsed
+    let (Some(origin), Some(destination)) = (
+        parser.zones.parse_field(record, origin)?,
+        parser.zones.parse_field(record, destination)?,
+    ) else {
+        return Ok(None);
+    };
+    let invalid_before = parser.invalid;
+    let value = parser.parse(value);
+    parser.reject_if_invalid(invalid_before, record, value_column);
+    Ok(value.map(|value| (origin, destination, value)))
+}
+
+/// The line a record starts on, counting from 1, or 0 if it is not known.
+fn record_line(record: &csv::StringRecord) -> u64 {
+    record.position().map_or(0, |position| position.line())
+}
+
+/// Parses a zone label, or `None` if it is not a zone number, such as in a header row.
+/// With `lenient`, an integral decimal such as `12.0` is also a zone number, but a
+/// label such as `12.5` is an error rather than being skipped.
+fn parse_zone(text: &str, lenient: bool) -> std::io::Result<Option<i32>> {
+    if let Ok(zone) = text.parse::<i32>() {
+        return Ok(Some(zone));
+    }
+    match text.parse::<f64>() {
+        Ok(zone) if lenient && zone.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(&zone) => {
+            Ok(Some(zone as i32))
+        }
+        Ok(_) if lenient => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("The zone label {} is not an integer", text),
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Reads the next record into `record`, reusing its allocation. Malformed records are
+/// skipped the same way as rows whose values fail to parse, but I/O errors are returned.
+///
+/// # Returns
+/// `false` once there are no more records.
+fn read_next_record<R: Read>(
+    rdr: &mut csv::Reader<R>,
+    record: &mut csv::StringRecord,
+) -> std::io::Result<bool> {
+    read_next_logged_record(rdr, record, None)
+}
+
+/// Reads the next record like `read_next_record`, logging the malformed records it
+/// skips with the parser's reject log.
+fn read_next_logged_record<R: Read>(
+    rdr: &mut csv::Reader<R>,
+    record: &mut csv::StringRecord,
+    parser: Option<&ValueParser>,
+) -> std::io::Result<bool> {
+    loop {
+        match rdr.read_record(record) {
+            Ok(has_record) => return Ok(has_record),
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                if let Some(parser) = parser {
+                    let line = e.position().map_or(0, |position| position.line());
+                    parser.reject_line(line, RejectReason::Malformed, || match e.kind() {
+                        csv::ErrorKind::UnequalLengths { expected_len, len, .. } => {
+                            format!("the record has {} fields rather than {}", len, expected_len)
+                        }
+                        csv::ErrorKind::Utf8 { .. } => "the record is not valid UTF-8".to_string(),
+                        _ => e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Parses the destination of each value column from the header row of a rectangular
+/// CSV file, or `None` for a column that is not labelled with a zone. Without origin
+/// labels every column is a destination, though a blank or text corner cell is still
+/// ignored.
+fn parse_destinations(
+    header_record: &csv::StringRecord,
+    implicit_origins: bool,
+    zones: &ZoneLabels,
+) -> std::io::Result<Vec<Option<i32>>> {
+    let mut destinations = Vec::with_capacity(header_record.len());
+    // Skip the first column (it's empty or contains a label)
+    for field in (if implicit_origins { 0 } else { 1 })..header_record.len() {
+        destinations.push(zones.parse_field(header_record, field)?);
+    }
+    Ok(destinations)
+}
+
+/// Reads a rectangular CSV from an already-started reader where the first row contains destinations
+/// and the first column contains origins.
+///
+/// # Arguments
+/// * `header_record` - The first record containing destinations
+/// * `rdr` - The reader positioned at the remaining CSV records
+/// * `split_blocks` - Start a new block at each blank line or repeated header row.
+/// * `read_options` - With `implicit_origins` the rows have no origin column, so the
+///   origin of the n-th row of each block is the n-th destination and only blank lines
+///   separate the blocks. `rect_orientation` says whether the header row lists the
+///   destinations, or the origins so that each row and its first column are a destination.
+/// * `parser` - Converts the values to `V`.
+///
+/// # Returns
+/// A vector of tuples `(i32, i32, V)` representing the origin, destination, and value,
+/// and the index in it where each block starts.
+fn read_rectangular_csv_from_records<R: Read, V: CellValue>(
+    header_record: &csv::StringRecord,
+    rdr: &mut csv::Reader<R>,
+    split_blocks: bool,
+    read_options: &ReadOptions,
+    parser: &mut ValueParser,
+) -> std::io::Result<(Rows<V>, Vec<usize>)> {
+    let implicit_origins = read_options.implicit_origins;
+    // A delimiter at the end of every row leaves a blank last column, which is stripped
+    // from the header and from each row, where it must also be blank
+    let trailing_blank = header_record.len() > 1 && header_record.get(header_record.len() - 1) == Some("");
+    let mut header_record = header_record.clone();
+    if trailing_blank {
+        header_record.truncate(header_record.len() - 1);
+    }
+    // Columns that are not labelled with a zone are skipped without moving the others
+    let columns = parse_destinations(&header_record, implicit_origins, &parser.zones)?;
+    let destinations: Vec<i32> = columns.iter().flatten().copied().collect();
+    let first_value_column = if implicit_origins { 0 } else { 1 };
+    let mut block_starts = vec![0];
+    
+    if destinations.is_empty() {
+        return Ok((Vec::new(), block_starts));
+    }
+    
+    let mut data = Vec::new();
+    let mut record = csv::StringRecord::with_capacity(header_record.as_slice().len(), header_record.len());
+    let mut rows_in_block = 0;
+    let mut row_zones = std::collections::BTreeSet::new();
+    let mut repeated_header = false;
+    
+    // Process each subsequent row
+    while read_next_logged_record(rdr, &mut record, Some(parser))? {
+        if trailing_blank && record.len() > header_record.len() {
+            let last = record.len() - 1;
+            if !record[last].is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Line {} has the value {} in the last column, whose header is blank. If the header row is missing its blank first cell, its destinations are misaligned with the values",
+                        record_line(&record),
+                        &record[last]
+                    ),
+                ));
+            }
+            record.truncate(last);
+        }
+        // A line identical to the header, as left by concatenating exports, is never a
+        // row, so it does not reach the zone labels where it could count as unmapped
+        let is_header = !implicit_origins
+            && (record == header_record
+                || split_blocks
+                    && !parse_zone(&record[0], parser.zones.lenient).is_ok_and(|origin| origin.is_some())
+                    && parse_destinations(&record, false, &parser.zones)? == columns);
+        if split_blocks {
+            // The csv reader skips blank lines, but a record's position is where the reader
+            // started looking for it, so a skipped line makes the record span several lines
+            let start_line = record.position().map_or(0, |position| position.line());
+            let after_blank_line = rdr.position().line() > start_line + 1;
+            if (after_blank_line || is_header) && rows_in_block > 0 {
+                block_starts.push(data.len());
+                rows_in_block = 0;
+            }
+        } else if is_header && !repeated_header {
+            repeated_header = true;
+            eprintln!(
+                "Warning: line {} repeats the header row and is skipped. Use --multi-block to write each block it starts as its own matrix",
+                record_line(&record)
+            );
+        }
+        if is_header {
+            continue;
+        }
+        // Parse the origin from the first column, or take it from the row's position
+        let origin = if implicit_origins {
+            match destinations.get(rows_in_block) {
+                Some(&origin) => Some(origin),
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Row {} has no destination in the header to take its origin from, as there are only {} destinations",
+                            rows_in_block + 1,
+                            destinations.len()
+                        ),
+                    ));
+                }
+            }
+        } else {
+            parser.zones.parse_field(&record, 0)?
+        };
+        if let Some(origin) = origin {
+            rows_in_block += 1;
+            if read_options.require_square {
+                row_zones.insert(origin);
+            }
+            // Grow the data once per row rather than once per value
+            data.reserve(destinations.len());
+            // Process each value in the row (skip the origin column)
+            for (col_idx, value_str) in record.iter().skip(first_value_column).enumerate() {
+                let Some(&Some(destination)) = columns.get(col_idx) else {
+                    continue;
+                };
+                let invalid_before = parser.invalid;
+                let value = parser.parse_cell::<V>(value_str);
+                parser.reject_if_invalid(invalid_before, &record, first_value_column + col_idx);
+                if let Some(value) = value
+                    && (value.to_f64() != 0.0 || read_options.keep_zero_cells)
+                {
+                    // Swap the zones back when the header lists the origins
+                    match read_options.rect_orientation {
+                        RectOrientation::DestHeader => data.push((origin, destination, value)),
+                        RectOrientation::OriginHeader => data.push((destination, origin, value)),
+                    }
+                    parser.record_row(&record);
+                }
+            }
+        }
+    }
+    
+    if read_options.require_square {
+        check_square(&row_zones, &destinations)?;
+    }
+    Ok((data, block_starts))
+}
+
+/// Checks that the rows of a rectangular file are labelled with the same zones as its
+/// header, which fails when an export is missing some of its rows.
+fn check_square(row_zones: &std::collections::BTreeSet<i32>, header_zones: &[i32]) -> std::io::Result<()> {
+    let header_zones: std::collections::BTreeSet<i32> = header_zones.iter().copied().collect();
+    if *row_zones == header_zones {
+        return Ok(());
+    }
+    let join = |zones: std::collections::btree_set::Difference<i32>| {
+        zones.map(i32::to_string).collect::<Vec<_>>().join(",")
+    };
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!(
+            "The input is not square. Zones in the header without a row: [{}]. Rows whose zone is not in the header: [{}]",
+            join(header_zones.difference(row_zones)),
+            join(row_zones.difference(&header_zones)),
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_interactive_format() -> std::io::Result<()> {
+        let record = csv::StringRecord::from(vec!["1", "2", "0.5", "7"]);
+        let read_options = ReadOptions {
+            interactive: true,
+            ..ReadOptions::default()
+        };
+        assert!(is_ambiguous_first_record(&record, &read_options));
+        assert!(!is_ambiguous_first_record(&csv::StringRecord::from(vec!["", "1", "2", "3"]), &read_options));
+
+        // Without a terminal nothing is read and the error says how to choose
+        let e = ask_if_columns("od.csv", &record, false, &mut std::io::Cursor::new("c\n")).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(
+            e.to_string(),
+            "The first row of od.csv (1,2,0.5,7) could be the header row of a square CSV or a row of origin, \
+             destination and value with an extra column. Pass --columns origin=0,destination=1,value=2 to read \
+             it as columns, or leave out --interactive to read it as a square CSV"
+        );
+
+        // An answer that is neither is asked again
+        assert!(ask_if_columns("od.csv", &record, true, &mut std::io::Cursor::new("x\nColumns\n"))?);
+        assert!(!ask_if_columns("od.csv", &record, true, &mut std::io::Cursor::new("s\n"))?);
+        let e = ask_if_columns("od.csv", &record, true, &mut std::io::Cursor::new("")).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wide_rectangular_rows() -> std::io::Result<()> {
+        const COLUMNS: i32 = 10_000;
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_wide_rows.csv");
+        let mut writer = std::io::BufWriter::new(File::create(&input_file)?);
+        let header: Vec<String> = (1..=COLUMNS).map(|zone| zone.to_string()).collect();
+        writeln!(writer, ",{}", header.join(","))?;
+        for origin in 1..=3 {
+            let row: Vec<String> = (1..=COLUMNS).map(|destination| (origin * destination).to_string()).collect();
+            writeln!(writer, "{},{}", origin, row.join(","))?;
+        }
+        drop(writer);
+
+        let data = read_csv(input_file.to_str().unwrap(), &ReadOptions::default())?;
+        assert_eq!(data.len(), 3 * COLUMNS as usize);
+        assert_eq!(data[0], (1, 1, 1.0));
+        assert_eq!(data.last(), Some(&(3, COLUMNS, (3 * COLUMNS) as f32)));
+        Ok(())
+    }
+
+    /// Compresses the test CSV file with gzip.
+    fn gzipped_test_csv() -> std::io::Result<Vec<u8>> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&std::fs::read("test/test.csv")?)?;
+        encoder.finish()
+    }
+
+    #[test]
+    fn test_gzipped_input() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_gzipped_input.csv.gz");
+        std::fs::write(&input_file, gzipped_test_csv()?)?;
+        assert_eq!(read_csv(input_file.to_str().unwrap(), &ReadOptions::default())?, read_csv("test/test.csv", &ReadOptions::default())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_gzipped_input() -> std::io::Result<()> {
+        let rows: Vec<(i32, i32, f32)> = (0..5000).map(|i| (i / 100 + 1, i % 100 + 1, i as f32)).collect();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        for (origin, destination, value) in &rows {
+            writeln!(encoder, "{},{},{}", origin, destination, value)?;
+        }
+        let compressed = encoder.finish()?;
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_truncated_input.csv.gz");
+        std::fs::write(&input_file, &compressed[..compressed.len() / 2])?;
+        let input_file = input_file.to_str().unwrap();
+
+        assert!(read_csv(input_file, &ReadOptions::default()).is_err());
+        let tolerant = ReadOptions {
+            tolerate_truncation: true,
+            ..ReadOptions::default()
+        };
+        let recovered = read_csv(input_file, &tolerant)?;
+        assert!(!recovered.is_empty() && recovered.len() < rows.len());
+        assert_eq!(recovered[..], rows[..recovered.len()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_expression() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_value_expression.csv");
+        std::fs::write(&input_file, "origin,destination,distance,time\n1,2,10,4\n2,1,9,3\n2,2,5,0\n")?;
+        let read_options = ReadOptions {
+            value_expression: Some("distance/time".to_string()),
+            ..ReadOptions::default()
+        };
+        assert_eq!(read_csv(input_file.to_str().unwrap(), &read_options)?, vec![(1, 2, 2.5), (2, 1, 3.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_weights() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_value_weights.csv");
+        std::fs::write(&input_file, "origin,destination,auto,transit\n1,1,10,20\n1,2,5,0\n2,1,,3\n2,2,1,1\n")?;
+        let options = crate::options::ConversionOptions {
+            value_weights: Some("auto=0.6,transit=0.4".to_string()),
+            ..crate::options::ConversionOptions::default()
+        };
+        let data = read_csv(input_file.to_str().unwrap(), &options.read_options()?)?;
+        // 10 * 0.6 + 20 * 0.4, 5 * 0.6 + 0 * 0.4 and 1 * 0.6 + 1 * 0.4, with the row missing its auto value skipped
+        assert_eq!(data.iter().map(|&(o, d, _)| (o, d)).collect::<Vec<_>>(), vec![(1, 1), (1, 2), (2, 2)]);
+        for (&(_, _, value), expected) in data.iter().zip([14.0, 3.0, 1.0]) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+
+        let both = crate::options::ConversionOptions {
+            value_expr: Some("auto".to_string()),
+            ..options
+        };
+        assert!(both.read_options().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stacked_blocks() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_stacked_blocks.csv");
+        let contents = ",1,2,3\n1,1,2,0\n2,3,4,0\n\n1,5,6,0\n2,7,8,0\n,1,2,3\n1,9,0,0\n";
+        std::fs::write(&input_file, contents)?;
+        let (data, blocks) = read_csv_blocks(input_file.to_str().unwrap(), &ReadOptions::default())?;
+        assert_eq!(blocks, vec![0..4, 4..8, 8..9]);
+        assert_eq!(data[blocks[1].clone()], [(1, 1, 5.0), (1, 2, 6.0), (2, 1, 7.0), (2, 2, 8.0)]);
+        assert_eq!(data[blocks[2].clone()], [(1, 1, 9.0)]);
+
+        // Without splitting, the same rows are read as a single block
+        assert_eq!(read_csv(input_file.to_str().unwrap(), &ReadOptions::default())?, data);
+        let (data, blocks) = read_csv_blocks("test/test.csv", &ReadOptions::default())?;
+        assert_eq!(blocks, vec![0..data.len()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_implicit_origins() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_implicit_origins.csv");
+        std::fs::write(&input_file, "10,20,30\n1,2,0\n0,5,6\n7,0,9\n")?;
+        let read_options = ReadOptions {
+            implicit_origins: true,
+            ..ReadOptions::default()
+        };
+        let data = read_csv(input_file.to_str().unwrap(), &read_options)?;
+        assert_eq!(
+            data,
+            vec![(10, 10, 1.0), (10, 20, 2.0), (20, 20, 5.0), (20, 30, 6.0), (30, 10, 7.0), (30, 30, 9.0)]
+        );
+        let dimensions = peek_dimensions(input_file.to_str().unwrap(), &read_options)?.unwrap();
+        assert_eq!(dimensions.destinations, 3);
+        assert_eq!(dimensions.count_origins()?, 3);
+
+        // A row past the last destination has no origin
+        std::fs::write(&input_file, "1,2\n1,2\n3,4\n5,6\n")?;
+        let error = read_csv(input_file.to_str().unwrap(), &read_options).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_zero_rows() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_zero_rows.csv");
+        std::fs::write(&input_file, "1,1,0\n1,2,2.5\n2,1,0.0\n2,2,-0\n")?;
+        let data = read_csv(input_file.to_str().unwrap(), &ReadOptions::default())?;
+        assert_eq!(data.len(), 4);
+        let read_options = ReadOptions {
+            drop_zero_rows: true,
+            ..ReadOptions::default()
+        };
+        assert_eq!(read_csv(input_file.to_str().unwrap(), &read_options)?, vec![(1, 2, 2.5)]);
+
+        let mut streamed = Vec::new();
+        stream_three_column_csv(input_file.to_str().unwrap(), &read_options, |origin, destination, value| {
+            streamed.push((origin, destination, value))
+        })?;
+        assert_eq!(streamed, vec![(1, 2, 2.5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_mapping() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_column_mapping.csv");
+        std::fs::write(&input_file, "id,time,value,dest,origin,flag\n1,7,2.5,20,10,x\n2,8,4.0,10,30,y\n")?;
+        let read_options = ReadOptions {
+            columns: Some(ColumnMapping::parse("origin=4,destination=3,value=2")?),
+            has_header: true,
+            ..ReadOptions::default()
+        };
+        let expected = vec![(10, 20, 2.5), (30, 10, 4.0)];
+        assert_eq!(read_csv(input_file.to_str().unwrap(), &read_options)?, expected);
+        let read_options = ReadOptions {
+            columns: Some(ColumnMapping::parse("origin=origin,destination=dest,value=value")?),
+            ..read_options
+        };
+        assert_eq!(read_csv(input_file.to_str().unwrap(), &read_options)?, expected);
+        let mut streamed = Vec::new();
+        stream_three_column_csv(input_file.to_str().unwrap(), &read_options, |origin, destination, value| {
+            streamed.push((origin, destination, value))
+        })?;
+        assert_eq!(streamed, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_origin_header() -> std::io::Result<()> {
+        let read_options = ReadOptions {
+            rect_orientation: RectOrientation::OriginHeader,
+            ..ReadOptions::default()
+        };
+        let mut data = read_csv("test/test_rectangular.csv", &read_options)?;
+        data.sort_by_key(|&(origin, destination, _)| (origin, destination));
+        let mut expected: Vec<_> = read_csv("test/test_rectangular.csv", &ReadOptions::default())?
+            .into_iter()
+            .map(|(origin, destination, value)| (destination, origin, value))
+            .collect();
+        expected.sort_by_key(|&(origin, destination, _)| (origin, destination));
+        assert_eq!(data, expected);
+
+        // Origins 1 and 2 across the header, destination 5 in the first column
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_origin_header.csv");
+        std::fs::write(&input_file, ",1,2,3\n5,0.5,1.5,0\n")?;
+        let data = read_csv(input_file.to_str().unwrap(), &read_options)?;
+        assert_eq!(data, vec![(1, 5, 0.5), (2, 5, 1.5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_na_values() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_na_values.csv");
+        let read_options = ReadOptions {
+            na_values: vec!["NA".to_string(), "-".to_string()],
+            ..ReadOptions::default()
+        };
+        std::fs::write(&input_file, "1,1,0.5\n1,2,NA\n2,1,-\n2,2,bad\n")?;
+        let (mut rdr, _) = open_csv(input_file.to_str().unwrap(), &read_options)?;
+        let mut parser = ValueParser::new(&read_options);
+        let data = read_csv_records::<_, f32>("", &mut rdr, &read_options, &mut parser)?;
+        assert_eq!(data, vec![(1, 1, 0.5)]);
+        // Only the value that is neither a number nor missing is invalid
+        assert_eq!(parser.invalid, 1);
+
+        std::fs::write(&input_file, ",1,2,3\n1,0.5,NA,\n2,-,1.5,x\n")?;
+        let (mut rdr, _) = open_csv(input_file.to_str().unwrap(), &read_options)?;
+        let mut parser = ValueParser::new(&read_options);
+        let data = read_csv_records::<_, f32>("", &mut rdr, &read_options, &mut parser)?;
+        assert_eq!(data, vec![(1, 1, 0.5), (2, 2, 1.5)]);
+        assert_eq!(parser.invalid, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lenient_zone_ids() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_lenient_zone_ids.csv");
+        let input = input_file.to_str().unwrap();
+        let lenient = ReadOptions {
+            lenient_zone_ids: true,
+            ..ReadOptions::default()
+        };
+        std::fs::write(&input_file, "origin,destination,value\n12.0,3,0.5\n3.0,12.00,1.5\n")?;
+        assert!(read_csv(input, &ReadOptions::default())?.is_empty());
+        assert_eq!(read_csv(input, &lenient)?, vec![(12, 3, 0.5), (3, 12, 1.5)]);
+
+        std::fs::write(&input_file, ",3.0,12.0,7\n12.0,0.5,1.5,0\n3,1,0,0\n")?;
+        assert_eq!(read_csv(input, &lenient)?, vec![(12, 3, 0.5), (12, 12, 1.5), (3, 3, 1.0)]);
+
+        // A label that is not a whole number is an error rather than a skipped row
+        std::fs::write(&input_file, "12.5,3,0.5\n")?;
+        assert!(read_csv(input, &lenient).is_err());
+        assert!(read_csv(input, &ReadOptions::default())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error_position() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_parse_error.csv");
+        let input = input_file.to_str().unwrap();
+        let lenient = ReadOptions {
+            lenient_zone_ids: true,
+            ..ReadOptions::default()
+        };
+        std::fs::write(&input_file, "origin,destination,value\n1,2,0.5\n2,1,1.5\n3,4.5,2.0\n")?;
+        let error = Error::from(read_csv(input, &lenient).unwrap_err());
+        assert!(matches!(error, Error::Parse { line: 4, field: 2, .. }), "{:?}", error);
+        assert_eq!(error.exit_code(), 65);
+
+        // The position of a rectangular header's zone is its column
+        std::fs::write(&input_file, ",1,2.5,3\n1,0.5,1.5,0\n")?;
+        let error = Error::from(read_csv(input, &lenient).unwrap_err());
+        assert!(matches!(error, Error::Parse { line: 1, field: 3, .. }), "{:?}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zone_prefix() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_zone_prefix.csv");
+        let input = input_file.to_str().unwrap();
+        let prefixed = ReadOptions {
+            zone_prefix: "Z".to_string(),
+            ..ReadOptions::default()
+        };
+        std::fs::write(&input_file, "origin,destination,value\nZ00123,Z7,0.5\n00123,Z7,1.5\n")?;
+        // The row without the prefix is skipped with a warning
+        assert_eq!(read_csv(input, &prefixed)?, vec![(123, 7, 0.5)]);
+        let strict = ReadOptions {
+            strict: true,
+            ..prefixed.clone()
+        };
+        assert!(read_csv(input, &strict).is_err());
+
+        std::fs::write(&input_file, ",Z1_a,Z2_a,Z3_a\nZ1_a,0.5,1.5,0\n")?;
+        let affixed = ReadOptions {
+            zone_suffix: "_a".to_string(),
+            ..prefixed
+        };
+        assert_eq!(read_csv(input, &affixed)?, vec![(1, 1, 0.5), (1, 2, 1.5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeated_header() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_repeated_header.csv");
+        let input = input_file.to_str().unwrap();
+        std::fs::write(&input_file, "zone,A,B,C\nA,1,2,0\nB,3,4,0\nC,0,0,1\nzone,A,B,C\nA,5,6,0\n")?;
+        // The corner cell of the repeated header is not a zone name, which is an error
+        // when strict if the line is taken as a row
+        let named = ReadOptions {
+            zone_map: HashMap::from([("A".to_string(), 1), ("B".to_string(), 2), ("C".to_string(), 3)]),
+            strict: true,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            read_csv(input, &named)?,
+            vec![(1, 1, 1.0), (1, 2, 2.0), (2, 1, 3.0), (2, 2, 4.0), (3, 3, 1.0), (1, 1, 5.0), (1, 2, 6.0)]
+        );
+        let (data, blocks) = read_csv_blocks(input, &named)?;
+        assert_eq!(data.len(), 7);
+        assert_eq!(blocks, vec![0..5, 5..7]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zone_map() -> std::io::Result<()> {
+        let map_file = std::env::temp_dir().join("csv_to_mtx_test_zone_map_names.csv");
+        std::fs::write(&map_file, "name,zone\nDowntown,1\nAirport,2\nHarbour,3\n")?;
+        let zone_map = crate::zones::read_zone_map(&crate::zones::ZonesFile::new(map_file.to_str().unwrap()))?;
+        assert_eq!(zone_map.len(), 3);
+        let named = ReadOptions {
+            zone_map,
+            ..ReadOptions::default()
+        };
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_zone_map.csv");
+        let input = input_file.to_str().unwrap();
+        std::fs::write(
+            &input_file,
+            ",Downtown,Airport,Harbour\nDowntown,0,1.5,2\nAirport,3,0,4\nHarbour,5,6,0\n",
+        )?;
+        assert_eq!(
+            read_csv(input, &named)?,
+            vec![(1, 2, 1.5), (1, 3, 2.0), (2, 1, 3.0), (2, 3, 4.0), (3, 1, 5.0), (3, 2, 6.0)]
+        );
+
+        // An unmapped column is skipped without moving the columns after it
+        std::fs::write(&input_file, ",Downtown,Suburb,Harbour\nDowntown,1,2,3\nSuburb,4,5,6\n")?;
+        assert_eq!(read_csv(input, &named)?, vec![(1, 1, 1.0), (1, 3, 3.0)]);
+        let strict = ReadOptions {
+            strict: true,
+            ..named.clone()
+        };
+        assert!(read_csv(input, &strict).is_err());
+
+        // The header row of a 3-column file is not taken for unmapped names
+        std::fs::write(&input_file, "origin,destination,value\nAirport,Downtown,2.5\n7,Harbour,1\n")?;
+        assert_eq!(read_csv(input, &strict)?, vec![(2, 1, 2.5), (7, 3, 1.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_transform() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_value_transform.csv");
+        std::fs::write(&input_file, "1\t1\t3:30\n1\t2\t0:45\n")?;
+        let read_options = ReadOptions {
+            delimiter: b'\t',
+            value_transform: Some(ValueTransform::MinutesColon),
+            ..ReadOptions::default()
+        };
+        let data = read_csv(input_file.to_str().unwrap(), &read_options)?;
+        assert_eq!(data, vec![(1, 1, 3.5), (1, 2, 0.75)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hms_values() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_hms_values.csv");
+        let input = input_file.to_str().unwrap();
+        std::fs::write(&input_file, "1,1,01:30:00\n1,2,00:00:45\n2,1,1:30\n")?;
+        let minutes = ReadOptions {
+            value_transform: Some(ValueTransform::Hms),
+            ..ReadOptions::default()
+        };
+        assert_eq!(read_csv(input, &minutes)?, vec![(1, 1, 90.0), (1, 2, 0.75)]);
+        let seconds = ReadOptions {
+            time_unit: TimeUnit::Seconds,
+            ..minutes.clone()
+        };
+        assert_eq!(read_csv(input, &seconds)?, vec![(1, 1, 5400.0), (1, 2, 45.0)]);
+
+        // The malformed time is skipped with a warning, or is an error when strict
+        let strict = ReadOptions { strict: true, ..minutes };
+        assert!(read_csv(input, &strict).unwrap_err().to_string().contains("'1:30'"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cell_split() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_cell_split.csv");
+        let input = input_file.to_str().unwrap();
+        std::fs::write(&input_file, ",1,2,3\n1,3|4,2,\n2,0|5,1|x,0\n")?;
+        let split = |cell_aggregate| ReadOptions {
+            cell_split: Some("|".to_string()),
+            cell_aggregate,
+            ..ReadOptions::default()
+        };
+        assert_eq!(read_csv(input, &split(CellAggregate::Sum))?, vec![(1, 1, 7.0), (1, 2, 2.0), (2, 1, 5.0)]);
+        assert_eq!(read_csv(input, &split(CellAggregate::Second))?, vec![(1, 1, 4.0), (2, 1, 5.0)]);
+        assert_eq!(read_csv(input, &split(CellAggregate::Max))?, vec![(1, 1, 4.0), (1, 2, 2.0), (2, 1, 5.0)]);
+        // Without splitting, the composite cells are not numbers
+        assert_eq!(read_csv(input, &ReadOptions::default())?, vec![(1, 2, 2.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_rows() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_skip_rows.csv");
+        std::fs::write(&input_file, "Trip table, AM peak\nExported 2024-01-01\n1,1,0.5\n1,2,1.5\n")?;
+        let input = input_file.to_str().unwrap();
+        // The first line's three fields are taken for a rectangular header without skipping
+        assert_ne!(read_csv(input, &ReadOptions::default())?, vec![(1, 1, 0.5), (1, 2, 1.5)]);
+        let read_options = ReadOptions {
+            skip_rows: 2,
+            ..ReadOptions::default()
+        };
+        assert_eq!(read_csv(input, &read_options)?, vec![(1, 1, 0.5), (1, 2, 1.5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_require_square() -> std::io::Result<()> {
+        let read_options = ReadOptions {
+            require_square: true,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            read_csv("test/test_rectangular.csv", &read_options)?,
+            read_csv("test/test_rectangular.csv", &ReadOptions::default())?
+        );
+
+        // The row of zone 2 is missing, and a row of zeros still counts as present
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_require_square.csv");
+        std::fs::write(&input_file, ",1,2,3\n1,0.5,1.5,0\n3,0,0,0\n")?;
+        let input = input_file.to_str().unwrap();
+        assert_eq!(read_csv(input, &ReadOptions::default())?.len(), 2);
+        let error = read_csv(input, &read_options).unwrap_err();
+        assert!(error.to_string().contains("without a row: [2]"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_line_endings() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_line_endings.csv");
+        std::fs::write(&input_file, "1,1,0.5\r\n1,2, 1.5 \n2,1,\"2.5\r\"\r\n2,2,3.5\r")?;
+        let data = read_csv(input_file.to_str().unwrap(), &ReadOptions::default())?;
+        assert_eq!(data, vec![(1, 1, 0.5), (1, 2, 1.5), (2, 1, 2.5), (2, 2, 3.5)]);
+
+        std::fs::write(&input_file, ",1,2,3\r\n1,0.5,1.5,0\n2,2.5,3.5,0\r\n")?;
+        let data = read_csv(input_file.to_str().unwrap(), &ReadOptions::default())?;
+        assert_eq!(data, vec![(1, 1, 0.5), (1, 2, 1.5), (2, 1, 2.5), (2, 2, 3.5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_blank_column() -> std::io::Result<()> {
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_trailing_blank.csv");
+        std::fs::write(&input_file, ",1,2,3,\n1,0.1,0.2,0.3,\n2,0.4,0.5,0.6,\n3,0.7,0.8,0.9,\n")?;
+        let expected = read_csv("test/test_rectangular.csv", &ReadOptions::default())?;
+        assert_eq!(read_csv(input_file.to_str().unwrap(), &ReadOptions::default())?, expected);
+        let (data, blocks) = read_csv_blocks(input_file.to_str().unwrap(), &ReadOptions::default())?;
+        assert_eq!(data, expected);
+        assert_eq!(blocks.len(), 1);
+
+        // A header missing its blank first cell puts a value under the blank last column
+        std::fs::write(&input_file, "1,2,3,\n1,0.1,0.2,0.3\n2,0.4,0.5,0.6\n")?;
+        let e = read_csv(input_file.to_str().unwrap(), &ReadOptions::default()).unwrap_err();
+        assert!(e.to_string().contains("misaligned"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_peek_dimensions() -> std::io::Result<()> {
+        let dimensions = peek_dimensions("test/test_rectangular.csv", &ReadOptions::default())?.unwrap();
+        assert_eq!(dimensions.destinations, 3);
+        assert_eq!(dimensions.count_origins()?, 3);
+        assert!(peek_dimensions("test/test.csv", &ReadOptions::default())?.is_none());
+
+        // The body of this file is cut short, which is only noticed when it is read
+        const COLUMNS: usize = 500;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let header: Vec<String> = (1..=COLUMNS).map(|zone| zone.to_string()).collect();
+        writeln!(encoder, ",{}", header.join(","))?;
+        for origin in 1..=1000 {
+            let row: Vec<String> = (1..=COLUMNS).map(|destination| ((origin * destination * 7919) % 100_003).to_string()).collect();
+            writeln!(encoder, "{},{}", origin, row.join(","))?;
+        }
+        let compressed = encoder.finish()?;
+        let input_file = std::env::temp_dir().join("csv_to_mtx_test_peek_dimensions.csv.gz");
+        std::fs::write(&input_file, &compressed[..compressed.len() / 2])?;
+        let dimensions = peek_dimensions(input_file.to_str().unwrap(), &ReadOptions::default())?.unwrap();
+        assert_eq!(dimensions.destinations, COLUMNS);
+        assert!(dimensions.count_origins().is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_remote_input() -> std::io::Result<()> {
+        use std::io::BufRead;
+        use std::net::TcpListener;
+
+        // A minimal HTTP server answering two requests, the second with a gzipped body
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let bodies = vec![std::fs::read("test/test.csv")?, gzipped_test_csv()?];
+        let server = std::thread::spawn(move || -> std::io::Result<()> {
+            for body in bodies {
+                let (mut stream, _) = listener.accept()?;
+                let mut request = BufReader::new(stream.try_clone()?);
+                let mut line = String::new();
+                while request.read_line(&mut line)? > 2 {
+                    line.clear();
+                }
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len())?;
+                stream.write_all(&body)?;
+            }
+            Ok(())
+        });
+
+        let expected = read_csv("test/test.csv", &ReadOptions::default())?;
+        let url = format!("http://{}/test.csv", address);
+        assert_eq!(read_csv(&url, &ReadOptions::default())?, expected);
+        assert_eq!(read_csv(&url, &ReadOptions::default())?, expected);
+        server.join().unwrap()?;
+
+        assert_eq!(s3_url("bucket/dir/od.csv", None)?, "https://bucket.s3.amazonaws.com/dir/od.csv");
+        assert_eq!(s3_url("bucket/od.csv", Some("http://localhost:9000/"))?, "http://localhost:9000/bucket/od.csv");
+        assert!(s3_url("bucket", None).is_err());
+        Ok(())
+    }
+}
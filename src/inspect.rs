@@ -0,0 +1,143 @@
+use crate::mtx::{read_mtx_cells, read_mtx_file, MtxMatrix, MtxReadOptions};
+use crate::pack::{is_pack_file, read_packed_matrices};
+
+/// Reads an MTX file and prints its dimensions along with a summary of its values. A
+/// container written by `pack` has a summary printed for each of its matrices.
+///
+/// # Arguments
+/// * `input_file` - The path to the MTX file to inspect.
+/// * `read_options` - The byte order of the file and how to handle truncation.
+pub fn inspect_mtx_file(input_file: &str, read_options: &MtxReadOptions) -> std::io::Result<()> {
+    if is_pack_file(input_file)? {
+        let matrices = read_packed_matrices(input_file, read_options)?;
+        println!("Container: {} ({} matrices)", input_file, matrices.len());
+        for (name, matrix) in &matrices {
+            println!();
+            print_summary(&format!("{}:{}", input_file, name), matrix);
+        }
+        return Ok(());
+    }
+    let matrix = read_mtx_file(input_file, read_options)?;
+    print_summary(input_file, &matrix);
+    Ok(())
+}
+
+/// Prints the top-left `size` by `size` corner of an MTX file as a rectangular CSV,
+/// reading only those cells rather than the whole matrix.
+pub fn print_mtx_head(input_file: &str, size: usize, read_options: &MtxReadOptions) -> std::io::Result<()> {
+    let (origins, destinations, values) = read_mtx_head(input_file, size, read_options)?;
+    let header: Vec<String> = destinations.iter().map(|zone| zone.to_string()).collect();
+    println!(",{}", header.join(","));
+    for (origin, row) in origins.iter().zip(values.chunks(destinations.len().max(1))) {
+        let row: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+        println!("{},{}", origin, row.join(","));
+    }
+    Ok(())
+}
+
+/// Prints the value of a single cell of an MTX file, reading only that cell.
+pub fn print_mtx_cell(input_file: &str, origin: i32, destination: i32, read_options: &MtxReadOptions) -> std::io::Result<()> {
+    let value = read_mtx_cell(input_file, origin, destination, read_options)?;
+    println!("Cell {},{}: {}", origin, destination, value);
+    Ok(())
+}
+
+/// Reads the top-left corner of an MTX file, at most `size` origins by `size` destinations.
+///
+/// # Returns
+/// The origins and destinations of the corner, and its values in row-major order.
+pub fn read_mtx_head(
+    input_file: &str,
+    size: usize,
+    read_options: &MtxReadOptions,
+) -> std::io::Result<(Vec<i32>, Vec<i32>, Vec<f32>)> {
+    let (mut rows, mut columns) = (0, 0);
+    let (mut origins, mut destinations, values) = read_mtx_cells(input_file, read_options, |origins, destinations| {
+        rows = size.min(origins.len());
+        columns = size.min(destinations.len());
+        Ok((0..rows)
+            .flat_map(|row| (0..columns).map(move |column| row * destinations.len() + column))
+            .collect())
+    })?;
+    origins.truncate(rows);
+    destinations.truncate(columns);
+    Ok((origins, destinations, values))
+}
+
+/// Reads the value of the cell of an MTX file from `origin` to `destination`.
+pub fn read_mtx_cell(input_file: &str, origin: i32, destination: i32, read_options: &MtxReadOptions) -> std::io::Result<f32> {
+    let (_, _, values) = read_mtx_cells(input_file, read_options, |origins, destinations| {
+        let position = |zones: &[i32], zone: i32, kind: &str| {
+            zones.iter().position(|&other| other == zone).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{} has no {} zone {}", input_file, kind, zone),
+                )
+            })
+        };
+        let row = position(origins, origin, "origin")?;
+        let column = position(destinations, destination, "destination")?;
+        Ok(vec![row * destinations.len() + column])
+    })?;
+    Ok(values[0])
+}
+
+fn print_summary(input_file: &str, matrix: &MtxMatrix) {
+    println!("File: {}", input_file);
+    println!("Origins: {}{}", matrix.origins.len(), zone_range(&matrix.origins));
+    println!("Destinations: {}{}", matrix.destinations.len(), zone_range(&matrix.destinations));
+    let non_zero = matrix.values.iter().filter(|&&value| value != 0.0).count();
+    println!("Non-zero cells: {} of {}", non_zero, matrix.values.len());
+    if !matrix.values.is_empty() {
+        let sum: f64 = matrix.values.iter().map(|&value| value as f64).sum();
+        let min = matrix.values.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = matrix.values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        println!("Sum: {}", sum);
+        println!("Min: {}", min);
+        println!("Max: {}", max);
+    }
+    if let Some(metadata) = &matrix.metadata {
+        println!("Metadata:");
+        for line in metadata.lines() {
+            println!("  {}", line);
+        }
+    }
+}
+
+/// Formats the first and last zone of an index array, if there are any.
+fn zone_range(zones: &[i32]) -> String {
+    match (zones.first(), zones.last()) {
+        (Some(first), Some(last)) => format!(" ({}..{})", first, last),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_mtx_cell() -> std::io::Result<()> {
+        let matrix = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        let (row, column) = (matrix.origins.len() - 1, 1);
+        let expected = matrix.values[row * matrix.destinations.len() + column];
+        let (origin, destination) = (matrix.origins[row], matrix.destinations[column]);
+        assert_eq!(read_mtx_cell("test/test_expected.mtx", origin, destination, &MtxReadOptions::default())?, expected);
+
+        // A gzipped copy is streamed to the cell instead
+        let gzipped = std::env::temp_dir().join("csv_to_mtx_test_read_cell.mtx.gz");
+        let gzipped = gzipped.to_str().unwrap();
+        crate::mtx::write_mtx_file(gzipped, &matrix.origins, &matrix.destinations, &matrix.values, &Default::default())?;
+        assert_eq!(read_mtx_cell(gzipped, origin, destination, &MtxReadOptions::default())?, expected);
+
+        let e = read_mtx_cell("test/test_expected.mtx", origin, i32::MAX, &MtxReadOptions::default()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+
+        let (origins, destinations, values) = read_mtx_head("test/test_expected.mtx", 2, &MtxReadOptions::default())?;
+        assert_eq!(origins, matrix.origins[..2]);
+        assert_eq!(destinations, matrix.destinations[..2]);
+        let width = matrix.destinations.len();
+        assert_eq!(values, vec![matrix.values[0], matrix.values[1], matrix.values[width], matrix.values[width + 1]]);
+        Ok(())
+    }
+}
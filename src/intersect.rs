@@ -0,0 +1,111 @@
+use std::collections::BTreeSet;
+
+use crate::input::{read_csv, ReadOptions};
+use crate::mtx::{read_mtx_zones, MtxReadOptions};
+use crate::zones::get_all_zones;
+
+/// How the zone systems of several inputs overlap, for planning how to combine them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneSetReport {
+    /// The zones found in every input.
+    pub intersection: Vec<i32>,
+    /// The zones found in any input.
+    pub union: Vec<i32>,
+    /// Each input with the zones found only in it.
+    pub unique: Vec<(String, Vec<i32>)>,
+}
+
+/// Finds the zones of each input and how they overlap. MTX files, recognised by their
+/// `.mtx` or `.mtx.gz` extension, only have their header read, and the zones of a CSV
+/// file are its origins and destinations.
+///
+/// # Arguments
+/// * `input_files` - The CSV and MTX files to compare.
+/// * `read_options` - How the CSV files are read.
+/// * `mtx_read_options` - How the MTX files are read.
+pub fn zone_set_report(
+    input_files: &[String],
+    read_options: &ReadOptions,
+    mtx_read_options: &MtxReadOptions,
+) -> std::io::Result<ZoneSetReport> {
+    let zone_sets = input_files
+        .iter()
+        .map(|input_file| {
+            let zones: BTreeSet<i32> = if input_file.ends_with(".mtx") || input_file.ends_with(".mtx.gz") {
+                let (origins, destinations) = read_mtx_zones(input_file, mtx_read_options)?;
+                origins.into_iter().chain(destinations).collect()
+            } else {
+                get_all_zones(None, &read_csv(input_file, read_options)?)?.into_iter().collect()
+            };
+            Ok(zones)
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let union: BTreeSet<i32> = zone_sets.iter().flatten().copied().collect();
+    let intersection = union
+        .iter()
+        .filter(|zone| zone_sets.iter().all(|zones| zones.contains(zone)))
+        .copied()
+        .collect();
+    let unique = input_files
+        .iter()
+        .zip(&zone_sets)
+        .enumerate()
+        .map(|(index, (input_file, zones))| {
+            let only_here = zones
+                .iter()
+                .filter(|zone| {
+                    zone_sets
+                        .iter()
+                        .enumerate()
+                        .all(|(other, other_zones)| other == index || !other_zones.contains(zone))
+                })
+                .copied()
+                .collect();
+            (input_file.clone(), only_here)
+        })
+        .collect();
+    Ok(ZoneSetReport {
+        intersection,
+        union: union.into_iter().collect(),
+        unique,
+    })
+}
+
+/// Prints the report, one set of zones per line.
+pub fn print_zone_set_report(report: &ZoneSetReport) {
+    println!("Intersection ({} zones): {}", report.intersection.len(), format_zones(&report.intersection));
+    println!("Union ({} zones): {}", report.union.len(), format_zones(&report.union));
+    for (input_file, zones) in &report.unique {
+        println!("Only in {} ({} zones): {}", input_file, zones.len(), format_zones(zones));
+    }
+}
+
+fn format_zones(zones: &[i32]) -> String {
+    zones.iter().map(i32::to_string).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zone_set_report() -> std::io::Result<()> {
+        // test_expected.mtx uses zones 1 to 4
+        let first = std::env::temp_dir().join("csv_to_mtx_test_intersect_first.csv");
+        std::fs::write(&first, "1,2,1.0\n2,5,1.0\n")?;
+        let second = std::env::temp_dir().join("csv_to_mtx_test_intersect_second.csv");
+        std::fs::write(&second, "2,1,1.0\n6,6,1.0\n")?;
+        let input_files = vec![
+            first.to_str().unwrap().to_string(),
+            second.to_str().unwrap().to_string(),
+            "test/test_expected.mtx".to_string(),
+        ];
+        let report = zone_set_report(&input_files, &ReadOptions::default(), &MtxReadOptions::default())?;
+        assert_eq!(report.intersection, vec![1, 2]);
+        assert_eq!(report.union, vec![1, 2, 3, 4, 5, 6]);
+        let unique: Vec<&[i32]> = report.unique.iter().map(|(_, zones)| zones.as_slice()).collect();
+        assert_eq!(unique, vec![&[5][..], &[6][..], &[3, 4][..]]);
+        Ok(())
+    }
+}
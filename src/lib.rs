@@ -0,0 +1,35 @@
+//! Converts origin-destination CSV files into the binary MTX matrix format, and
+//! inspects, compares, transposes, exports and validates MTX files, generates synthetic inputs and
+//! checks that the build round-trips its own files. The `csv_to_mtx` binary is a thin
+//! command-line front end over these modules.
+
+pub mod columns;
+pub mod convert;
+pub mod csr;
+pub mod csv_output;
+pub mod diff;
+pub mod error;
+pub mod explain;
+pub mod export;
+pub mod expression;
+pub mod generate;
+pub mod input;
+pub mod intersect;
+pub mod inspect;
+pub mod low_memory;
+pub mod marginals;
+pub mod matrix;
+pub mod memory;
+pub mod mtx;
+pub mod options;
+pub mod pack;
+#[cfg(feature = "parquet")]
+pub mod parquet_output;
+pub mod quantiles;
+pub mod rejects;
+pub mod selftest;
+pub mod transpose;
+pub mod truncation;
+pub mod validate;
+pub mod value_transform;
+pub mod zones;
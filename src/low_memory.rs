@@ -0,0 +1,93 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::input::{stream_three_column_csv, ReadOptions};
+use crate::matrix::CellRules;
+use crate::zones::{check_zone_limit, resolve_zones, ZonesFile};
+
+/// Builds the matrix for a 3-column CSV file by accumulating each row directly into the
+/// dense matrix, never holding all of the rows in memory. The input is streamed twice:
+/// once to find its zones, which is skipped when a zones file replaces them, and once
+/// to fill in the matrix.
+///
+/// # Arguments
+/// * `input_file` - The path to the input CSV file.
+/// * `read_options` - The delimiter and how to handle truncated input.
+/// * `zones_file` - Optional zones CSV file.
+/// * `rules` - The fill value and limits for the cells.
+/// * `max_zones` - The most zones allowed, checked before the matrix is allocated.
+///
+/// # Returns
+/// The sorted zones and the flattened matrix, or `None` if the input is not in the
+/// 3-column format.
+pub fn build_matrix_low_memory(
+    input_file: &str,
+    read_options: &ReadOptions,
+    zones_file: Option<&ZonesFile>,
+    rules: &CellRules,
+    max_zones: Option<usize>,
+) -> std::io::Result<Option<(Vec<i32>, Vec<f32>)>> {
+    let mut is_three_column = true;
+    let all_zones = resolve_zones(zones_file, || {
+        let mut zones = HashSet::new();
+        is_three_column = stream_three_column_csv(input_file, read_options, |origin, destination, _| {
+            zones.insert(origin);
+            zones.insert(destination);
+        })?;
+        Ok(zones)
+    })?;
+    if !is_three_column {
+        return Ok(None);
+    }
+    check_zone_limit(&all_zones, max_zones)?;
+
+    let zone_count = all_zones.len();
+    let zone_index: HashMap<i32, usize> = all_zones
+        .iter()
+        .enumerate()
+        .map(|(i, &zone)| (zone, i))
+        .collect();
+    let mut matrix = vec![rules.fill; zone_count * zone_count];
+    let is_three_column = stream_three_column_csv(input_file, read_options, |origin, destination, value| {
+        if let (Some(&origin_idx), Some(&destination_idx)) =
+            (zone_index.get(&origin), zone_index.get(&destination))
+        {
+            matrix[origin_idx * zone_count + destination_idx] = rules.apply(value);
+        }
+    })?;
+    Ok(is_three_column.then_some((all_zones, matrix)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::read_csv;
+    use crate::matrix::build_matrix;
+    use crate::zones::get_all_zones;
+
+    #[test]
+    fn test_matches_in_memory_matrix() -> std::io::Result<()> {
+        let zones_file = ZonesFile::new("test/test_zones.csv");
+        for zones_file in [Some(&zones_file), None] {
+            let data = read_csv("test/test.csv", &ReadOptions::default())?;
+            let all_zones = get_all_zones(zones_file, &data)?;
+            let rules = CellRules {
+                fill: -1.0,
+                min_value: Some(0.2),
+                max_value: None,
+            };
+            let matrix = build_matrix(&data, &all_zones, &rules);
+
+            let low_memory = build_matrix_low_memory("test/test.csv", &ReadOptions::default(), zones_file, &rules, None)?;
+            assert_eq!(low_memory, Some((all_zones, matrix)));
+            assert!(build_matrix_low_memory("test/test.csv", &ReadOptions::default(), zones_file, &rules, Some(3)).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rectangular_input_is_not_streamed() -> std::io::Result<()> {
+        let low_memory = build_matrix_low_memory("test/test_rectangular.csv", &ReadOptions::default(), None, &CellRules::default(), None)?;
+        assert_eq!(low_memory, None);
+        Ok(())
+    }
+}
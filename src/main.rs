@@ -1,321 +1,121 @@
 use std::env;
-use std::collections::HashSet;
-use std::io::{BufWriter, Write};
-use std::fs::File;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use rayon::prelude::*; // For parallel processing
-
-/// The main function parses command-line arguments, processes the input CSV file,
-/// optionally uses a zones CSV file, and writes the output in MTX format.
-fn main() -> std::io::Result<()> {
-    let arg: Vec<String> = env::args().collect();
-
-    if arg.len() < 3 {
-        println!("Usage: csv_to_mtx <input.csv> <output.mtx/.mtx.gz> [zones.csv]");
-        return Ok(());
-    }
-
-    let zones_file = if arg.len() > 3 {
-        Some(&arg[3] as &str)
-    } else {
-        None
+use std::process::ExitCode;
+
+mod cli;
+
+use cli::{Cli, Command};
+use csv_to_mtx::error::Error;
+use csv_to_mtx::{convert, diff, export, generate, inspect, intersect, marginals, pack, selftest, transpose, validate};
+
+/// The main function parses command-line arguments and runs the requested subcommand.
+/// Running without a subcommand, `csv_to_mtx <input.csv> <output.mtx> [zones.csv]`,
+/// converts the input CSV file as before. A failure exits with the code of its kind
+/// of error, such as 65 for invalid input data.
+fn main() -> ExitCode {
+    let cli = match Cli::parse_with_legacy(env::args()) {
+        Ok(cli) => cli,
+        Err(e) => e.exit(),
     };
-    convert_csv_to_mtx(&arg[1], &arg[2], zones_file)
-}
-
-/// Converts the input CSV file to MTX format and writes it to the output file.
-/// 
-fn convert_csv_to_mtx(
-    input_file: &str,
-    output_file: &str,
-    zones_file: Option<&str>,
-) -> std::io::Result<()> {
-    let data = match read_csv(input_file) {
-        Ok(data) => data,
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
-            eprintln!("Error reading CSV file: {}", e);
-            return Err(e);
+            let error = Error::from(e);
+            eprintln!("Error: {}", error);
+            ExitCode::from(error.exit_code())
         }
-    };
-    let all_zones = match get_all_zones(zones_file, &data) {
-        Ok(zones) => zones,
-        Err(e) => {
-            eprintln!("Error reading zones file: {}", e);
-            return Err(e);
-        }
-    };
-    println!("Found {} zones", all_zones.len());
-    let matrix = build_matrix(&data, &all_zones);
-    match write_mtx_file(output_file, &all_zones, &matrix) {
-        Err(e) => {
-            eprintln!("Error writing MTX file: {}", e);
-            Err(e)
-        }
-        _ => Ok(()),
     }
 }
 
-/// Reads the input CSV file and extracts the data as a vector of tuples containing
-/// origin, destination, and value. Automatically detects the CSV format:
-/// - 3-column format: origin, destination, value
-/// - Rectangular format: first row contains destinations, first column contains origins
-///
-/// # Arguments
-/// * `input_file` - The path to the input CSV file.
-///
-/// # Returns
-/// A vector of tuples `(i32, i32, f32)` representing the origin, destination, and value.
-fn read_csv(input_file: &str) -> std::io::Result<Vec<(i32, i32, f32)>> {
-    let file = File::open(input_file)?;
-
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(file);
-    
-    let mut records = rdr.records();
-    
-    // Read the first record to determine the format
-    if let Some(Ok(first_record)) = records.next() {
-        if first_record.len() == 3 {
-            // 3-column format - process this record and continue with the iterator
-            let mut data = Vec::new();
-            
-            // Process the first record we already read
-            if let (Ok(origin), Ok(destination), Ok(value)) = (
-                first_record[0].parse::<i32>(),
-                first_record[1].parse::<i32>(),
-                first_record[2].parse::<f32>()
-            ) {
-                data.push((origin, destination, value));
-            }
-            
-            // Process remaining records
-            for record in records.filter_map(Result::ok) {
-                if let (Ok(origin), Ok(destination), Ok(value)) = (
-                    record[0].parse::<i32>(),
-                    record[1].parse::<i32>(),
-                    record[2].parse::<f32>()
-                ) {
-                    data.push((origin, destination, value));
+/// Runs a subcommand.
+fn run(command: Command) -> std::io::Result<()> {
+    match command {
+        Command::Convert(args) => {
+            let options = match args.options() {
+                Ok(options) => options,
+                Err(e) => {
+                    eprintln!("Error reading options: {}", e);
+                    return Err(e);
                 }
+            };
+            convert::convert_csv_to_mtx(&args.input, &args.output, &options)
+        }
+        Command::Inspect(args) => match (args.head, args.cell.as_slice()) {
+            (Some(size), _) => inspect::print_mtx_head(&args.input, size, &args.read.read_options()),
+            (None, &[origin, destination]) => {
+                inspect::print_mtx_cell(&args.input, origin, destination, &args.read.read_options())
             }
-            
-            Ok(data)
-        } else {
-            // Rectangular format - pass the first record and remaining iterator
-            Ok(read_rectangular_csv_from_records(first_record, records))
+            (None, []) => inspect::inspect_mtx_file(&args.input, &args.read.read_options()),
+            (None, _) => {
+                let e = std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--cell takes an origin and a destination, such as 12,34",
+                );
+                eprintln!("Error reading options: {}", e);
+                Err(e)
+            }
+        },
+        Command::Diff(args) => diff::diff_mtx_files(
+            &args.first,
+            &args.second,
+            args.output.as_deref(),
+            args.tolerance,
+            &args.read.read_options(),
+        )
+        .map(|_| ()),
+        Command::Transpose(args) => {
+            transpose::transpose_mtx_file(&args.input, &args.output, &args.read.read_options())
         }
-    } else {
-        Ok(Vec::new())
-    }
-}
-
-/// Reads a rectangular CSV from an already-started records iterator where the first row contains destinations
-/// and the first column contains origins.
-///
-/// # Arguments
-/// * `header_record` - The first record containing destinations
-/// * `records` - Iterator over remaining CSV records
-///
-/// # Returns
-/// A vector of tuples `(i32, i32, f32)` representing the origin, destination, and value.
-fn read_rectangular_csv_from_records(
-    header_record: csv::StringRecord,
-    records: csv::StringRecordsIter<std::fs::File>
-) -> Vec<(i32, i32, f32)> {
-    // Parse the header row to get destinations
-    let destinations: Vec<i32> = header_record.iter()
-        .skip(1) // Skip the first column (it's empty or contains a label)
-        .filter_map(|s| s.parse().ok())
-        .collect();
-    
-    if destinations.is_empty() {
-        return Vec::new();
-    }
-    
-    let mut data = Vec::new();
-    
-    // Process each subsequent row
-    for record in records.filter_map(Result::ok) {
-        // Parse the origin from the first column
-        if let Ok(origin) = record[0].parse::<i32>() {
-            // Process each value in the row (skip first column)
-            for (col_idx, value_str) in record.iter().skip(1).enumerate() {
-                if col_idx < destinations.len() && 
-                   let Ok(value) = value_str.parse::<f32>() && 
-                   value != 0.0 {
-                    data.push((origin, destinations[col_idx], value));
-                }
+        Command::Marginals(args) => marginals::marginals_mtx_file(
+            &args.input,
+            &args.productions,
+            &args.attractions,
+            args.float_format,
+            &args.read.read_options(),
+        ),
+        Command::Export(args) => export::export_mtx_file(&args.input, &args.options(), &args.read.read_options()),
+        Command::BatchConvert(args) => {
+            let (converted, failed) = export::batch_convert(
+                &args.directory,
+                args.to,
+                args.float_format,
+                &args.read.read_options(),
+                args.strict,
+            )?;
+            println!("Converted {} files", converted);
+            if failed > 0 {
+                eprintln!("{} files could not be converted", failed);
+                std::process::exit(1);
             }
+            Ok(())
         }
-    }
-    
-    data
-}
-
-/// Determines the complete list of zones either from the optional zones CSV file
-/// or by extracting unique origins and destinations from the input data.
-///
-/// # Arguments
-/// * `zones_file` - Optional path to the zones CSV file.
-/// * `data` - The vector of tuples `(i32, i32, f32)` representing the input data.
-///
-/// # Returns
-/// A sorted vector of unique zone numbers.
-fn get_all_zones(zones_file: Option<&str>, data: &[(i32, i32, f32)]) -> std::io::Result<Vec<i32>> {
-    if let Some(zone_file) = zones_file {
-        let zone_file = File::open(zone_file)?;
-        let mut zone_rdr = csv::Reader::from_reader(zone_file);
-        let mut zones: Vec<i32> = zone_rdr
-            .records()
-            .filter_map(|result| result.ok()?.get(0)?.parse().ok())
-            .collect();
-        zones.sort_unstable();
-        Ok(zones)
-    } else {
-        let zones: HashSet<i32> = data
-            .par_iter()
-            .flat_map(|(origin, destination, _)| vec![*origin, *destination])
-            .collect();
-        let mut zones: Vec<i32> = zones.into_iter().collect();
-        zones.sort_unstable();
-        Ok(zones)
-    }
-}
-
-/// Builds a matrix of size `|origin| * |destination|` where each cell contains
-/// the value corresponding to the origin and destination pair.
-///
-/// # Arguments
-/// * `data` - The vector of tuples `(i32, i32, f32)` representing the input data.
-/// * `all_zones` - The sorted vector of unique zone numbers.
-///
-/// # Returns
-/// A vector of `f32` representing the flattened matrix.
-fn build_matrix(data: &[(i32, i32, f32)], all_zones: &[i32]) -> Vec<f32> {
-    let zone_count = all_zones.len();
-    let zone_index: std::collections::HashMap<i32, usize> = all_zones
-        .iter()
-        .enumerate()
-        .map(|(i, &zone)| (zone, i))
-        .collect();
-
-    let mut matrix = vec![0.0f32; zone_count * zone_count];
-    for (origin, destination, value) in data {
-        if let (Some(&origin_idx), Some(&destination_idx)) =
-            (zone_index.get(origin), zone_index.get(destination))
-        {
-            matrix[origin_idx * zone_count + destination_idx] = *value;
+        Command::Pack(args) => pack::pack_mtx_files(&args.inputs, &args.output, &args.read.read_options(), args.fsync),
+        Command::Unpack(args) => {
+            for output_file in pack::unpack_mtx_files(&args.input, &args.output_directory)? {
+                println!("Wrote {}", output_file);
+            }
+            Ok(())
         }
-    }
-    matrix
-}
-
-enum WriterType {
-    Plain(BufWriter<File>),
-    Gzip(BufWriter<GzEncoder<File>>),
-}
-
-impl Write for WriterType {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self {
-            WriterType::Plain(writer) => writer.write(buf),
-            WriterType::Gzip(writer) => writer.write(buf),
+        Command::ValidateZones(args) => {
+            if !validate::validate_zones(&args.zones_file()?, &args.against, &args.read.read_options())? {
+                std::process::exit(1);
+            }
+            Ok(())
         }
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        match self {
-            WriterType::Plain(writer) => writer.flush(),
-            WriterType::Gzip(writer) => writer.flush(),
+        Command::ZonesIntersect(args) => {
+            let report = intersect::zone_set_report(&args.inputs, &args.csv_read_options()?, &args.read.read_options())?;
+            intersect::print_zone_set_report(&report);
+            Ok(())
         }
-    }
-    
-    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
-        match self {
-            WriterType::Plain(writer) => writer.write_all(buf),
-            WriterType::Gzip(writer) => writer.write_all(buf),
+        Command::Generate(args) => {
+            let cells = generate::generate_csv(&args.output, &args.options())?;
+            println!("Wrote {} non-zero cells to {}", cells, args.output);
+            Ok(())
+        }
+        Command::Selftest(args) => {
+            if !selftest::run_self_test(args.seed())? {
+                std::process::exit(1);
+            }
+            Ok(())
         }
-    }
-}
-
-/// Writes the MTX file in the specified format. If the output file name ends with `.gz`,
-/// the file is written as a gzip-compressed file.
-///
-/// # Arguments
-/// * `output_file_name` - The path to the output MTX file.
-/// * `all_zones` - The sorted vector of unique zone numbers.
-/// * `matrix` - The flattened matrix of values.
-///
-/// # Panics
-/// This function will panic if it fails to create or write to the output file.
-fn write_mtx_file(output_file_name: &str, all_zones: &[i32], matrix: &[f32]) -> std::io::Result<()> {
-    let output_file = File::create(output_file_name)?;
-    let mut writer: WriterType = if output_file_name.ends_with(".gz") {
-        WriterType::Gzip(BufWriter::new(GzEncoder::new(output_file, Compression::default())))
-    } else {
-        WriterType::Plain(BufWriter::new(output_file))
-    };
-
-    let zone_count = all_zones.len() as i32;
-
-    writer.write_all(&0xC4D4F1B2u32.to_le_bytes())?; // Magic Number
-    writer.write_all(&1i32.to_le_bytes())?; // Version Number
-    writer.write_all(&1i32.to_le_bytes())?; // Type
-    writer.write_all(&2i32.to_le_bytes())?; // Dimensions
-    writer.write_all(&zone_count.to_le_bytes())?; // Index size for origin
-    writer.write_all(&zone_count.to_le_bytes())?; // Index size for destination
-
-    let is_little_endian = cfg!(target_endian = "little");
-
-    if is_little_endian {
-        // Write all origin zone numbers in a single call 
-        let origin_zone_bytes: &[u8] = bytemuck::cast_slice(all_zones);
-        writer.write_all(origin_zone_bytes)?; // Zone Numbers for Origin
-
-        // Write all destination zone numbers in a single call
-        writer.write_all(origin_zone_bytes)?; // Zone Numbers for Destination
-
-        // Write all matrix values in a single call
-        let matrix_bytes: &[u8] = bytemuck::cast_slice(matrix);
-        writer.write_all(matrix_bytes)?;
-
-    } else {
-        // Convert all_zones to little-endian
-        let origin_zone_bytes: Vec<u8> = all_zones
-            .par_iter()
-            .flat_map(|&zone| zone.to_le_bytes())
-            .collect();
-        writer.write_all(&origin_zone_bytes)?; // Zone Numbers for Origin
-        writer.write_all(&origin_zone_bytes)?; // Zone Numbers for Destination
-
-        // Convert matrix to little-endian
-        let matrix_bytes: Vec<u8> = matrix
-            .par_iter()
-            .flat_map(|&value| value.to_le_bytes())
-            .collect();
-        writer.write_all(&matrix_bytes)?;
-    }
-    Ok(())
-}
-
-// Write a test using test.csv to make sure that it converts to an mtx file
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test_csv_to_mtx() -> std::io::Result<()> {
-        let input_file = "test/test.csv";
-        let output_file = "test/test_output.mtx";
-
-        convert_csv_to_mtx(input_file, output_file, None)?;
-
-        // Compare against a known good output file
-        let expected_output_file = "test/test_expected.mtx";
-        let output_data = std::fs::read(output_file)?;
-        let expected_data = std::fs::read(expected_output_file)?;
-        assert_eq!(output_data, expected_data);
-        Ok(())
     }
 }
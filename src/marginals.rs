@@ -0,0 +1,59 @@
+use crate::csv_output::{write_zone_values, FloatFormat};
+use crate::matrix::{column_sums, row_sums};
+use crate::mtx::{read_mtx_file, MtxReadOptions};
+
+/// Reads an MTX file and writes its row sums, the productions of each origin, and its
+/// column sums, the attractions of each destination, as `zone,total` CSV files.
+///
+/// # Arguments
+/// * `input_file` - The path to the MTX file to sum.
+/// * `productions_file` - The path to write the row sums to.
+/// * `attractions_file` - The path to write the column sums to.
+/// * `float_format` - How the totals are written.
+/// * `read_options` - The byte order of the input file and how to handle truncation.
+pub fn marginals_mtx_file(
+    input_file: &str,
+    productions_file: &str,
+    attractions_file: &str,
+    float_format: FloatFormat,
+    read_options: &MtxReadOptions,
+) -> std::io::Result<()> {
+    let matrix = read_mtx_file(input_file, read_options)?;
+    let columns = matrix.destinations.len();
+    let productions = row_sums(&matrix.values, columns);
+    write_zone_values(productions_file, "total", &matrix.origins, &productions, float_format)?;
+    let attractions = column_sums(&matrix.values, columns);
+    write_zone_values(attractions_file, "total", &matrix.destinations, &attractions, float_format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marginals_mtx_file() -> std::io::Result<()> {
+        let productions_file = "test/test_productions_output.csv";
+        let attractions_file = "test/test_attractions_output.csv";
+        let two_decimals = "{:.2}".parse().unwrap();
+        marginals_mtx_file(
+            "test/test_expected.mtx",
+            productions_file,
+            attractions_file,
+            two_decimals,
+            &MtxReadOptions::default(),
+        )?;
+        // 0.1 0.2 0.3 0
+        // 1   2   3   0
+        // 0   0   0   0
+        // 0   0   0   0.1
+        assert_eq!(
+            std::fs::read_to_string(productions_file)?,
+            "zone,total\n1,0.60\n2,6.00\n3,0.00\n4,0.10\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(attractions_file)?,
+            "zone,total\n1,1.10\n2,2.20\n3,3.30\n4,0.10\n"
+        );
+        Ok(())
+    }
+}
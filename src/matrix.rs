@@ -0,0 +1,659 @@
+use rayon::prelude::*; // For parallel processing
+use std::borrow::Cow;
+
+use crate::rejects::RejectReason;
+
+/// A type the cells of a matrix are stored as. Matrices are single precision unless
+/// the values need more precision than `f32` can give.
+pub trait CellValue: bytemuck::Pod + PartialOrd + Send + Sync + std::fmt::Display + std::fmt::LowerExp {
+    /// The value type code in the header of an MTX file.
+    const MTX_TYPE: i32;
+
+    /// Converts a value parsed from the input, rounding it if needed.
+    fn from_f64(value: f64) -> Self;
+
+    /// Widens the value for sums and comparisons.
+    fn to_f64(self) -> f64;
+
+    /// Gets the values as `f32` for writers that only store single precision.
+    fn to_f32_values(values: &[Self]) -> Cow<'_, [f32]>;
+}
+
+impl CellValue for f32 {
+    const MTX_TYPE: i32 = 1;
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn to_f32_values(values: &[Self]) -> Cow<'_, [f32]> {
+        Cow::Borrowed(values)
+    }
+}
+
+impl CellValue for f64 {
+    const MTX_TYPE: i32 = 2;
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn to_f32_values(values: &[Self]) -> Cow<'_, [f32]> {
+        Cow::Owned(values.iter().map(|&value| value as f32).collect())
+    }
+}
+
+/// Checks if storing a value as `V` would silently change it, either by overflowing
+/// to infinity or by rounding a whole number. Fractions like `0.1` are never exact in
+/// binary, so their rounding is not counted.
+pub fn loses_precision<V: CellValue>(value: f64) -> bool {
+    let stored = V::from_f64(value).to_f64();
+    stored != value && (value.fract() == 0.0 || (stored.is_infinite() && value.is_finite()))
+}
+
+/// Rules for the values placed in the matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellRules {
+    /// The value of cells without any data.
+    pub fill: f32,
+    /// Values below this are replaced with the fill value.
+    pub min_value: Option<f32>,
+    /// Values above this are clamped to it.
+    pub max_value: Option<f32>,
+}
+
+impl Default for CellRules {
+    fn default() -> Self {
+        CellRules {
+            fill: 0.0,
+            min_value: None,
+            max_value: None,
+        }
+    }
+}
+
+impl CellRules {
+    /// Applies the limits to a value read from the input.
+    pub fn apply<V: CellValue>(&self, value: V) -> V {
+        let compared = value.to_f64();
+        match (self.min_value, self.max_value) {
+            (Some(min), _) if compared < min as f64 => V::from_f64(self.fill as f64),
+            (_, Some(max)) if compared > max as f64 => V::from_f64(max as f64),
+            _ => value,
+        }
+    }
+}
+
+/// Which cells of a square matrix are kept when it is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Triangle {
+    /// Every cell.
+    #[default]
+    Full,
+    /// The diagonal and the cells above it.
+    Upper,
+    /// The diagonal and the cells below it.
+    Lower,
+}
+
+/// How the two triangles of a square matrix are combined into a symmetric matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Symmetrize {
+    /// The cells are left as they are.
+    #[default]
+    None,
+    /// Both cells of a pair take their average.
+    Average,
+    /// Both cells of a pair take the larger of the two.
+    Max,
+}
+
+/// What each cell of a matrix is divided by, such as to turn trips into the share of
+/// each origin's trips going to each destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalize {
+    /// The cells are left as they are.
+    #[default]
+    None,
+    /// Each cell is divided by the sum of its row, so every row sums to 1.
+    Row,
+    /// Each cell is divided by the sum of its column, so every column sums to 1.
+    Col,
+    /// Each cell is divided by the sum of the whole matrix.
+    Total,
+}
+
+/// Builds a matrix of size `|origin| * |destination|` where each cell contains
+/// the value corresponding to the origin and destination pair.
+///
+/// # Arguments
+/// * `data` - The vector of tuples `(i32, i32, V)` representing the input data.
+/// * `all_zones` - The unique zone numbers, in the order of the matrix's rows and columns.
+/// * `rules` - The fill value and limits for the cells.
+///
+/// # Returns
+/// A vector of `V` representing the flattened matrix.
+pub fn build_matrix<V: CellValue>(data: &[(i32, i32, V)], all_zones: &[i32], rules: &CellRules) -> Vec<V> {
+    build_matrix_with_rejects(data, all_zones, rules, |_, _, _| {})
+}
+
+/// Builds a matrix like `build_matrix`, calling `on_reject` with the index in `data`
+/// of each row left out, why and a description, for rows whose zones are not in the
+/// matrix and values below the minimum value.
+pub fn build_matrix_with_rejects<V: CellValue>(
+    data: &[(i32, i32, V)],
+    all_zones: &[i32],
+    rules: &CellRules,
+    mut on_reject: impl FnMut(usize, RejectReason, String),
+) -> Vec<V> {
+    let zone_count = all_zones.len();
+    let zone_index: std::collections::HashMap<i32, usize> = all_zones
+        .iter()
+        .enumerate()
+        .map(|(i, &zone)| (zone, i))
+        .collect();
+
+    let mut matrix = vec![V::from_f64(rules.fill as f64); zone_count * zone_count];
+    for (row, (origin, destination, value)) in data.iter().enumerate() {
+        match (zone_index.get(origin), zone_index.get(destination)) {
+            (Some(&origin_idx), Some(&destination_idx)) => {
+                matrix[origin_idx * zone_count + destination_idx] = rules.apply(*value);
+                if let Some(min) = rules.min_value
+                    && value.to_f64() < min as f64
+                {
+                    on_reject(row, RejectReason::OutOfRange, format!("the value {} is below the minimum value {}", value, min));
+                }
+            }
+            (origin_idx, _) => {
+                let zone = if origin_idx.is_none() { origin } else { destination };
+                on_reject(row, RejectReason::UnknownZone, format!("the zone {} is not in the matrix", zone));
+            }
+        }
+    }
+    matrix
+}
+
+/// Builds a matrix over districts, summing the values of the rows whose zones fall in
+/// the same pair of districts. The limits are applied to each row's value before it is
+/// added, and pairs of districts without any rows take the fill value.
+///
+/// # Arguments
+/// * `data` - The rows of the input, between zones.
+/// * `all_zones` - The zones of the input kept in the matrix. Rows with other zones,
+///   or with a zone that has no district, are left out.
+/// * `district_of` - The district of each zone.
+/// * `districts` - The districts, in the order of the matrix's rows and columns.
+/// * `rules` - The fill value and limits for the cells.
+///
+/// # Returns
+/// The flattened `|districts| * |districts|` matrix of sums, kept in `f64` so they can
+/// be checked against the type the matrix is stored as.
+pub fn build_aggregated_matrix<V: CellValue>(
+    data: &[(i32, i32, V)],
+    all_zones: &[i32],
+    district_of: &std::collections::HashMap<i32, i32>,
+    districts: &[i32],
+    rules: &CellRules,
+) -> Vec<f64> {
+    let district_index: std::collections::HashMap<i32, usize> = districts.iter().enumerate().map(|(i, &district)| (district, i)).collect();
+    // The position of each zone's district among the districts
+    let zone_index: std::collections::HashMap<i32, usize> = all_zones
+        .iter()
+        .filter_map(|zone| Some((*zone, *district_index.get(district_of.get(zone)?)?)))
+        .collect();
+    let size = districts.len();
+    let mut sums = vec![0.0f64; size * size];
+    let mut has_rows = vec![false; size * size];
+    for (origin, destination, value) in data {
+        if let (Some(&origin_idx), Some(&destination_idx)) = (zone_index.get(origin), zone_index.get(destination)) {
+            let cell = origin_idx * size + destination_idx;
+            sums[cell] += rules.apply(*value).to_f64();
+            has_rows[cell] = true;
+        }
+    }
+    sums.into_iter()
+        .zip(has_rows)
+        .map(|(sum, has_rows)| if has_rows { sum } else { rules.fill as f64 })
+        .collect()
+}
+
+/// Extracts the intrazonal cells of a matrix. When the origins and destinations
+/// differ, the diagonal is only defined for zones that appear in both.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `origins` - The zone numbers for the rows of the matrix.
+/// * `destinations` - The zone numbers for the columns of the matrix.
+///
+/// # Returns
+/// The zones that are both an origin and a destination, with their intrazonal values.
+pub fn diagonal<V: CellValue>(matrix: &[V], origins: &[i32], destinations: &[i32]) -> (Vec<i32>, Vec<V>) {
+    let destination_index: std::collections::HashMap<i32, usize> = destinations
+        .iter()
+        .enumerate()
+        .map(|(i, &zone)| (zone, i))
+        .collect();
+    origins
+        .iter()
+        .enumerate()
+        .filter_map(|(row, zone)| {
+            let column = destination_index.get(zone)?;
+            Some((*zone, matrix[row * destinations.len() + column]))
+        })
+        .unzip()
+}
+
+/// Finds the first non-zero cells of a square matrix in row-major order, for checking
+/// a conversion against its input.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `all_zones` - The zone numbers for the rows and columns of the matrix.
+/// * `count` - The most cells to return.
+///
+/// # Returns
+/// The origin, destination and value of each cell.
+pub fn sample_cells<V: CellValue>(matrix: &[V], all_zones: &[i32], count: usize) -> Vec<(i32, i32, V)> {
+    matrix
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| value.to_f64() != 0.0)
+        .take(count)
+        .map(|(cell, &value)| (all_zones[cell / all_zones.len()], all_zones[cell % all_zones.len()], value))
+        .collect()
+}
+
+/// Finds the largest non-zero cells of a square matrix, partially sorting the cells so
+/// that only the ones kept are fully ordered.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `all_zones` - The zone numbers for the rows and columns of the matrix.
+/// * `count` - The most cells to return.
+///
+/// # Returns
+/// The origin, destination and value of each cell, largest first. Cells with the same
+/// value are in row-major order.
+pub fn top_flows<V: CellValue>(matrix: &[V], all_zones: &[i32], count: usize) -> Vec<(i32, i32, V)> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let mut cells: Vec<usize> = (0..matrix.len())
+        .filter(|&cell| {
+            let value = matrix[cell].to_f64();
+            value != 0.0 && !value.is_nan()
+        })
+        .collect();
+    let largest_first = |a: &usize, b: &usize| matrix[*b].to_f64().total_cmp(&matrix[*a].to_f64()).then(a.cmp(b));
+    if count < cells.len() {
+        cells.select_nth_unstable_by(count - 1, largest_first);
+        cells.truncate(count);
+    }
+    cells.sort_unstable_by(largest_first);
+    cells
+        .into_iter()
+        .map(|cell| (all_zones[cell / all_zones.len()], all_zones[cell % all_zones.len()], matrix[cell]))
+        .collect()
+}
+
+/// Counts the cells that would be non-zero in the matrix built by `build_matrix`,
+/// without allocating the dense matrix. Like `build_matrix`, the last value given
+/// for an origin and destination pair is the one that counts.
+///
+/// # Arguments
+/// * `data` - The vector of tuples `(i32, i32, f32)` representing the input data.
+/// * `all_zones` - The sorted vector of unique zone numbers.
+/// * `rules` - The fill value and limits for the cells.
+///
+/// # Returns
+/// The number of non-zero cells.
+pub fn count_non_zero_cells(data: &[(i32, i32, f32)], all_zones: &[i32], rules: &CellRules) -> usize {
+    let zones: std::collections::HashSet<i32> = all_zones.iter().copied().collect();
+    let mut cells = std::collections::HashMap::new();
+    for &(origin, destination, value) in data {
+        if zones.contains(&origin) && zones.contains(&destination) {
+            cells.insert((origin, destination), rules.apply(value));
+        }
+    }
+    let empty_cells = all_zones.len() * all_zones.len() - cells.len();
+    let filled = if rules.fill != 0.0 { empty_cells } else { 0 };
+    filled + cells.values().filter(|&&value| value != 0.0).count()
+}
+
+/// Transposes a flattened `rows * columns` matrix into a `columns * rows` matrix.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `rows` - The number of rows in the matrix.
+/// * `columns` - The number of columns in the matrix.
+///
+/// # Returns
+/// The flattened transposed matrix.
+pub fn transpose<V: CellValue>(matrix: &[V], rows: usize, columns: usize) -> Vec<V> {
+    let mut transposed = vec![V::zeroed(); matrix.len()];
+    transposed
+        .par_chunks_mut(rows.max(1))
+        .enumerate()
+        .for_each(|(column, transposed_row)| {
+            for (row, value) in transposed_row.iter_mut().enumerate() {
+                *value = matrix[row * columns + column];
+            }
+        });
+    transposed
+}
+
+/// Makes a flattened `size * size` matrix symmetric by combining each cell with its
+/// mirror across the diagonal.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `size` - The number of rows and columns in the matrix.
+/// * `symmetrize` - How each pair of cells is combined.
+pub fn symmetrize<V: CellValue>(matrix: &mut [V], size: usize, symmetrize: Symmetrize) {
+    for row in 0..size {
+        for column in row + 1..size {
+            let (upper, lower) = (matrix[row * size + column], matrix[column * size + row]);
+            let value = match symmetrize {
+                Symmetrize::None => return,
+                Symmetrize::Average => V::from_f64((upper.to_f64() + lower.to_f64()) / 2.0),
+                Symmetrize::Max if lower > upper => lower,
+                Symmetrize::Max => upper,
+            };
+            matrix[row * size + column] = value;
+            matrix[column * size + row] = value;
+        }
+    }
+}
+
+/// Zeroes the cells of a flattened `size * size` matrix outside of a triangle.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `size` - The number of rows and columns in the matrix.
+/// * `triangle` - The cells to keep, always including the diagonal.
+pub fn keep_triangle<V: CellValue>(matrix: &mut [V], size: usize, triangle: Triangle) {
+    if size == 0 || triangle == Triangle::Full {
+        return;
+    }
+    matrix.par_chunks_mut(size).enumerate().for_each(|(row, values)| {
+        let dropped = match triangle {
+            Triangle::Upper => &mut values[..row],
+            Triangle::Lower => &mut values[row + 1..],
+            Triangle::Full => &mut [],
+        };
+        dropped.fill(V::from_f64(0.0));
+    });
+}
+
+/// Divides the cells of a flattened `rows * columns` matrix by the sums of their rows,
+/// their columns or the whole matrix. Cells whose sum is zero are left as they are,
+/// so a row of zeros stays zero.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `columns` - The number of columns in the matrix.
+/// * `normalize` - Which sum each cell is divided by.
+pub fn normalize<V: CellValue>(matrix: &mut [V], columns: usize, normalize: Normalize) {
+    if columns == 0 {
+        return;
+    }
+    let divided = |value: V, sum: f64| if sum == 0.0 { value } else { V::from_f64(value.to_f64() / sum) };
+    match normalize {
+        Normalize::None => {}
+        Normalize::Row => {
+            let sums = row_sums(matrix, columns);
+            matrix.par_chunks_mut(columns).zip(sums).for_each(|(row, sum)| {
+                row.iter_mut().for_each(|value| *value = divided(*value, sum));
+            });
+        }
+        Normalize::Col => {
+            let sums = column_sums(matrix, columns);
+            matrix.par_chunks_mut(columns).for_each(|row| {
+                for (value, &sum) in row.iter_mut().zip(&sums) {
+                    *value = divided(*value, sum);
+                }
+            });
+        }
+        Normalize::Total => {
+            let total: f64 = row_sums(matrix, columns).iter().sum();
+            matrix.par_iter_mut().for_each(|value| *value = divided(*value, total));
+        }
+    }
+}
+
+/// Sums each row of a flattened `rows * columns` matrix.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `columns` - The number of columns in the matrix.
+///
+/// # Returns
+/// The sum of each row, accumulated in `f64`.
+pub fn row_sums<V: CellValue>(matrix: &[V], columns: usize) -> Vec<f64> {
+    if columns == 0 {
+        return Vec::new();
+    }
+    matrix
+        .par_chunks(columns)
+        .map(|row| row.iter().map(|&value| value.to_f64()).sum())
+        .collect()
+}
+
+/// The number of rows summed together by `column_sums` before they are added to the
+/// sums of the other rows.
+const COLUMN_SUM_BLOCK_ROWS: usize = 64;
+
+/// Sums each column of a flattened `rows * columns` matrix.
+///
+/// # Arguments
+/// * `matrix` - The flattened matrix of values in row-major order.
+/// * `columns` - The number of columns in the matrix.
+///
+/// # Returns
+/// The sum of each column, accumulated in `f64`.
+pub fn column_sums<V: CellValue>(matrix: &[V], columns: usize) -> Vec<f64> {
+    if columns == 0 {
+        return Vec::new();
+    }
+    // Each fixed block of rows is summed on its own and the blocks are added in order,
+    // so the sums are rounded the same way whatever the number of threads
+    let blocks: Vec<Vec<f64>> = matrix
+        .par_chunks(columns * COLUMN_SUM_BLOCK_ROWS)
+        .map(|block| {
+            let mut sums = vec![0.0f64; columns];
+            for row in block.chunks(columns) {
+                for (sum, &value) in sums.iter_mut().zip(row) {
+                    *sum += value.to_f64();
+                }
+            }
+            sums
+        })
+        .collect();
+    let mut sums = vec![0.0f64; columns];
+    for block in blocks {
+        for (sum, value) in sums.iter_mut().zip(block) {
+            *sum += value;
+        }
+    }
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_row_and_column_sums() {
+        // 1 2 3
+        // 4 5 6
+        let matrix = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        assert_eq!(row_sums(&matrix, 3), vec![6.0, 15.0]);
+        assert_eq!(column_sums(&matrix, 3), vec![5.0, 7.0, 9.0]);
+        assert!(row_sums::<f32>(&[], 0).is_empty());
+        assert!(column_sums::<f32>(&[], 0).is_empty());
+
+        // Values of very different sizes round differently when added in another order
+        let columns = 3;
+        let matrix: Vec<f32> = (0..columns * 1000)
+            .map(|i| if i % 7 == 0 { 1e8 } else { 0.1 + i as f32 * 1e-3 })
+            .collect();
+        let sums_with = |threads: usize| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| column_sums(&matrix, columns))
+        };
+        let single = sums_with(1);
+        for threads in [2, 3, 8] {
+            assert_eq!(sums_with(threads), single);
+        }
+    }
+
+    #[test]
+    fn test_triangles() {
+        // 1 2 3
+        // 4 5 6
+        // 7 8 9
+        let matrix = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0];
+        let mut upper = matrix;
+        keep_triangle(&mut upper, 3, Triangle::Upper);
+        assert_eq!(upper, [1.0, 2.0, 3.0, 0.0, 5.0, 6.0, 0.0, 0.0, 9.0]);
+        let mut lower = matrix;
+        keep_triangle(&mut lower, 3, Triangle::Lower);
+        assert_eq!(lower, [1.0, 0.0, 0.0, 4.0, 5.0, 0.0, 7.0, 8.0, 9.0]);
+
+        let mut average = matrix;
+        symmetrize(&mut average, 3, Symmetrize::Average);
+        assert_eq!(average, [1.0, 3.0, 5.0, 3.0, 5.0, 7.0, 5.0, 7.0, 9.0]);
+        let mut max = matrix;
+        symmetrize(&mut max, 3, Symmetrize::Max);
+        assert_eq!(max, [1.0, 4.0, 7.0, 4.0, 5.0, 8.0, 7.0, 8.0, 9.0]);
+        let mut unchanged = matrix;
+        symmetrize(&mut unchanged, 3, Symmetrize::None);
+        assert_eq!(unchanged, matrix);
+    }
+
+    #[test]
+    fn test_normalize() {
+        // 1 3
+        // 0 0
+        let matrix = [1.0, 3.0, 0.0, 0.0];
+        let mut rows = matrix;
+        normalize(&mut rows, 2, Normalize::Row);
+        assert_eq!(rows, [0.25, 0.75, 0.0, 0.0]);
+        let mut columns = matrix;
+        normalize(&mut columns, 2, Normalize::Col);
+        assert_eq!(columns, [1.0, 1.0, 0.0, 0.0]);
+        let mut total = matrix;
+        normalize(&mut total, 2, Normalize::Total);
+        assert_eq!(total, [0.25, 0.75, 0.0, 0.0]);
+        let mut unchanged = matrix;
+        normalize(&mut unchanged, 2, Normalize::None);
+        assert_eq!(unchanged, matrix);
+    }
+
+    #[test]
+    fn test_sample_cells() {
+        // 0 2 0
+        // 4 0 6
+        // 7 0 0
+        let matrix = [0.0f32, 2.0, 0.0, 4.0, 0.0, 6.0, 7.0, 0.0, 0.0];
+        let zones = [10, 20, 30];
+        let samples = sample_cells(&matrix, &zones, 3);
+        assert_eq!(samples, vec![(10, 20, 2.0), (20, 10, 4.0), (20, 30, 6.0)]);
+        for (origin, destination, value) in samples {
+            let row = zones.iter().position(|&zone| zone == origin).unwrap();
+            let column = zones.iter().position(|&zone| zone == destination).unwrap();
+            assert_eq!(matrix[row * zones.len() + column], value);
+        }
+        assert_eq!(sample_cells(&matrix, &zones, 10).len(), 4);
+        assert!(sample_cells::<f32>(&[], &[], 10).is_empty());
+    }
+
+    #[test]
+    fn test_top_flows() {
+        // 0 2 0
+        // 4 0 6
+        // 7 0 -9
+        let matrix = [0.0f32, 2.0, 0.0, 4.0, 0.0, 6.0, 7.0, 0.0, -9.0];
+        let zones = [10, 20, 30];
+        assert_eq!(top_flows(&matrix, &zones, 3), vec![(30, 10, 7.0), (20, 30, 6.0), (20, 10, 4.0)]);
+        assert_eq!(top_flows(&matrix, &zones, 10).len(), 5);
+        assert_eq!(top_flows(&matrix, &zones, 10)[4], (30, 30, -9.0));
+        assert!(top_flows(&matrix, &zones, 0).is_empty());
+
+        // Against a full sort of a larger matrix with repeated values
+        let zones: Vec<i32> = (1..=50).collect();
+        let matrix: Vec<f64> = (0..2500).map(|cell| ((cell * 7919) % 1013) as f64).collect();
+        let mut sorted: Vec<f64> = matrix.iter().copied().filter(|&value| value != 0.0).collect();
+        sorted.sort_by(|a, b| b.total_cmp(a));
+        let top = top_flows(&matrix, &zones, 20);
+        assert_eq!(top.iter().map(|&(_, _, value)| value).collect::<Vec<_>>(), sorted[..20]);
+        for (origin, destination, value) in top {
+            assert_eq!(matrix[(origin as usize - 1) * 50 + destination as usize - 1], value);
+        }
+    }
+
+    #[test]
+    fn test_diagonal() {
+        let matrix = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0];
+        let zones = [10, 20, 30];
+        let (diagonal_zones, values) = diagonal(&matrix, &zones, &zones);
+        assert_eq!(diagonal_zones, zones);
+        let n = zones.len();
+        assert_eq!(values, (0..n).map(|i| matrix[i * n + i]).collect::<Vec<_>>());
+
+        // Only zone 20 is both an origin and a destination
+        let (diagonal_zones, values) = diagonal(&[1.0, 2.0, 3.0, 4.0], &[10, 20], &[20, 30]);
+        assert_eq!(diagonal_zones, vec![20]);
+        assert_eq!(values, vec![3.0]);
+    }
+
+    #[test]
+    fn test_loses_precision() {
+        // The first whole number a 32-bit float cannot hold
+        assert!(loses_precision::<f32>(16_777_217.0));
+        assert!(!loses_precision::<f32>(16_777_216.0));
+        assert!(loses_precision::<f32>(1e39));
+        assert!(!loses_precision::<f32>(0.1));
+        assert!(!loses_precision::<f32>(f64::INFINITY));
+        assert!(!loses_precision::<f64>(16_777_217.0));
+    }
+
+    #[test]
+    fn test_count_non_zero_cells() {
+        // The second (1, 2) replaces the first, and zone 9 is not in the zone system
+        let data = [(1, 2, 3.0), (1, 2, 0.0), (2, 1, 4.0), (2, 2, 0.0), (1, 9, 1.0)];
+        let zones = [1, 2];
+        let rules = CellRules::default();
+        let matrix = build_matrix(&data, &zones, &rules);
+        let expected = matrix.iter().filter(|&&value| value != 0.0).count();
+        assert_eq!(count_non_zero_cells(&data, &zones, &rules), expected);
+        assert_eq!(expected, 1);
+    }
+
+    #[test]
+    fn test_value_limits() {
+        let data = [(1, 1, 0.3), (1, 2, 0.8), (2, 1, 5.0)];
+        let rules = CellRules {
+            fill: -1.0,
+            min_value: Some(0.5),
+            max_value: Some(2.0),
+        };
+        // The 0.3 cell falls below the minimum, 5.0 is clamped and (2, 2) has no data
+        assert_eq!(build_matrix(&data, &[1, 2], &rules), vec![-1.0, 0.8, 2.0, -1.0]);
+        assert_eq!(build_matrix(&data, &[1, 2], &CellRules::default()), vec![0.3, 0.8, 5.0, 0.0]);
+    }
+}
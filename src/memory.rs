@@ -0,0 +1,105 @@
+/// Estimates of the largest allocations of a conversion, for sizing the memory of the
+/// jobs that run it. Each figure is the size of the allocation's contents, leaving out
+/// the allocator's overhead and the spare capacity of growing vectors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryProfile {
+    /// The number of rows held in memory after the input is read.
+    pub rows: usize,
+    /// The size in bytes of each row.
+    pub row_size: usize,
+    /// The number of zones along each side of the dense matrix.
+    pub zones: usize,
+    /// The size in bytes of each value of the dense matrix.
+    pub value_size: usize,
+    /// The number of dense matrices held at once, such as the matrix and its transpose.
+    pub matrices: usize,
+    /// The bytes of the buffers used while the matrix is written.
+    pub buffer_bytes: usize,
+}
+
+impl MemoryProfile {
+    /// The bytes of the rows read from the input, `rows * sizeof((i32, i32, V))`.
+    pub fn data_bytes(&self) -> usize {
+        self.rows * self.row_size
+    }
+
+    /// The bytes of one dense matrix, `zones^2 * sizeof(V)`.
+    pub fn matrix_bytes(&self) -> usize {
+        self.zones * self.zones * self.value_size
+    }
+
+    /// The estimated peak, an upper bound taking the rows, every dense matrix and the
+    /// write buffers to be held at the same time.
+    pub fn peak_bytes(&self) -> usize {
+        self.data_bytes() + self.matrices * self.matrix_bytes() + self.buffer_bytes
+    }
+
+    /// Describes each estimate on a line of its own.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("input rows: {} x {} bytes = {}", self.rows, self.row_size, format_bytes(self.data_bytes())),
+            format!(
+                "dense matrix: {} x {} zones x {} bytes = {}",
+                self.zones,
+                self.zones,
+                self.value_size,
+                format_bytes(self.matrix_bytes())
+            ),
+        ];
+        if self.matrices > 1 {
+            lines.push(format!("dense matrices held at once: {}", self.matrices));
+        }
+        lines.push(format!("write buffers: {}", format_bytes(self.buffer_bytes)));
+        lines.push(format!("estimated peak: {}", format_bytes(self.peak_bytes())));
+        lines
+    }
+
+    /// Prints the estimates to standard error.
+    pub fn print(&self) {
+        for line in self.lines() {
+            eprintln!("Memory: {}", line);
+        }
+    }
+}
+
+/// Writes a number of bytes exactly, followed by its size in the largest binary unit
+/// it fills, such as `1572864 bytes (1.5 MiB)`.
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = None;
+    for name in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = Some(name);
+    }
+    match unit {
+        Some(unit) => format!("{} bytes ({:.1} {})", bytes, size, unit),
+        None => format!("{} bytes", bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_profile() {
+        let profile = MemoryProfile {
+            rows: 1000,
+            row_size: std::mem::size_of::<(i32, i32, f32)>(),
+            zones: 2500,
+            value_size: std::mem::size_of::<f32>(),
+            matrices: 1,
+            buffer_bytes: 0,
+        };
+        assert_eq!(profile.matrix_bytes(), 2500 * 2500 * 4);
+        assert_eq!(profile.data_bytes(), 12_000);
+        assert_eq!(profile.peak_bytes(), 25_012_000);
+        assert_eq!(profile.lines()[1], "dense matrix: 2500 x 2500 zones x 4 bytes = 25000000 bytes (23.8 MiB)");
+        assert_eq!(format_bytes(512), "512 bytes");
+        assert_eq!(format_bytes(1536), "1536 bytes (1.5 KiB)");
+    }
+}
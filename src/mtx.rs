@@ -0,0 +1,1677 @@
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::fs::File;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::{Compression, GzBuilder};
+use rayon::prelude::*; // For parallel processing
+
+use crate::error::Error;
+use crate::matrix::CellValue;
+use crate::truncation::{TolerantReader, TruncationReport};
+
+/// Where an output file is written, compressed or not.
+pub(crate) enum WriterType {
+    Plain(BufWriter<File>),
+    Gzip(BufWriter<GzipMembers>),
+    Stdout(BufWriter<std::io::Stdout>),
+}
+
+/// Starts a gzip member writing to `file`. There is no time stamp and an unknown
+/// operating system, so the same matrix always compresses to the same bytes.
+fn gzip_encoder(file: File) -> GzEncoder<File> {
+    GzBuilder::new()
+        .mtime(0)
+        .operating_system(GZIP_UNKNOWN_OS)
+        .write(file, Compression::default())
+}
+
+/// Compresses to a gzip file, optionally starting a new member every `block_bytes`
+/// bytes once blocks have been started, so each block can be decompressed on its own.
+pub(crate) struct GzipMembers {
+    encoder: Option<GzEncoder<File>>,
+    block_bytes: Option<u64>,
+    /// The bytes left before the current member is finished and the next one started.
+    remaining: u64,
+}
+
+impl GzipMembers {
+    fn new(file: File) -> GzipMembers {
+        GzipMembers {
+            encoder: Some(gzip_encoder(file)),
+            block_bytes: None,
+            remaining: 0,
+        }
+    }
+
+    fn encoder(&mut self) -> &mut GzEncoder<File> {
+        self.encoder.as_mut().expect("the gzip member is only taken while it is replaced")
+    }
+
+    /// Writes the trailer of the last member, returning the file.
+    fn finish(mut self) -> std::io::Result<File> {
+        self.encoder.take().expect("the gzip member is only taken while it is replaced").finish()
+    }
+}
+
+impl Write for GzipMembers {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Some(block_bytes) = self.block_bytes else {
+            return self.encoder().write(buf);
+        };
+        if self.remaining == 0 {
+            let file = self.encoder.take().expect("the gzip member is only taken while it is replaced").finish()?;
+            self.encoder = Some(gzip_encoder(file));
+            self.remaining = block_bytes;
+        }
+        let length = buf.len().min(self.remaining as usize);
+        let written = self.encoder().write(&buf[..length])?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.encoder().flush()
+    }
+}
+
+impl Write for WriterType {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            WriterType::Plain(writer) => writer.write(buf),
+            WriterType::Gzip(writer) => writer.write(buf),
+            WriterType::Stdout(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            WriterType::Plain(writer) => writer.flush(),
+            WriterType::Gzip(writer) => writer.flush(),
+            WriterType::Stdout(writer) => writer.flush(),
+        }
+    }
+    
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            WriterType::Plain(writer) => writer.write_all(buf),
+            WriterType::Gzip(writer) => writer.write_all(buf),
+            WriterType::Stdout(writer) => writer.write_all(buf),
+        }
+    }
+}
+
+impl WriterType {
+    /// Flushes the buffered bytes and writes the gzip trailer, returning the file that
+    /// was written to, if any.
+    fn finish(self) -> std::io::Result<Option<File>> {
+        match self {
+            WriterType::Plain(writer) => Ok(Some(writer.into_inner().map_err(|e| e.into_error())?)),
+            WriterType::Gzip(writer) => Ok(Some(writer.into_inner().map_err(|e| e.into_error())?.finish()?)),
+            WriterType::Stdout(mut writer) => {
+                writer.flush()?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Starts a new gzip member every `block_bytes` bytes from here on, the first
+    /// holding everything written so far. Other outputs are unaffected.
+    fn start_gzip_blocks(&mut self, block_bytes: u64) -> std::io::Result<()> {
+        if let WriterType::Gzip(writer) = self {
+            writer.flush()?;
+            let members = writer.get_mut();
+            members.block_bytes = Some(block_bytes.max(1));
+            members.remaining = block_bytes.max(1);
+        }
+        Ok(())
+    }
+}
+
+/// The contents of an MTX file, with the values stored as a flattened
+/// `|origins| * |destinations|` matrix in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtxMatrix {
+    pub origins: Vec<i32>,
+    pub destinations: Vec<i32>,
+    pub values: Vec<f32>,
+    /// The provenance text stored in the file, if any.
+    pub metadata: Option<String>,
+}
+
+/// The contents of an MTX file with its values at the precision they are stored in,
+/// along with how they were laid out, so the file can be rewritten without changing
+/// its value type, byte order or index width.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtxFile<V> {
+    pub origins: Vec<i32>,
+    pub destinations: Vec<i32>,
+    pub values: Vec<V>,
+    pub metadata: Option<String>,
+    pub endianness: Endianness,
+    pub index_width: IndexWidth,
+    /// Whether the values were stored byte-transposed.
+    pub byte_transposed: bool,
+}
+
+/// The gzip header's value for an unknown operating system.
+const GZIP_UNKNOWN_OS: u8 = 255;
+
+/// The number of values byte-swapped at a time when writing in the non-native byte order.
+const SWAP_BLOCK_VALUES: usize = 1 << 20;
+
+/// The magic number at the start of every MTX file.
+pub const MTX_MAGIC: u32 = 0xC4D4F1B2;
+
+/// Parses a magic number written in hexadecimal, with or without a leading `0x`.
+pub fn parse_magic(text: &str) -> Result<u32, String> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("{} is not a 32-bit hexadecimal number", text))
+}
+
+/// Version 2 files add a flags field after the dimensions describing the optional
+/// sections of the file. Files without any optional sections are written as version 1.
+const FLAGS_VERSION: i32 = 2;
+
+/// Set when a length-prefixed UTF-8 metadata block follows the index arrays.
+const FLAG_METADATA: i32 = 1;
+
+/// Set when the values are stored byte-transposed, as written by `GzipFilter::ByteTranspose`.
+const FLAG_BYTE_TRANSPOSE: i32 = 2;
+
+/// Set when the index arrays are stored as 16-bit integers, as written by `IndexWidth::W16`.
+const FLAG_INDEX_16: i32 = 4;
+
+/// Set when the index arrays are stored as 64-bit integers, as written by `IndexWidth::W64`.
+const FLAG_INDEX_64: i32 = 8;
+
+/// Set when the rows of values are padded to a stride, as written with
+/// `MtxWriteOptions::row_align`. The byte offset of the first value in the file and the
+/// stride in bytes follow the flags as two more integers.
+const FLAG_ROW_STRIDE: i32 = 16;
+
+/// The integer width of the zone numbers in the index arrays of an MTX file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+pub enum IndexWidth {
+    /// 16-bit zone numbers, halving the index arrays of zone systems numbered below 32768.
+    #[value(name = "16")]
+    #[serde(rename = "16")]
+    W16,
+    /// 32-bit zone numbers, as every MTX reader expects.
+    #[default]
+    #[value(name = "32")]
+    #[serde(rename = "32")]
+    W32,
+    /// 64-bit zone numbers, for readers that index with 64-bit integers.
+    #[value(name = "64")]
+    #[serde(rename = "64")]
+    W64,
+}
+
+impl IndexWidth {
+    /// The number of bytes in each zone number.
+    fn size(self) -> usize {
+        match self {
+            IndexWidth::W16 => 2,
+            IndexWidth::W32 => 4,
+            IndexWidth::W64 => 8,
+        }
+    }
+}
+
+/// How the values of an MTX file are laid out so that they compress better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GzipFilter {
+    /// The values are stored one after another.
+    #[default]
+    None,
+    /// The first byte of every value is stored, then the second byte of every value and
+    /// so on. The sign and exponent bytes of nearby floats are often the same, so
+    /// grouping them gives gzip long runs to compress.
+    ByteTranspose,
+}
+
+/// Settings controlling how `write_mtx_file` lays out the file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MtxWriteOptions {
+    /// The byte order to write the file in.
+    pub endianness: Endianness,
+    /// Provenance text to store after the index arrays.
+    pub metadata: Option<String>,
+    /// Sync the file's contents to disk before it replaces the output file.
+    pub fsync: bool,
+    /// How the values are laid out, which is recorded in the header for readers.
+    pub gzip_filter: GzipFilter,
+    /// The integer width of the index arrays, which is recorded in the header for readers.
+    pub index_width: IndexWidth,
+    /// Starts a new gzip member every this many rows of values in a `.gz` file, so
+    /// readers can decompress the blocks in parallel. The header is in the first block.
+    pub gzip_blocks: Option<usize>,
+    /// The magic number to start the file with instead of `MTX_MAGIC`, for private
+    /// formats sharing the layout. Only readers expecting it can read the file.
+    pub magic: Option<u32>,
+    /// Pads the header and each row of values with zeros so that every row starts at a
+    /// multiple of this many bytes in the uncompressed file, for readers mapping the
+    /// rows straight into memory. It must be a power of two.
+    pub row_align: Option<usize>,
+}
+
+impl MtxWriteOptions {
+    fn flags(&self) -> i32 {
+        let metadata = if self.metadata.is_some() { FLAG_METADATA } else { 0 };
+        let byte_transpose = if self.gzip_filter == GzipFilter::ByteTranspose { FLAG_BYTE_TRANSPOSE } else { 0 };
+        let index_width = match self.index_width {
+            IndexWidth::W16 => FLAG_INDEX_16,
+            IndexWidth::W32 => 0,
+            IndexWidth::W64 => FLAG_INDEX_64,
+        };
+        let row_stride = if self.row_align.is_some() { FLAG_ROW_STRIDE } else { 0 };
+        metadata | byte_transpose | index_width | row_stride
+    }
+
+    /// The number of bytes before the padding of a file with aligned rows.
+    fn header_bytes(&self, origins: usize, destinations: usize) -> usize {
+        strided_header_bytes(origins + destinations, self.index_width, self.metadata.as_deref())
+    }
+
+    /// Works out where the values of a matrix with `destinations` columns start and the
+    /// stride of its rows when they are aligned.
+    ///
+    /// # Returns
+    /// The byte offset of the first value and the stride of the rows in bytes.
+    fn row_layout(&self, origins: usize, destinations: usize, value_size: usize) -> std::io::Result<Option<(usize, usize)>> {
+        let Some(align) = self.row_align else {
+            return Ok(None);
+        };
+        if !align.is_power_of_two() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The row alignment {} is not a power of two", align),
+            ));
+        }
+        let offset = self.header_bytes(origins, destinations).next_multiple_of(align);
+        let stride = (destinations * value_size).next_multiple_of(align);
+        if i32::try_from(offset).is_err() || i32::try_from(stride).is_err() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The rows aligned to {} bytes are too long to record in the header", align),
+            ));
+        }
+        Ok(Some((offset, stride)))
+    }
+}
+
+/// Settings controlling how `read_mtx_file` reads a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MtxReadOptions {
+    /// The byte order of the file, or `None` to detect it from the magic number.
+    pub endianness: Option<Endianness>,
+    /// Keep the values read before a gzip decode error, leaving the rest of the
+    /// cells zero, instead of failing.
+    pub tolerate_truncation: bool,
+    /// How the origin and destination index arrays are checked.
+    pub index_check: IndexCheck,
+    /// The magic number the file starts with, or `None` for `MTX_MAGIC`.
+    pub expect_magic: Option<u32>,
+}
+
+/// How the index arrays of an MTX file are checked as they are read, for files from
+/// other tools whose zones may be repeated or out of order, which breaks lookups by zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexCheck {
+    /// A zone listed more than once is reported with a warning. Zones in any order are
+    /// accepted, as written with an ordered zones file.
+    #[default]
+    Warn,
+    /// A zone listed more than once is an error.
+    Unique,
+    /// Zones that are not strictly increasing are an error.
+    Sorted,
+}
+
+/// The number of bytes before the padding of a file with aligned rows: the magic
+/// number, version, type, dimensions and flags, the offset and stride, the index sizes,
+/// the index arrays of `zones` zones in all and the metadata.
+fn strided_header_bytes(zones: usize, index_width: IndexWidth, metadata: Option<&str>) -> usize {
+    5 * 4 + 2 * 4 + 2 * 4 + zones * index_width.size() + metadata.map_or(0, |metadata| 4 + metadata.len())
+}
+
+/// Checks an index array against the policy, warning about or failing on the first
+/// zone that breaks it.
+fn check_index_array(zones: &[i32], array: &str, input_file_name: &str, policy: IndexCheck) -> std::io::Result<()> {
+    if policy == IndexCheck::Sorted {
+        return match zones.windows(2).position(|pair| pair[0] >= pair[1]) {
+            Some(position) => Err(invalid_mtx(
+                input_file_name,
+                format!(
+                    "the {} index array is not strictly increasing, as zone {} follows zone {} at position {}",
+                    array,
+                    zones[position + 1],
+                    zones[position],
+                    position + 2
+                ),
+            )),
+            None => Ok(()),
+        };
+    }
+    let mut seen = std::collections::HashSet::with_capacity(zones.len());
+    let Some(&zone) = zones.iter().find(|&&zone| !seen.insert(zone)) else {
+        return Ok(());
+    };
+    let problem = format!("the {} index array lists zone {} more than once", array, zone);
+    if policy == IndexCheck::Unique {
+        return Err(invalid_mtx(input_file_name, problem));
+    }
+    eprintln!("Warning: {} in {}, so a lookup by zone finds only one of its entries", problem, input_file_name);
+    Ok(())
+}
+
+/// The byte order used for the header, index arrays and values of an MTX file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// The byte order of the machine we are running on.
+    pub fn native() -> Endianness {
+        if cfg!(target_endian = "little") {
+            Endianness::Little
+        } else {
+            Endianness::Big
+        }
+    }
+
+    fn i32_bytes(self, value: i32) -> [u8; 4] {
+        match self {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        }
+    }
+
+    /// The bytes of a zone number in an index array of the given width, which it must fit.
+    fn zone_bytes(self, zone: i32, width: IndexWidth) -> Vec<u8> {
+        match (width, self) {
+            (IndexWidth::W16, Endianness::Little) => (zone as i16).to_le_bytes().to_vec(),
+            (IndexWidth::W16, Endianness::Big) => (zone as i16).to_be_bytes().to_vec(),
+            (IndexWidth::W32, _) => self.i32_bytes(zone).to_vec(),
+            (IndexWidth::W64, Endianness::Little) => (zone as i64).to_le_bytes().to_vec(),
+            (IndexWidth::W64, Endianness::Big) => (zone as i64).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// Writes the MTX file in the specified format. If the output file name ends with `.gz`,
+/// the file is written as a gzip-compressed file. An output file name of `-` writes
+/// the matrix to standard output.
+///
+/// Files are written to a temporary file beside the output, which only replaces the
+/// output once it is complete, so a failed write never leaves a partial matrix behind.
+///
+/// # Arguments
+/// * `output_file_name` - The path to the output MTX file.
+/// * `origins` - The zone numbers for the rows of the matrix.
+/// * `destinations` - The zone numbers for the columns of the matrix.
+/// * `matrix` - The flattened matrix of values, stored as single or double precision
+///   according to their type.
+/// * `options` - The byte order and optional sections of the file.
+pub fn write_mtx_file<V: CellValue>(
+    output_file_name: &str,
+    origins: &[i32],
+    destinations: &[i32],
+    matrix: &[V],
+    options: &MtxWriteOptions,
+) -> std::io::Result<()> {
+    write_output(output_file_name, options.fsync, |writer| {
+        write_mtx_contents(writer, origins, destinations, matrix, options)
+    })
+}
+
+/// Writes only the values of the matrix, without the header or index arrays of an MTX
+/// file, for readers that already know the zone system. The file is written like
+/// `write_mtx_file`, in the requested byte order, and is exactly `|matrix|` values long.
+///
+/// # Arguments
+/// * `output_file_name` - The path to the output file.
+/// * `matrix` - The flattened matrix of values, stored as single or double precision
+///   according to their type.
+/// * `options` - The byte order of the file. There is nowhere to store metadata.
+pub fn write_raw_file<V: CellValue>(
+    output_file_name: &str,
+    matrix: &[V],
+    options: &MtxWriteOptions,
+) -> std::io::Result<()> {
+    write_output(output_file_name, options.fsync, |writer| {
+        write_values(writer, matrix, options.endianness)
+    })
+}
+
+/// Writes a file with `write`, compressing it if the name ends with `.gz`. Files are
+/// written to a temporary file that replaces the output once it is complete, after
+/// syncing it to disk if `fsync` is set. A name of `-` writes to standard output.
+pub(crate) fn write_output(
+    output_file_name: &str,
+    fsync: bool,
+    write: impl FnOnce(&mut WriterType) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    if output_file_name == "-" {
+        let mut writer = WriterType::Stdout(BufWriter::new(std::io::stdout()));
+        write(&mut writer)?;
+        return writer.finish().map(|_| ());
+    }
+    let temp_file_name = format!("{}.tmp", output_file_name);
+    let result = write_temp_file(&temp_file_name, output_file_name.ends_with(".gz"), fsync, write)
+        .and_then(|()| std::fs::rename(&temp_file_name, output_file_name));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_file_name);
+    }
+    result
+}
+
+/// Writes the whole file to `temp_file_name`, syncing it to disk when `fsync` is set.
+fn write_temp_file(
+    temp_file_name: &str,
+    gzip: bool,
+    fsync: bool,
+    write: impl FnOnce(&mut WriterType) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let output_file = File::create(temp_file_name)?;
+    let mut writer = if gzip {
+        WriterType::Gzip(BufWriter::new(GzipMembers::new(output_file)))
+    } else {
+        WriterType::Plain(BufWriter::new(output_file))
+    };
+    write(&mut writer)?;
+    if let Some(file) = writer.finish()?
+        && fsync
+    {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Estimates the bytes `write_mtx_file` holds in buffers beyond the matrix itself: the
+/// index arrays when they are converted to another byte order or width, and the block
+/// of values being byte-swapped or transposed.
+///
+/// # Arguments
+/// * `zones` - The number of origins, and of destinations.
+/// * `value_size` - The size in bytes of each value.
+/// * `options` - The byte order and layout the file is written in.
+pub fn write_buffer_bytes(zones: usize, value_size: usize, options: &MtxWriteOptions) -> usize {
+    let native = options.endianness == Endianness::native();
+    let index_bytes = if native && options.index_width == IndexWidth::W32 {
+        0
+    } else {
+        2 * zones * options.index_width.size()
+    };
+    let cells = zones * zones;
+    let value_bytes = match options.gzip_filter {
+        GzipFilter::ByteTranspose => cells.min(SWAP_BLOCK_VALUES),
+        GzipFilter::None if native => 0,
+        GzipFilter::None => cells.min(SWAP_BLOCK_VALUES) * value_size,
+    };
+    index_bytes + value_bytes
+}
+
+/// Writes the header, index arrays, metadata and values of an MTX file.
+fn write_mtx_contents<V: CellValue>(
+    writer: &mut WriterType,
+    origins: &[i32],
+    destinations: &[i32],
+    matrix: &[V],
+    options: &MtxWriteOptions,
+) -> std::io::Result<()> {
+    let endianness = options.endianness;
+    let flags = options.flags();
+    if options.index_width == IndexWidth::W16
+        && let Some(&zone) = origins.iter().chain(destinations).find(|&&zone| i16::try_from(zone).is_err())
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("The zone {} does not fit in a 16-bit index array", zone),
+        ));
+    }
+    if options.row_align.is_some() && options.gzip_filter == GzipFilter::ByteTranspose {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Rows cannot be aligned when the values are byte-transposed, as they are not stored row by row",
+        ));
+    }
+    let row_layout = options.row_layout(origins.len(), destinations.len(), std::mem::size_of::<V>())?;
+
+    let magic = options.magic.unwrap_or(MTX_MAGIC);
+    let magic = match endianness {
+        Endianness::Little => magic.to_le_bytes(),
+        Endianness::Big => magic.to_be_bytes(),
+    };
+    writer.write_all(&magic)?; // Magic Number
+    let version = if flags != 0 { FLAGS_VERSION } else { 1 };
+    writer.write_all(&endianness.i32_bytes(version))?; // Version Number
+    writer.write_all(&endianness.i32_bytes(V::MTX_TYPE))?; // Type
+    writer.write_all(&endianness.i32_bytes(2))?; // Dimensions
+    if version == FLAGS_VERSION {
+        writer.write_all(&endianness.i32_bytes(flags))?; // Optional sections
+    }
+    if let Some((offset, stride)) = row_layout {
+        writer.write_all(&endianness.i32_bytes(offset as i32))?; // Offset of the first value
+        writer.write_all(&endianness.i32_bytes(stride as i32))?; // Row stride
+    }
+    writer.write_all(&endianness.i32_bytes(origins.len() as i32))?; // Index size for origin
+    writer.write_all(&endianness.i32_bytes(destinations.len() as i32))?; // Index size for destination
+
+    if endianness == Endianness::native() && options.index_width == IndexWidth::W32 {
+        // Write all origin zone numbers in a single call 
+        let origin_zone_bytes: &[u8] = bytemuck::cast_slice(origins);
+        writer.write_all(origin_zone_bytes)?; // Zone Numbers for Origin
+
+        // Write all destination zone numbers in a single call
+        let destination_zone_bytes: &[u8] = bytemuck::cast_slice(destinations);
+        writer.write_all(destination_zone_bytes)?; // Zone Numbers for Destination
+        write_metadata(writer, options)?;
+    } else {
+        // Convert the zone numbers to the requested byte order and width
+        let width = options.index_width;
+        let origin_zone_bytes: Vec<u8> = origins
+            .par_iter()
+            .flat_map(|&zone| endianness.zone_bytes(zone, width))
+            .collect();
+        writer.write_all(&origin_zone_bytes)?; // Zone Numbers for Origin
+        let destination_zone_bytes: Vec<u8> = destinations
+            .par_iter()
+            .flat_map(|&zone| endianness.zone_bytes(zone, width))
+            .collect();
+        writer.write_all(&destination_zone_bytes)?; // Zone Numbers for Destination
+        write_metadata(writer, options)?;
+    }
+    if let Some((offset, _)) = row_layout {
+        // Pad the header so the first row starts on the alignment
+        let header_bytes = options.header_bytes(origins.len(), destinations.len());
+        writer.write_all(&vec![0u8; offset - header_bytes])?;
+    }
+    if let Some(rows) = options.gzip_blocks {
+        // The first block holds the header as well as its rows
+        let row_bytes = row_layout.map_or(destinations.len() * std::mem::size_of::<V>(), |(_, stride)| stride);
+        writer.start_gzip_blocks((rows * row_bytes) as u64)?;
+    }
+    match (options.gzip_filter, row_layout) {
+        (GzipFilter::None, Some((_, stride))) => write_strided_values(writer, matrix, destinations.len(), stride, endianness),
+        (GzipFilter::None, None) => write_values(writer, matrix, endianness),
+        (GzipFilter::ByteTranspose, _) => write_transposed_values(writer, matrix, endianness),
+    }
+}
+
+/// Writes the values of a matrix with `columns` columns in the requested byte order,
+/// padding each row with zeros to `stride` bytes.
+fn write_strided_values<V: CellValue>(
+    writer: &mut impl Write,
+    matrix: &[V],
+    columns: usize,
+    stride: usize,
+    endianness: Endianness,
+) -> std::io::Result<()> {
+    if columns == 0 {
+        return Ok(());
+    }
+    let value_size = std::mem::size_of::<V>();
+    let mut row_bytes = vec![0u8; stride];
+    for row in matrix.chunks(columns) {
+        let values = &mut row_bytes[..columns * value_size];
+        values.copy_from_slice(bytemuck::cast_slice(row));
+        if endianness != Endianness::native() {
+            values.chunks_mut(value_size).for_each(|value| value.reverse());
+        }
+        writer.write_all(&row_bytes)?;
+    }
+    Ok(())
+}
+
+/// Writes the values of a matrix in the requested byte order.
+fn write_values<V: CellValue>(writer: &mut impl Write, matrix: &[V], endianness: Endianness) -> std::io::Result<()> {
+    if endianness == Endianness::native() {
+        // Write all matrix values in a single call
+        writer.write_all(bytemuck::cast_slice(matrix))
+    } else {
+        // Convert the values to the requested byte order a block at a time, so only a
+        // block rather than a copy of the whole matrix is held in memory
+        let mut block_bytes = Vec::with_capacity(SWAP_BLOCK_VALUES * std::mem::size_of::<V>());
+        for block in matrix.chunks(SWAP_BLOCK_VALUES) {
+            block_bytes.clear();
+            block_bytes.extend_from_slice(bytemuck::cast_slice(block));
+            block_bytes
+                .par_chunks_mut(std::mem::size_of::<V>())
+                .for_each(|value| value.reverse());
+            writer.write_all(&block_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the values of a matrix in the requested byte order with their bytes
+/// transposed: the first byte of every value, then the second byte of every value and
+/// so on. Each of these planes is gathered a block at a time, so no copy of the whole
+/// matrix is held in memory.
+fn write_transposed_values<V: CellValue>(
+    writer: &mut impl Write,
+    matrix: &[V],
+    endianness: Endianness,
+) -> std::io::Result<()> {
+    let value_size = std::mem::size_of::<V>();
+    let mut plane_bytes = Vec::with_capacity(SWAP_BLOCK_VALUES);
+    for plane in 0..value_size {
+        // The position of the plane's byte within each value in memory
+        let byte = if endianness == Endianness::native() { plane } else { value_size - 1 - plane };
+        for block in matrix.chunks(SWAP_BLOCK_VALUES) {
+            let block_bytes: &[u8] = bytemuck::cast_slice(block);
+            plane_bytes.clear();
+            plane_bytes.extend(block_bytes.iter().skip(byte).step_by(value_size));
+            writer.write_all(&plane_bytes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the length-prefixed metadata block, if there is one.
+fn write_metadata(writer: &mut impl Write, options: &MtxWriteOptions) -> std::io::Result<()> {
+    if let Some(metadata) = &options.metadata {
+        writer.write_all(&options.endianness.i32_bytes(metadata.len() as i32))?;
+        writer.write_all(metadata.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads an MTX file written by `write_mtx_file`. If the file name ends with `.gz`,
+/// the file is decompressed while reading.
+///
+/// # Arguments
+/// * `input_file_name` - The path to the MTX file.
+/// * `options` - The byte order of the file and how to handle truncation.
+///
+/// # Returns
+/// The zone numbers and values stored in the file.
+pub fn read_mtx_file(
+    input_file_name: &str,
+    options: &MtxReadOptions,
+) -> std::io::Result<MtxMatrix> {
+    let (mut reader, endianness, truncation) = open_mtx_file(input_file_name, options)?;
+    match endianness {
+        Endianness::Little => read_mtx_contents::<LittleEndian>(&mut reader, input_file_name, options, truncation.as_ref()),
+        Endianness::Big => read_mtx_contents::<BigEndian>(&mut reader, input_file_name, options, truncation.as_ref()),
+    }
+}
+
+/// Reads an MTX file's contents from any reader, such as a matrix held in memory.
+///
+/// # Arguments
+/// * `reader` - The uncompressed contents, starting at the magic number.
+/// * `name` - The name of the matrix to use in error messages.
+/// * `options` - The byte order of the contents.
+///
+/// # Returns
+/// The zone numbers and values stored in the contents.
+pub fn read_mtx_from(mut reader: impl Read, name: &str, options: &MtxReadOptions) -> std::io::Result<MtxMatrix> {
+    match read_magic(&mut reader, name, options)? {
+        Endianness::Little => read_mtx_contents::<LittleEndian>(&mut reader, name, options, None),
+        Endianness::Big => read_mtx_contents::<BigEndian>(&mut reader, name, options, None),
+    }
+}
+
+/// Reads the value type of an MTX file from its header: 1 for 32-bit and 2 for 64-bit
+/// floats.
+pub fn read_mtx_value_type(input_file_name: &str, options: &MtxReadOptions) -> std::io::Result<i32> {
+    let (mut reader, endianness, _) = open_mtx_file(input_file_name, options)?;
+    let header = match endianness {
+        Endianness::Little => read_mtx_header::<LittleEndian>(&mut reader, input_file_name, options)?,
+        Endianness::Big => read_mtx_header::<BigEndian>(&mut reader, input_file_name, options)?,
+    };
+    Ok(header.value_type)
+}
+
+/// Reads an MTX file whose values are of type `V` without narrowing them, keeping the
+/// layout they were stored with.
+///
+/// # Arguments
+/// * `input_file_name` - The path to the MTX file.
+/// * `options` - The byte order of the file and how to handle truncation.
+///
+/// # Returns
+/// The zone numbers, values and layout of the file, or an error if its values are not
+/// of type `V`.
+pub fn read_mtx_file_as<V: CellValue>(input_file_name: &str, options: &MtxReadOptions) -> std::io::Result<MtxFile<V>> {
+    let (mut reader, endianness, truncation) = open_mtx_file(input_file_name, options)?;
+    let (header, values) = match endianness {
+        Endianness::Little => read_mtx_contents_as::<LittleEndian, V>(&mut reader, input_file_name, options, truncation.as_ref())?,
+        Endianness::Big => read_mtx_contents_as::<BigEndian, V>(&mut reader, input_file_name, options, truncation.as_ref())?,
+    };
+    Ok(MtxFile {
+        origins: header.origins,
+        destinations: header.destinations,
+        values,
+        metadata: header.metadata,
+        endianness,
+        index_width: header.index_width,
+        byte_transposed: header.byte_transposed,
+    })
+}
+
+fn read_mtx_contents_as<B: ByteOrder, V: CellValue>(
+    reader: &mut impl Read,
+    input_file_name: &str,
+    options: &MtxReadOptions,
+    truncation: Option<&TruncationReport>,
+) -> std::io::Result<(MtxHeader, Vec<V>)> {
+    let header = read_mtx_header::<B>(reader, input_file_name, options)?;
+    if header.value_type != V::MTX_TYPE {
+        return Err(invalid_mtx(
+            input_file_name,
+            format!("expected values of type {} but found type {}", V::MTX_TYPE, header.value_type),
+        ));
+    }
+    let values = read_header_values::<B, V>(reader, &header, input_file_name, truncation)?;
+    Ok((header, values))
+}
+
+/// Reads the zone numbers of an MTX file from its header, without reading its values.
+///
+/// # Arguments
+/// * `input_file_name` - The path to the MTX file.
+/// * `options` - The byte order of the file.
+///
+/// # Returns
+/// The origin and destination zone numbers.
+pub fn read_mtx_zones(
+    input_file_name: &str,
+    options: &MtxReadOptions,
+) -> std::io::Result<(Vec<i32>, Vec<i32>)> {
+    let (mut reader, endianness, _) = open_mtx_file(input_file_name, options)?;
+    let header = match endianness {
+        Endianness::Little => read_mtx_header::<LittleEndian>(&mut reader, input_file_name, options)?,
+        Endianness::Big => read_mtx_header::<BigEndian>(&mut reader, input_file_name, options)?,
+    };
+    Ok((header.origins, header.destinations))
+}
+
+/// Reads the values of a few cells of an MTX file without reading the rest of its
+/// values. An uncompressed file is seeked to each cell, while a gzipped file is
+/// decompressed up to the last cell asked for.
+///
+/// # Arguments
+/// * `input_file_name` - The path to the MTX file.
+/// * `options` - The byte order of the file.
+/// * `select` - Chooses the cells to read from the origins and destinations, as
+///   positions in the flattened row-major matrix.
+///
+/// # Returns
+/// The origin and destination zone numbers, and the value of each selected cell.
+pub fn read_mtx_cells(
+    input_file_name: &str,
+    options: &MtxReadOptions,
+    select: impl FnOnce(&[i32], &[i32]) -> std::io::Result<Vec<usize>>,
+) -> std::io::Result<(Vec<i32>, Vec<i32>, Vec<f32>)> {
+    let input_file = File::open(input_file_name)?;
+    if input_file_name.ends_with(".gz") {
+        let mut reader = BufReader::new(MultiGzDecoder::new(input_file));
+        read_cells_from(&mut reader, input_file_name, options, select, |reader, bytes| {
+            std::io::copy(&mut Read::by_ref(reader).take(bytes), &mut std::io::sink()).map(|_| ())
+        })
+    } else {
+        let mut reader = BufReader::new(input_file);
+        read_cells_from(&mut reader, input_file_name, options, select, |reader, bytes| {
+            reader.seek_relative(bytes as i64)
+        })
+    }
+}
+
+/// Reads the selected cells of an MTX file, calling `skip` to move past the bytes
+/// of the cells in between.
+fn read_cells_from<R: Read>(
+    reader: &mut R,
+    input_file_name: &str,
+    options: &MtxReadOptions,
+    select: impl FnOnce(&[i32], &[i32]) -> std::io::Result<Vec<usize>>,
+    skip: impl FnMut(&mut R, u64) -> std::io::Result<()>,
+) -> std::io::Result<(Vec<i32>, Vec<i32>, Vec<f32>)> {
+    match read_magic(reader, input_file_name, options)? {
+        Endianness::Little => read_cells::<LittleEndian, R>(reader, input_file_name, options, select, skip),
+        Endianness::Big => read_cells::<BigEndian, R>(reader, input_file_name, options, select, skip),
+    }
+}
+
+fn read_cells<B: ByteOrder, R: Read>(
+    reader: &mut R,
+    input_file_name: &str,
+    options: &MtxReadOptions,
+    select: impl FnOnce(&[i32], &[i32]) -> std::io::Result<Vec<usize>>,
+    mut skip: impl FnMut(&mut R, u64) -> std::io::Result<()>,
+) -> std::io::Result<(Vec<i32>, Vec<i32>, Vec<f32>)> {
+    let header = read_mtx_header::<B>(reader, input_file_name, options)?;
+    let cells = select(&header.origins, &header.destinations)?;
+    if header.byte_transposed {
+        // The bytes of each value are spread through the file, so every value is read
+        let values = read_f32_values::<B>(reader, &header, input_file_name, None)?;
+        let selected = cells.iter().map(|&cell| values[cell]).collect();
+        return Ok((header.origins, header.destinations, selected));
+    }
+    let value_size = if header.value_type == f64::MTX_TYPE { 8 } else { 4 };
+    // The byte of the values at which a cell starts, past the padding of the rows before it
+    let columns = header.destinations.len().max(1);
+    let position = |cell: usize| match header.row_stride {
+        Some(stride) => (cell / columns) * stride + (cell % columns) * value_size,
+        None => cell * value_size,
+    };
+    // The cells are read in the order they are stored, so the reader only moves forwards
+    let mut order: Vec<usize> = (0..cells.len()).collect();
+    order.sort_by_key(|&index| cells[index]);
+    let mut values = vec![0.0f32; cells.len()];
+    let mut next_position = 0;
+    let mut last_cell = None;
+    let mut last_value = 0.0;
+    for index in order {
+        let cell = cells[index];
+        if last_cell != Some(cell) {
+            skip(reader, (position(cell) - next_position) as u64)?;
+            last_value = if value_size == 8 { reader.read_f64::<B>()? as f32 } else { reader.read_f32::<B>()? };
+            next_position = position(cell) + value_size;
+            last_cell = Some(cell);
+        }
+        values[index] = last_value;
+    }
+    Ok((header.origins, header.destinations, values))
+}
+
+/// Rereads randomly chosen cells of a written MTX file and checks them against the
+/// matrix it was written from, catching byte order or indexing mistakes without the
+/// cost of reading back the whole file.
+///
+/// # Arguments
+/// * `input_file_name` - The path to the MTX file that was written.
+/// * `origins` - The zone numbers written for the rows of the matrix.
+/// * `destinations` - The zone numbers written for the columns of the matrix.
+/// * `matrix` - The flattened matrix of values that was written.
+/// * `read_options` - How the file is read back, such as the magic number it was written with.
+/// * `count` - The number of different cells to check, or every cell if there are fewer.
+/// * `seed` - The seed choosing the cells, which is reported if a cell differs.
+///
+/// # Returns
+/// The number of cells checked.
+pub fn verify_mtx_sample<V: CellValue>(
+    input_file_name: &str,
+    origins: &[i32],
+    destinations: &[i32],
+    matrix: &[V],
+    read_options: &MtxReadOptions,
+    count: usize,
+    seed: u64,
+) -> std::io::Result<usize> {
+    let cells = if count >= matrix.len() {
+        (0..matrix.len()).collect()
+    } else {
+        let mut rng = crate::generate::SplitMix64(seed);
+        let mut chosen = std::collections::BTreeSet::new();
+        while chosen.len() < count {
+            chosen.insert((rng.next_u64() % matrix.len() as u64) as usize);
+        }
+        chosen.into_iter().collect::<Vec<usize>>()
+    };
+    let (read_origins, read_destinations, values) =
+        read_mtx_cells(input_file_name, read_options, |_, _| Ok(cells.clone()))?;
+    if read_origins != origins || read_destinations != destinations {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("The zones read back from {} differ from the zones written", input_file_name),
+        ));
+    }
+    for (&cell, &value) in cells.iter().zip(&values) {
+        let written = matrix[cell].to_f64() as f32;
+        if written.to_bits() != value.to_bits() && !(written.is_nan() && value.is_nan()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "The cell from zone {} to zone {} of {} was written as {} but reads back as {}, checking cells chosen with seed {}",
+                    origins[cell / destinations.len()],
+                    destinations[cell % destinations.len()],
+                    input_file_name,
+                    written,
+                    value,
+                    seed
+                ),
+            ));
+        }
+    }
+    Ok(cells.len())
+}
+
+/// Opens an MTX file and reads its magic number.
+///
+/// # Returns
+/// The reader positioned after the magic number, the byte order of the file, and the
+/// report of any truncation when it is being tolerated.
+fn open_mtx_file(
+    input_file_name: &str,
+    options: &MtxReadOptions,
+) -> std::io::Result<(Box<dyn Read>, Endianness, Option<TruncationReport>)> {
+    let input_file = File::open(input_file_name)?;
+    let mut truncation = None;
+    let mut reader: Box<dyn Read> = if input_file_name.ends_with(".gz") {
+        let decoder = MultiGzDecoder::new(input_file);
+        if options.tolerate_truncation {
+            let (reader, report) = TolerantReader::new(decoder, false);
+            truncation = Some(report);
+            Box::new(BufReader::new(reader))
+        } else {
+            Box::new(BufReader::new(decoder))
+        }
+    } else {
+        Box::new(BufReader::new(input_file))
+    };
+    let endianness = read_magic(&mut reader, input_file_name, options)?;
+    Ok((reader, endianness, truncation))
+}
+
+/// Reads the magic number, which must be the one expected by `options`, checking it
+/// against the byte order in `options` if one is given.
+///
+/// # Returns
+/// The byte order of the file.
+fn read_magic(reader: &mut impl Read, input_file_name: &str, options: &MtxReadOptions) -> std::io::Result<Endianness> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    let expected = options.expect_magic.unwrap_or(MTX_MAGIC);
+    let detected = if u32::from_le_bytes(magic) == expected {
+        Endianness::Little
+    } else if u32::from_be_bytes(magic) == expected {
+        Endianness::Big
+    } else {
+        return Err(Error::MagicMismatch {
+            file: input_file_name.to_string(),
+            found: u32::from_le_bytes(magic),
+        }
+        .into());
+    };
+    let endianness = options.endianness.unwrap_or(detected);
+    if endianness != detected {
+        return Err(invalid_mtx(
+            input_file_name,
+            format!("expected {:?} endian data but the magic number is {:?} endian", endianness, detected),
+        ));
+    }
+    Ok(endianness)
+}
+
+/// Everything in an MTX file before its values.
+struct MtxHeader {
+    value_type: i32,
+    origins: Vec<i32>,
+    destinations: Vec<i32>,
+    metadata: Option<String>,
+    index_width: IndexWidth,
+    /// Whether the values are stored byte-transposed.
+    byte_transposed: bool,
+    /// The number of bytes from the start of one row of values to the next, when the
+    /// rows are padded to align them.
+    row_stride: Option<usize>,
+}
+
+/// Reads everything between the magic number and the values, byte-swapping as needed
+/// for `B`, and checks the index arrays as `options` asks.
+fn read_mtx_header<B: ByteOrder>(
+    reader: &mut impl Read,
+    input_file_name: &str,
+    options: &MtxReadOptions,
+) -> std::io::Result<MtxHeader> {
+    let version = reader.read_i32::<B>()?;
+    if version != 1 && version != FLAGS_VERSION {
+        return Err(invalid_mtx(input_file_name, format!("unsupported version {}", version)));
+    }
+    let value_type = reader.read_i32::<B>()?;
+    if value_type != f32::MTX_TYPE && value_type != f64::MTX_TYPE {
+        return Err(invalid_mtx(input_file_name, format!("unsupported value type {}", value_type)));
+    }
+    let dimensions = reader.read_i32::<B>()?;
+    if dimensions != 2 {
+        return Err(invalid_mtx(input_file_name, format!("unsupported number of dimensions {}", dimensions)));
+    }
+    let flags = if version == FLAGS_VERSION { reader.read_i32::<B>()? } else { 0 };
+    if flags & !(FLAG_METADATA | FLAG_BYTE_TRANSPOSE | FLAG_INDEX_16 | FLAG_INDEX_64 | FLAG_ROW_STRIDE) != 0
+        || flags & (FLAG_INDEX_16 | FLAG_INDEX_64) == FLAG_INDEX_16 | FLAG_INDEX_64
+        || flags & (FLAG_BYTE_TRANSPOSE | FLAG_ROW_STRIDE) == FLAG_BYTE_TRANSPOSE | FLAG_ROW_STRIDE
+    {
+        return Err(invalid_mtx(input_file_name, format!("unsupported flags {:#X}", flags)));
+    }
+    let row_layout = if flags & FLAG_ROW_STRIDE != 0 {
+        let offset = read_index_size::<B>(reader, input_file_name)?;
+        let stride = read_index_size::<B>(reader, input_file_name)?;
+        Some((offset, stride))
+    } else {
+        None
+    };
+    let origin_count = read_index_size::<B>(reader, input_file_name)?;
+    let destination_count = read_index_size::<B>(reader, input_file_name)?;
+
+    let index_width = if flags & FLAG_INDEX_16 != 0 {
+        IndexWidth::W16
+    } else if flags & FLAG_INDEX_64 != 0 {
+        IndexWidth::W64
+    } else {
+        IndexWidth::W32
+    };
+    let origins = read_index_array::<B>(reader, origin_count, index_width, input_file_name)?;
+    let destinations = read_index_array::<B>(reader, destination_count, index_width, input_file_name)?;
+    check_index_array(&origins, "origin", input_file_name, options.index_check)?;
+    check_index_array(&destinations, "destination", input_file_name, options.index_check)?;
+    let metadata = if flags & FLAG_METADATA != 0 {
+        let length = read_index_size::<B>(reader, input_file_name)?;
+        let mut bytes = vec![0u8; length];
+        reader.read_exact(&mut bytes)?;
+        Some(String::from_utf8(bytes).map_err(|_| invalid_mtx(input_file_name, "metadata is not UTF-8".to_string()))?)
+    } else {
+        None
+    };
+    let row_stride = match row_layout {
+        Some((offset, stride)) => {
+            let header_bytes = strided_header_bytes(origins.len() + destinations.len(), index_width, metadata.as_deref());
+            let value_size = if value_type == f64::MTX_TYPE { 8 } else { 4 };
+            if offset < header_bytes || stride < destinations.len() * value_size {
+                return Err(invalid_mtx(
+                    input_file_name,
+                    format!("the values cannot start at byte {} with rows every {} bytes", offset, stride),
+                ));
+            }
+            // Skip the padding that aligns the first row
+            std::io::copy(&mut Read::by_ref(reader).take((offset - header_bytes) as u64), &mut std::io::sink())?;
+            Some(stride)
+        }
+        None => None,
+    };
+    Ok(MtxHeader {
+        value_type,
+        origins,
+        destinations,
+        metadata,
+        index_width,
+        byte_transposed: flags & FLAG_BYTE_TRANSPOSE != 0,
+        row_stride,
+    })
+}
+
+/// Reads `count` zone numbers stored as integers of the given width.
+fn read_index_array<B: ByteOrder>(
+    reader: &mut impl Read,
+    count: usize,
+    width: IndexWidth,
+    input_file_name: &str,
+) -> std::io::Result<Vec<i32>> {
+    let mut zones = vec![0i32; count];
+    match width {
+        IndexWidth::W16 => {
+            let mut bytes = vec![0u8; count * width.size()];
+            reader.read_exact(&mut bytes)?;
+            for (zone, bytes) in zones.iter_mut().zip(bytes.chunks_exact(2)) {
+                *zone = B::read_i16(bytes) as i32;
+            }
+        }
+        IndexWidth::W32 => reader.read_i32_into::<B>(&mut zones)?,
+        IndexWidth::W64 => {
+            let mut bytes = vec![0u8; count * width.size()];
+            reader.read_exact(&mut bytes)?;
+            for (zone, bytes) in zones.iter_mut().zip(bytes.chunks_exact(8)) {
+                let wide = B::read_i64(bytes);
+                *zone = i32::try_from(wide)
+                    .map_err(|_| invalid_mtx(input_file_name, format!("the zone {} is too large", wide)))?;
+            }
+        }
+    }
+    Ok(zones)
+}
+
+/// Reads everything after the magic number, byte-swapping as needed for `B`. If
+/// `truncation` reports that the input ended early, the values read so far are kept.
+fn read_mtx_contents<B: ByteOrder>(
+    reader: &mut impl Read,
+    input_file_name: &str,
+    options: &MtxReadOptions,
+    truncation: Option<&TruncationReport>,
+) -> std::io::Result<MtxMatrix> {
+    let header = read_mtx_header::<B>(reader, input_file_name, options)?;
+    let values = read_f32_values::<B>(reader, &header, input_file_name, truncation)?;
+
+    Ok(MtxMatrix {
+        origins: header.origins,
+        destinations: header.destinations,
+        values,
+        metadata: header.metadata,
+    })
+}
+
+/// Reads every value of a file after its header, narrowing double-precision values to
+/// `f32`, as everything that reads an MTX file works in f32.
+fn read_f32_values<B: ByteOrder>(
+    reader: &mut impl Read,
+    header: &MtxHeader,
+    input_file_name: &str,
+    truncation: Option<&TruncationReport>,
+) -> std::io::Result<Vec<f32>> {
+    if header.value_type == f64::MTX_TYPE {
+        Ok(read_header_values::<B, f64>(reader, header, input_file_name, truncation)?
+            .into_iter()
+            .map(|value| value as f32)
+            .collect())
+    } else {
+        read_header_values::<B, f32>(reader, header, input_file_name, truncation)
+    }
+}
+
+/// Reads every value of a file after its header as `V`, leaving out the padding of
+/// its rows.
+fn read_header_values<B: ByteOrder, V: CellValue>(
+    reader: &mut impl Read,
+    header: &MtxHeader,
+    input_file_name: &str,
+    truncation: Option<&TruncationReport>,
+) -> std::io::Result<Vec<V>> {
+    let cells = header.origins.len() * header.destinations.len();
+    let transposed = header.byte_transposed;
+    match header.row_stride {
+        Some(stride) => {
+            let row_bytes = header.destinations.len() * std::mem::size_of::<V>();
+            let mut rows = StridedRows {
+                inner: reader,
+                row_bytes,
+                padding: (stride - row_bytes) as u64,
+                remaining: row_bytes,
+            };
+            read_values::<B, V>(&mut rows, cells, transposed, input_file_name, truncation)
+        }
+        None => read_values::<B, V>(reader, cells, transposed, input_file_name, truncation),
+    }
+}
+
+/// Passes on the values of a file whose rows are padded to a stride, leaving out the
+/// padding after each row.
+struct StridedRows<R> {
+    inner: R,
+    row_bytes: usize,
+    padding: u64,
+    /// The bytes of the current row still to be passed on.
+    remaining: usize,
+}
+
+impl<R: Read> Read for StridedRows<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 {
+            std::io::copy(&mut Read::by_ref(&mut self.inner).take(self.padding), &mut std::io::sink())?;
+            self.remaining = self.row_bytes;
+        }
+        let limit = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..limit])?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+/// Reads `cells` values stored in the byte order `B`, with their bytes transposed when
+/// `transposed` is set. If `truncation` reports that the input ended early, the values
+/// read so far are kept and the rest are left zero.
+fn read_values<B: ByteOrder, V: CellValue>(
+    reader: &mut impl Read,
+    cells: usize,
+    transposed: bool,
+    input_file_name: &str,
+    truncation: Option<&TruncationReport>,
+) -> std::io::Result<Vec<V>> {
+    let value_size = std::mem::size_of::<V>();
+    let mut values = vec![V::zeroed(); cells];
+    let value_bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut values);
+    let read = read_fully(reader, value_bytes)?;
+    if read < value_bytes.len() {
+        // Every value of a transposed file is missing its last bytes
+        let recovered = truncation.filter(|_| !transposed).and_then(TruncationReport::recovered_bytes);
+        let Some(recovered_bytes) = recovered else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("{} ends before all of its values", input_file_name),
+            ));
+        };
+        // Drop the partially read value so only whole values are kept
+        let recovered_cells = read / value_size;
+        value_bytes[recovered_cells * value_size..].fill(0);
+        eprintln!(
+            "Warning: {} is truncated, recovered {} of {} cells from the first {} bytes",
+            input_file_name, recovered_cells, cells, recovered_bytes
+        );
+    }
+    if transposed {
+        let planes = value_bytes.to_vec();
+        value_bytes
+            .par_chunks_mut(value_size)
+            .enumerate()
+            .for_each(|(cell, value)| {
+                for (plane, byte) in value.iter_mut().enumerate() {
+                    *byte = planes[plane * cells + cell];
+                }
+            });
+    }
+    let little_endian_data = B::read_u16(&[1, 0]) == 1;
+    if little_endian_data != cfg!(target_endian = "little") {
+        value_bytes
+            .par_chunks_mut(value_size)
+            .for_each(|value| value.reverse());
+    }
+    Ok(values)
+}
+
+/// Reads until `buffer` is full or the input ends.
+///
+/// # Returns
+/// The number of bytes read.
+fn read_fully(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(count) => read += count,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Reads the size of an index array or block, rejecting negative sizes.
+fn read_index_size<B: ByteOrder>(reader: &mut impl Read, input_file_name: &str) -> std::io::Result<usize> {
+    let size = reader.read_i32::<B>()?;
+    usize::try_from(size)
+        .map_err(|_| invalid_mtx(input_file_name, format!("negative index size {}", size)))
+}
+
+fn invalid_mtx(input_file_name: &str, reason: String) -> std::io::Error {
+    Error::UnknownFormat {
+        file: input_file_name.to_string(),
+        reason,
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_mtx_file() -> std::io::Result<()> {
+        let matrix = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        assert_eq!(matrix.origins, vec![1, 2, 3, 4]);
+        assert_eq!(matrix.destinations, vec![1, 2, 3, 4]);
+        assert_eq!(matrix.values.len(), 16);
+        assert_eq!(matrix.values[1], 0.2);
+        assert_eq!(matrix.values[15], 0.1);
+
+        // Writing it back out must reproduce the original bytes
+        let output_file = "test/test_read_output.mtx";
+        write_mtx_file(output_file, &matrix.origins, &matrix.destinations, &matrix.values, &MtxWriteOptions::default())?;
+        assert_eq!(std::fs::read(output_file)?, std::fs::read("test/test_expected.mtx")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_big_endian_round_trip() -> std::io::Result<()> {
+        let matrix = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        let output_file = "test/test_big_endian_output.mtx";
+        let options = MtxWriteOptions {
+            endianness: Endianness::Big,
+            ..MtxWriteOptions::default()
+        };
+        write_mtx_file(output_file, &matrix.origins, &matrix.destinations, &matrix.values, &options)?;
+
+        let bytes = std::fs::read(output_file)?;
+        assert_eq!(&bytes[..4], &[0xC4, 0xD4, 0xF1, 0xB2]);
+        assert_eq!(&bytes[16..20], &[0, 0, 0, 4]);
+        assert_eq!(bytes.len(), std::fs::read("test/test_expected.mtx")?.len());
+
+        // Detected from the magic number, or given explicitly
+        assert_eq!(read_mtx_file(output_file, &MtxReadOptions::default())?, matrix);
+        assert_eq!(read_mtx_file(output_file, &MtxReadOptions { endianness: Some(Endianness::Big), ..MtxReadOptions::default() })?, matrix);
+        assert!(read_mtx_file(output_file, &MtxReadOptions { endianness: Some(Endianness::Little), ..MtxReadOptions::default() }).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_swapped_values_written_in_blocks() -> std::io::Result<()> {
+        // Spans more than one block, ending part way through the last
+        let values: Vec<f32> = (0..SWAP_BLOCK_VALUES + 3).map(|value| value as f32 * 0.5).collect();
+        let swapped = match Endianness::native() {
+            Endianness::Little => Endianness::Big,
+            Endianness::Big => Endianness::Little,
+        };
+        let mut streamed = Vec::new();
+        write_values(&mut streamed, &values, swapped)?;
+        let collected: Vec<u8> = values
+            .iter()
+            .flat_map(|value| match swapped {
+                Endianness::Little => value.to_le_bytes(),
+                Endianness::Big => value.to_be_bytes(),
+            })
+            .collect();
+        assert_eq!(streamed, collected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_byte_transpose_round_trip() -> std::io::Result<()> {
+        // Travel times between 200 zones, whose exponent bytes are mostly the same
+        let zones: Vec<i32> = (1..=200).collect();
+        let values: Vec<f32> = (0..zones.len() * zones.len())
+            .map(|cell| 20.0 + (cell as f32 * 0.37).sin() * 15.0 + (cell % 200) as f32 * 0.3)
+            .collect();
+        let plain_file = "test/test_byte_transpose_plain_output.mtx.gz";
+        write_mtx_file(plain_file, &zones, &zones, &values, &MtxWriteOptions::default())?;
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let output_file = "test/test_byte_transpose_output.mtx.gz";
+            let options = MtxWriteOptions {
+                endianness,
+                gzip_filter: GzipFilter::ByteTranspose,
+                ..MtxWriteOptions::default()
+            };
+            write_mtx_file(output_file, &zones, &zones, &values, &options)?;
+            let matrix = read_mtx_file(output_file, &MtxReadOptions::default())?;
+            assert_eq!(matrix.values, values);
+            assert!(std::fs::metadata(output_file)?.len() < std::fs::metadata(plain_file)?.len());
+
+            let (_, _, cells) = read_mtx_cells(output_file, &MtxReadOptions::default(), |_, _| Ok(vec![40_000 - 1, 7]))?;
+            assert_eq!(cells, vec![values[40_000 - 1], values[7]]);
+
+            let doubles: Vec<f64> = values.iter().map(|&value| value as f64).collect();
+            write_mtx_file(output_file, &zones, &zones, &doubles, &options)?;
+            assert_eq!(read_mtx_file(output_file, &MtxReadOptions::default())?.values, values);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_width_round_trip() -> std::io::Result<()> {
+        let matrix = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        let plain_length = std::fs::metadata("test/test_expected.mtx")?.len();
+        for endianness in [Endianness::Little, Endianness::Big] {
+            for (index_width, zone_size) in [(IndexWidth::W16, 2), (IndexWidth::W64, 8)] {
+                let output_file = "test/test_index_width_output.mtx";
+                let options = MtxWriteOptions {
+                    endianness,
+                    index_width,
+                    ..MtxWriteOptions::default()
+                };
+                write_mtx_file(output_file, &matrix.origins, &matrix.destinations, &matrix.values, &options)?;
+                // The flags field is added, and each of the 8 zone numbers changes size
+                let expected_length = plain_length as i64 + 4 + 8 * (zone_size - 4);
+                assert_eq!(std::fs::metadata(output_file)?.len() as i64, expected_length);
+                assert_eq!(read_mtx_file(output_file, &MtxReadOptions::default())?, matrix);
+                let (_, _, cells) = read_mtx_cells(output_file, &MtxReadOptions::default(), |_, _| Ok(vec![5]))?;
+                assert_eq!(cells, vec![matrix.values[5]]);
+            }
+        }
+
+        let zones = [1, 40_000];
+        let error = write_mtx_file(
+            "test/test_index_width_output.mtx",
+            &zones,
+            &zones,
+            &[0.0f32; 4],
+            &MtxWriteOptions { index_width: IndexWidth::W16, ..MtxWriteOptions::default() },
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("40000"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_round_trip() -> std::io::Result<()> {
+        let mut matrix = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        assert_eq!(matrix.metadata, None);
+        matrix.metadata = Some("source: test.csv\ntool: csv_to_mtx".to_string());
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let output_file = "test/test_metadata_output.mtx.gz";
+            let options = MtxWriteOptions {
+                endianness,
+                metadata: matrix.metadata.clone(),
+                ..MtxWriteOptions::default()
+            };
+            write_mtx_file(output_file, &matrix.origins, &matrix.destinations, &matrix.values, &options)?;
+            assert_eq!(read_mtx_file(output_file, &MtxReadOptions::default())?, matrix);
+        }
+
+        let output_file = "test/test_metadata_output.mtx";
+        let options = MtxWriteOptions {
+            metadata: matrix.metadata.clone(),
+            ..MtxWriteOptions::default()
+        };
+        write_mtx_file(output_file, &matrix.origins, &matrix.destinations, &matrix.values, &options)?;
+        let bytes = std::fs::read(output_file)?;
+        assert_eq!(&bytes[4..8], &2i32.to_le_bytes());
+        assert_eq!(&bytes[16..20], &1i32.to_le_bytes());
+        assert_eq!(read_mtx_file(output_file, &MtxReadOptions::default())?, matrix);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsync() -> std::io::Result<()> {
+        let matrix = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        let options = MtxWriteOptions {
+            fsync: true,
+            ..MtxWriteOptions::default()
+        };
+        for output_file in ["test/test_fsync_output.mtx", "test/test_fsync_output.mtx.gz"] {
+            write_mtx_file(output_file, &matrix.origins, &matrix.destinations, &matrix.values, &options)?;
+            assert_eq!(read_mtx_file(output_file, &MtxReadOptions::default())?, matrix);
+            assert!(!std::path::Path::new(&format!("{}.tmp", output_file)).exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_zone_and_empty_matrices() -> std::io::Result<()> {
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let options = MtxWriteOptions {
+                endianness,
+                ..MtxWriteOptions::default()
+            };
+            let output_file = "test/test_single_zone_output.mtx";
+            write_mtx_file(output_file, &[7], &[7], &[2.5f32], &options)?;
+            let bytes = std::fs::read(output_file)?;
+            // The header, one zone in each index and a single value
+            assert_eq!(bytes.len(), 6 * 4 + 2 * 4 + 4);
+            assert_eq!(&bytes[16..20], &endianness.i32_bytes(1));
+            assert_eq!(&bytes[20..24], &endianness.i32_bytes(1));
+            assert_eq!(&bytes[24..28], &endianness.i32_bytes(7));
+            assert_eq!(&bytes[28..32], &endianness.i32_bytes(7));
+            let matrix = read_mtx_file(output_file, &MtxReadOptions::default())?;
+            assert_eq!((matrix.origins, matrix.destinations, matrix.values), (vec![7], vec![7], vec![2.5]));
+
+            let output_file = "test/test_empty_output.mtx";
+            write_mtx_file::<f32>(output_file, &[], &[], &[], &options)?;
+            assert_eq!(std::fs::read(output_file)?.len(), 6 * 4);
+            let matrix = read_mtx_file(output_file, &MtxReadOptions::default())?;
+            assert!(matrix.origins.is_empty() && matrix.destinations.is_empty() && matrix.values.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_gzipped_mtx() -> std::io::Result<()> {
+        let zones: Vec<i32> = (1..=200).collect();
+        let values: Vec<f32> = (0..zones.len() * zones.len()).map(|i| i as f32).collect();
+        let output_file = "test/test_truncated_output.mtx.gz";
+        write_mtx_file(output_file, &zones, &zones, &values, &MtxWriteOptions::default())?;
+        let bytes = std::fs::read(output_file)?;
+        std::fs::write(output_file, &bytes[..bytes.len() / 2])?;
+
+        assert!(read_mtx_file(output_file, &MtxReadOptions::default()).is_err());
+        let options = MtxReadOptions {
+            tolerate_truncation: true,
+            ..MtxReadOptions::default()
+        };
+        let matrix = read_mtx_file(output_file, &options)?;
+        assert_eq!(matrix.origins, zones);
+        assert_eq!(matrix.values.len(), values.len());
+        let recovered = matrix.values.iter().rposition(|&value| value != 0.0).unwrap() + 1;
+        assert!(recovered < values.len());
+        assert_eq!(matrix.values[..recovered], values[..recovered]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_mtx_sample() -> std::io::Result<()> {
+        let zones: Vec<i32> = (1..=20).collect();
+        let values: Vec<f32> = (0..zones.len() * zones.len()).map(|i| i as f32 + 0.5).collect();
+        let output_file = "test/test_verify_sample_output.mtx";
+        write_mtx_file(output_file, &zones, &zones, &values, &MtxWriteOptions::default())?;
+        assert_eq!(verify_mtx_sample(output_file, &zones, &zones, &values, &MtxReadOptions::default(), 50, 7)?, 50);
+        assert_eq!(verify_mtx_sample(output_file, &zones, &zones, &values, &MtxReadOptions::default(), 1000, 7)?, values.len());
+
+        // Miswrite the value of the cell from zone 3 to zone 5
+        let mut bytes = std::fs::read(output_file)?;
+        let cell = 2 * zones.len() + 4;
+        let offset = 6 * 4 + 2 * zones.len() * 4 + cell * 4;
+        bytes[offset..offset + 4].copy_from_slice(&1234.0f32.to_le_bytes());
+        std::fs::write(output_file, &bytes)?;
+        let e = verify_mtx_sample(output_file, &zones, &zones, &values, &MtxReadOptions::default(), values.len(), 7).unwrap_err();
+        assert!(e.to_string().contains("zone 3 to zone 5"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_align() -> std::io::Result<()> {
+        let zones: Vec<i32> = (1..=5).collect();
+        let values: Vec<f32> = (0..25).map(|i| i as f32 + 0.25).collect();
+        for (endianness, output_file) in [
+            (Endianness::Little, "test/test_row_align_output.mtx"),
+            (Endianness::Big, "test/test_row_align_output.mtx.gz"),
+        ] {
+            let options = MtxWriteOptions {
+                endianness,
+                metadata: Some("aligned".to_string()),
+                row_align: Some(64),
+                ..MtxWriteOptions::default()
+            };
+            write_mtx_file(output_file, &zones, &zones, &values, &options)?;
+            let read = read_mtx_file(output_file, &MtxReadOptions::default())?;
+            assert_eq!(read.values, values);
+            assert_eq!(read.metadata.as_deref(), Some("aligned"));
+            let (_, _, cells) = read_mtx_cells(output_file, &MtxReadOptions::default(), |_, _| Ok(vec![24, 6, 5, 6]))?;
+            assert_eq!(cells, vec![24.25, 6.25, 5.25, 6.25]);
+        }
+
+        // Every row of the uncompressed file starts on the alignment
+        let bytes = std::fs::read("test/test_row_align_output.mtx")?;
+        let field = |position: usize| i32::from_le_bytes(bytes[position..position + 4].try_into().unwrap()) as usize;
+        let (offset, stride) = (field(20), field(24));
+        assert_eq!((offset % 64, stride), (0, 64));
+        assert_eq!(bytes.len(), offset + 5 * stride);
+        for row in 0..5 {
+            let start = offset + row * stride;
+            assert_eq!(f32::from_le_bytes(bytes[start..start + 4].try_into().unwrap()), values[row * 5]);
+        }
+
+        let options = MtxWriteOptions {
+            row_align: Some(48),
+            ..MtxWriteOptions::default()
+        };
+        assert!(write_mtx_file("test/test_row_align_output.mtx", &zones, &zones, &values, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_magic() -> std::io::Result<()> {
+        let zones = vec![1, 2];
+        let values = vec![1.0f32, 2.0, 3.0, 4.0];
+        let output_file = "test/test_custom_magic_output.mtx";
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let options = MtxWriteOptions {
+                endianness,
+                magic: Some(0x1234ABCD),
+                ..MtxWriteOptions::default()
+            };
+            write_mtx_file(output_file, &zones, &zones, &values, &options)?;
+            let bytes = std::fs::read(output_file)?;
+            let magic = match endianness {
+                Endianness::Little => 0x1234ABCDu32.to_le_bytes(),
+                Endianness::Big => 0x1234ABCDu32.to_be_bytes(),
+            };
+            assert_eq!(bytes[..4], magic);
+
+            let e = Error::from(read_mtx_file(output_file, &MtxReadOptions::default()).unwrap_err());
+            assert!(matches!(e, Error::MagicMismatch { .. }), "{:?}", e);
+            let read_options = MtxReadOptions {
+                expect_magic: Some(0x1234ABCD),
+                ..MtxReadOptions::default()
+            };
+            let matrix = read_mtx_file(output_file, &read_options)?;
+            assert_eq!(matrix.values, values);
+        }
+        // A reader expecting the custom magic rejects the public one
+        write_mtx_file(output_file, &zones, &zones, &values, &MtxWriteOptions::default())?;
+        let read_options = MtxReadOptions {
+            expect_magic: Some(0x1234ABCD),
+            ..MtxReadOptions::default()
+        };
+        assert!(read_mtx_file(output_file, &read_options).is_err());
+
+        assert_eq!(parse_magic("0x1234abcd"), Ok(0x1234ABCD));
+        assert_eq!(parse_magic("C4D4F1B2"), Ok(MTX_MAGIC));
+        assert!(parse_magic("0x123456789").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_check() -> std::io::Result<()> {
+        let output_file = "test/test_index_check_output.mtx";
+        let read = |index_check| read_mtx_file(output_file, &MtxReadOptions { index_check, ..MtxReadOptions::default() });
+        // Zone 2 is listed twice among the origins
+        write_mtx_file(output_file, &[1, 2, 2], &[1, 2, 3], &[0.0f32; 9], &MtxWriteOptions::default())?;
+        assert_eq!(read(IndexCheck::Warn)?.origins, vec![1, 2, 2]);
+        let e = read(IndexCheck::Unique).unwrap_err();
+        assert!(e.to_string().contains("the origin index array lists zone 2 more than once"));
+        assert!(read(IndexCheck::Sorted).is_err());
+        assert!(read_mtx_zones(output_file, &MtxReadOptions { index_check: IndexCheck::Unique, ..MtxReadOptions::default() }).is_err());
+
+        // Unique zones out of order, as written with an ordered zones file
+        write_mtx_file(output_file, &[1, 2, 3], &[3, 1, 2], &[0.0f32; 9], &MtxWriteOptions::default())?;
+        read(IndexCheck::Unique)?;
+        let e = read(IndexCheck::Sorted).unwrap_err();
+        assert!(e.to_string().contains("the destination index array is not strictly increasing, as zone 1 follows zone 3"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_blocks() -> std::io::Result<()> {
+        let zones: Vec<i32> = (1..=200).collect();
+        let values: Vec<f32> = (0..zones.len() * zones.len()).map(|i| (i % 97) as f32).collect();
+        let single_file = "test/test_gzip_single_output.mtx.gz";
+        let blocks_file = "test/test_gzip_blocks_output.mtx.gz";
+        write_mtx_file(single_file, &zones, &zones, &values, &MtxWriteOptions::default())?;
+        let options = MtxWriteOptions {
+            gzip_blocks: Some(16),
+            ..MtxWriteOptions::default()
+        };
+        write_mtx_file(blocks_file, &zones, &zones, &values, &options)?;
+        assert_ne!(std::fs::read(single_file)?, std::fs::read(blocks_file)?);
+
+        let decompress = |file: &str| -> std::io::Result<Vec<u8>> {
+            let mut bytes = Vec::new();
+            MultiGzDecoder::new(File::open(file)?).read_to_end(&mut bytes)?;
+            Ok(bytes)
+        };
+        assert_eq!(decompress(blocks_file)?, decompress(single_file)?);
+
+        // The first member holds the header, both index arrays and the first 16 rows
+        let mut first_member = Vec::new();
+        flate2::read::GzDecoder::new(File::open(blocks_file)?).read_to_end(&mut first_member)?;
+        assert_eq!(first_member.len(), 6 * 4 + 2 * 200 * 4 + 16 * 200 * 4);
+
+        let matrix = read_mtx_file(blocks_file, &MtxReadOptions::default())?;
+        assert_eq!(matrix.values, values);
+        Ok(())
+    }
+}
@@ -0,0 +1,544 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::columns::ColumnMapping;
+use crate::convert::OutputFormat;
+use crate::csv_output::FloatFormat;
+use crate::expression::weighted_sum_expression;
+use crate::input::{ReadOptions, RectOrientation};
+use crate::matrix::{CellRules, Normalize, Symmetrize, Triangle};
+use crate::mtx::{Endianness, GzipFilter, IndexWidth};
+use crate::rejects::RejectLog;
+use crate::value_transform::{CellAggregate, ClampNegative, TimeUnit, ValueRange, ValueTransform};
+use crate::zones::{read_zone_map, CompareZones, ZoneSource, ZonesFile, ZonesMode};
+
+/// Options controlling how an input CSV file is converted into an MTX file.
+/// These can be loaded from a TOML config file and are then overridden by any
+/// flags given on the command line. Keys missing from the config file fall back
+/// to the defaults below.
+///
+/// When embedding the conversion, the options can be built up from the defaults:
+///
+/// ```
+/// use csv_to_mtx::convert::convert_csv_to_mtx;
+/// use csv_to_mtx::options::ConversionOptions;
+///
+/// let options = ConversionOptions::default()
+///     .delimiter(',')
+///     .fill(-1.0)
+///     .min_value(0.2)
+///     .force(true);
+/// let output_file = std::env::temp_dir().join("csv_to_mtx_doc_example.mtx");
+/// convert_csv_to_mtx("test/test.csv", output_file.to_str().unwrap(), &options)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConversionOptions {
+    /// The character separating fields in the input and zones CSV files.
+    pub delimiter: char,
+    /// Optional path to a CSV file listing the zone system to use.
+    pub zones_file: Option<String>,
+    /// How the zones file combines with the zones found in the data.
+    pub zones_mode: ZonesMode,
+    /// Optional source of the zones, or `None` to use the zones file when one is given
+    /// and otherwise the input's zones.
+    pub zone_source: Option<ZoneSource>,
+    /// Optional path to a CSV file listing the only zones to use, in the order the
+    /// matrix is written in. Cells of other zones are dropped.
+    pub select_zones_file: Option<String>,
+    /// If the first row of the zones file is a header rather than a zone.
+    pub zones_header: bool,
+    /// The file format to write.
+    pub format: OutputFormat,
+    /// The byte order to write the output MTX file in.
+    pub output_endianness: Endianness,
+    /// How the values of the output MTX file are laid out so that they compress better.
+    pub gzip_filter: GzipFilter,
+    /// The integer width of the zone numbers in the index arrays of the output MTX file.
+    pub index_width: IndexWidth,
+    /// Starts a new gzip member every this many rows of a `.mtx.gz` output, so the
+    /// blocks can be decompressed in parallel.
+    pub gzip_blocks: Option<usize>,
+    /// The magic number to write at the start of the output MTX file instead of the
+    /// public one, for a private format sharing the layout.
+    pub magic: Option<u32>,
+    /// Pads each row of values of the output MTX file so that it starts at a multiple
+    /// of this many bytes, for readers mapping the rows straight into memory.
+    pub row_align: Option<usize>,
+    /// Optional path to write the sum of each origin's row to as a CSV file.
+    pub row_sums: Option<String>,
+    /// Optional path to write the sum of each destination's column to as a CSV file.
+    pub col_sums: Option<String>,
+    /// Optional path to write the intrazonal values to as a CSV file.
+    pub diagonal: Option<String>,
+    /// Overwrite the output file if it already exists.
+    pub force: bool,
+    /// Stream 3-column input straight into the matrix instead of holding every row in memory.
+    pub low_memory: bool,
+    /// Sync the MTX file to disk before it replaces the output file.
+    pub fsync: bool,
+    /// Store the source file, creation time and tool version in the output file.
+    pub stamp_metadata: bool,
+    /// Write the same bytes on every run, leaving the creation time out of the metadata.
+    pub reproducible: bool,
+    /// Write the same bytes on every platform and with any number of threads, as well
+    /// as on every run. This implies `reproducible`.
+    pub canonical_order: bool,
+    /// The value of cells without any data.
+    pub fill: f32,
+    /// Values below this are replaced with the fill value.
+    pub min_value: Option<f32>,
+    /// Values above this are clamped to it.
+    pub max_value: Option<f32>,
+    /// Only print the number of non-zero cells instead of writing the matrix.
+    pub count_only: bool,
+    /// Only print how the conversion would run, such as the input's format and the
+    /// zones, without converting.
+    pub explain: bool,
+    /// Keep the records read before a gzip input turns out to be truncated instead of failing.
+    pub tolerate_truncation: bool,
+    /// Split a rectangular input that stacks several matrices into a numbered output file per block.
+    pub multi_block: bool,
+    /// Optional column of a long-format input, by 0-based position or header name, whose
+    /// value groups the rows into a separate output file per value.
+    pub pivot_col: Option<String>,
+    /// Optional `zone,partition` CSV file splitting the output into an MTX file per
+    /// partition, holding the rows of its origins and every destination.
+    pub partition_file: Option<String>,
+    /// How the two triangles of the matrix are combined before it is written.
+    pub symmetrize: Symmetrize,
+    /// Which triangle of the matrix is written, zeroing the other cells.
+    pub triangle: Triangle,
+    /// Which sum each cell is divided by after the matrix is built, such as `row` for
+    /// the share of each origin's trips.
+    pub normalize: Normalize,
+    /// Optional quantile of the non-zero cells, such as `0.95`, below which cells are
+    /// zeroed so only the largest flows are kept.
+    pub top_percentile: Option<f64>,
+    /// Add padding zones, numbered after the largest zone, until the number of zones
+    /// is a power of two. Their cells take the fill value.
+    pub pad_to_pow2: bool,
+    /// Optional expression computing each row's value from the named columns of an input
+    /// with a header row, such as `distance / time`.
+    pub value_expr: Option<String>,
+    /// Optional weights such as `auto=0.6,transit=0.4`, taking each row's value as the
+    /// weighted sum of the named columns of an input with a header row.
+    pub value_weights: Option<String>,
+    /// Read a rectangular input without an origin column, taking each row's origin from
+    /// the destination in the same position of the header row.
+    pub implicit_origins: bool,
+    /// Whether the header row of a rectangular input lists the destinations or the origins.
+    pub rect_orientation: RectOrientation,
+    /// Values such as `["NA", "null"]` that mark a missing value, leaving the cell as the fill value.
+    pub na_values: Vec<String>,
+    /// Accept zone labels written as integral decimals, such as `12.0`, as spreadsheets often export them.
+    pub lenient_zone_ids: bool,
+    /// Ask at the terminal how to read an input whose format could be detected either way.
+    pub interactive: bool,
+    /// Optional text before the number of every zone label, such as the `Z` of `Z00123`.
+    pub zone_prefix: Option<String>,
+    /// Optional text after the number of every zone label.
+    pub zone_suffix: Option<String>,
+    /// Optional `name,zone` CSV file giving the zone number of each zone name labelling
+    /// the input, such as `Downtown`, in either format.
+    pub zone_map: Option<String>,
+    /// Optional `zone,district` CSV file whose districts the flows are summed into,
+    /// writing a matrix over the districts rather than the zones.
+    pub aggregate: Option<String>,
+    /// Optional built-in transform turning the text of each value into a number, such as
+    /// `minutes-colon` reading `3:30` as 3.5.
+    pub value_transform: Option<ValueTransform>,
+    /// The unit the `hms` value transform reads `HH:MM:SS` times in.
+    pub time_unit: TimeUnit,
+    /// What is done with negative values, for feeds where they are codes rather than data.
+    pub clamp_negative: ClampNegative,
+    /// Optional `min:max` range of plausible values, outside of which a value is reported,
+    /// or is an error when strict.
+    pub value_range: Option<String>,
+    /// Optional text separating the values of rectangular cells that hold several, such as `|`.
+    pub cell_split: Option<String>,
+    /// How the values of a split rectangular cell are combined.
+    pub cell_agg: CellAggregate,
+    /// The number of lines at the start of the input to discard, such as a title or a date.
+    pub skip_rows: usize,
+    /// Fail unless the row labels of a rectangular input are the same zones as its header.
+    pub require_square: bool,
+    /// Skip the rows of a 3-column input whose value is exactly zero.
+    pub drop_zero_rows: bool,
+    /// Which columns hold the origin, destination and value, such as
+    /// `origin=4,destination=3,value=2`, by 0-based position or by header name.
+    pub columns: Option<String>,
+    /// The first row of an input read with `columns` is a header naming its columns.
+    pub header: bool,
+    /// Optional path to write a `zone,new_id` mapping to, renumbering the zones written
+    /// with the matrix to 1..N in sorted order.
+    pub renumber: Option<String>,
+    /// Write a double-precision matrix when a value cannot be stored exactly as a 32-bit float.
+    pub auto_promote: bool,
+    /// How the numbers in the sums and diagonal CSV files are written.
+    pub float_format: FloatFormat,
+    /// Quantiles of the non-zero cell values to print, such as `[0.5, 0.95]`.
+    pub quantiles: Vec<f64>,
+    /// The number of non-zero cells of the built matrix to print, for checking the conversion.
+    pub sample_cells: Option<usize>,
+    /// The number of largest non-zero cells of the built matrix to report, largest first.
+    pub top_flows: Option<usize>,
+    /// Optional CSV file to write the top flows to instead of standard error.
+    pub top_flows_file: Option<String>,
+    /// The number of randomly chosen cells of the written MTX file to read back and
+    /// check against the built matrix.
+    pub verify_sample: Option<usize>,
+    /// Print the estimated sizes of the rows, dense matrix and write buffers.
+    pub profile_memory: bool,
+    /// Write the MTX file twice, as `.le.mtx` and `.be.mtx`, in each byte order.
+    pub dual_endian: bool,
+    /// Optional path to also write the transpose of the matrix to, in the same format.
+    pub also_transpose: Option<String>,
+    /// Optional CSV file listing every record left out of the matrix, with its line
+    /// and the reason.
+    pub reject_log: Option<String>,
+    /// The most zones the matrix may have, failing before it is allocated when there are more.
+    pub max_zones: Option<usize>,
+    /// Optional base CSV file whose matrix is subtracted from the input's, over the zones of both.
+    pub relative_to: Option<String>,
+    /// Whether `relative_to` requires the input and base file to have the same zones,
+    /// or compares them over the zones of both.
+    pub compare_zones: CompareZones,
+    /// The number of threads reading the input and building the matrix, or `None` for
+    /// rayon's global pool.
+    pub read_threads: Option<usize>,
+    /// The number of threads writing the matrix and its other outputs, or `None` for
+    /// rayon's global pool.
+    pub write_threads: Option<usize>,
+    /// Treat suspicious input, such as zone numbers far larger than the number of zones, as an error.
+    pub strict: bool,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        ConversionOptions {
+            delimiter: ',',
+            zones_file: None,
+            select_zones_file: None,
+            zones_mode: ZonesMode::Replace,
+            zone_source: None,
+            zones_header: false,
+            format: OutputFormat::Mtx,
+            output_endianness: Endianness::Little,
+            gzip_filter: GzipFilter::None,
+            index_width: IndexWidth::W32,
+            gzip_blocks: None,
+            magic: None,
+            row_align: None,
+            row_sums: None,
+            col_sums: None,
+            diagonal: None,
+            force: false,
+            low_memory: false,
+            fsync: false,
+            stamp_metadata: false,
+            reproducible: false,
+            canonical_order: false,
+            fill: 0.0,
+            min_value: None,
+            max_value: None,
+            count_only: false,
+            explain: false,
+            tolerate_truncation: false,
+            multi_block: false,
+            pivot_col: None,
+            partition_file: None,
+            symmetrize: Symmetrize::None,
+            triangle: Triangle::Full,
+            normalize: Normalize::None,
+            top_percentile: None,
+            pad_to_pow2: false,
+            value_expr: None,
+            value_weights: None,
+            implicit_origins: false,
+            rect_orientation: RectOrientation::DestHeader,
+            na_values: Vec::new(),
+            lenient_zone_ids: false,
+            interactive: false,
+            zone_prefix: None,
+            zone_suffix: None,
+            zone_map: None,
+            aggregate: None,
+            value_transform: None,
+            time_unit: TimeUnit::Minutes,
+            clamp_negative: ClampNegative::Keep,
+            value_range: None,
+            cell_split: None,
+            cell_agg: CellAggregate::Sum,
+            skip_rows: 0,
+            require_square: false,
+            drop_zero_rows: false,
+            columns: None,
+            header: false,
+            renumber: None,
+            auto_promote: false,
+            float_format: FloatFormat::Shortest,
+            quantiles: Vec::new(),
+            sample_cells: None,
+            verify_sample: None,
+            profile_memory: false,
+            dual_endian: false,
+            also_transpose: None,
+            top_flows: None,
+            top_flows_file: None,
+            reject_log: None,
+            max_zones: None,
+            relative_to: None,
+            compare_zones: CompareZones::Match,
+            read_threads: None,
+            write_threads: None,
+            strict: false,
+        }
+    }
+}
+
+impl ConversionOptions {
+    /// Loads the options from a TOML config file.
+    ///
+    /// # Arguments
+    /// * `config_file` - The path to the TOML config file.
+    ///
+    /// # Returns
+    /// The options described by the file, with defaults for any missing keys.
+    pub fn from_config_file(config_file: &str) -> std::io::Result<ConversionOptions> {
+        let contents = fs::read_to_string(config_file)?;
+        toml::from_str(&contents).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid config file {}: {}", config_file, e),
+            )
+        })
+    }
+
+    /// Sets the character separating fields in the input and zones CSV files.
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets the CSV file listing the zone system, and how it combines with the zones in the data.
+    pub fn zones(mut self, zones_file: &str, zones_mode: ZonesMode) -> Self {
+        self.zones_file = Some(zones_file.to_string());
+        self.zones_mode = zones_mode;
+        self
+    }
+
+    /// Sets the file format to write.
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the byte order to write the output MTX file in.
+    pub fn output_endianness(mut self, endianness: Endianness) -> Self {
+        self.output_endianness = endianness;
+        self
+    }
+
+    /// Sets the value of cells without any data.
+    pub fn fill(mut self, fill: f32) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// Replaces values below `min_value` with the fill value.
+    pub fn min_value(mut self, min_value: f32) -> Self {
+        self.min_value = Some(min_value);
+        self
+    }
+
+    /// Clamps values above `max_value` to it.
+    pub fn max_value(mut self, max_value: f32) -> Self {
+        self.max_value = Some(max_value);
+        self
+    }
+
+    /// Sets whether an existing output file is overwritten.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Sets whether a 3-column input is streamed into the matrix instead of read into memory.
+    pub fn low_memory(mut self, low_memory: bool) -> Self {
+        self.low_memory = low_memory;
+        self
+    }
+
+    /// Sets whether a double-precision matrix is written when a value cannot be stored
+    /// exactly as a 32-bit float.
+    pub fn auto_promote(mut self, auto_promote: bool) -> Self {
+        self.auto_promote = auto_promote;
+        self
+    }
+
+    /// Sets how the two triangles of the matrix are combined and which of them is written.
+    pub fn symmetry(mut self, symmetrize: Symmetrize, triangle: Triangle) -> Self {
+        self.symmetrize = symmetrize;
+        self.triangle = triangle;
+        self
+    }
+
+    /// Sets whether suspicious input is an error rather than a warning.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Limits the number of zones the matrix may have.
+    pub fn max_zones(mut self, max_zones: usize) -> Self {
+        self.max_zones = Some(max_zones);
+        self
+    }
+
+    /// Gets the zones file to read, if there is one. The zone source decides whether a
+    /// zones file that was given is read, and how.
+    pub fn zones_file(&self) -> std::io::Result<Option<ZonesFile<'_>>> {
+        let source = match self.zone_source {
+            None => ZoneSource::File,
+            Some(ZoneSource::Data) => return Ok(None),
+            Some(ZoneSource::Range) => {
+                return Ok(Some(ZonesFile {
+                    source: ZoneSource::Range,
+                    ..ZonesFile::new("")
+                }));
+            }
+            Some(source) if self.zones_file.is_none() && self.select_zones_file.is_none() => {
+                let name = if source == ZoneSource::Mtx { "mtx" } else { "file" };
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("--zones {} needs a zones file to read the zones from", name),
+                ));
+            }
+            Some(source) => source,
+        };
+        let delimiter = self.delimiter_byte()?;
+        match (&self.zones_file, &self.select_zones_file) {
+            (Some(_), Some(_)) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "A zones file and --select-zones-file cannot be used together",
+            )),
+            (_, Some(path)) => Ok(Some(ZonesFile {
+                delimiter,
+                has_header: self.zones_header,
+                ordered: true,
+                source,
+                ..ZonesFile::new(path)
+            })),
+            (path, None) => Ok(path.as_deref().map(|path| ZonesFile {
+                delimiter,
+                has_header: self.zones_header,
+                mode: self.zones_mode,
+                source,
+                ..ZonesFile::new(path)
+            })),
+        }
+    }
+
+    /// Gets the expression computing each row's value, from `value_expr` or `value_weights`.
+    pub fn value_expression(&self) -> std::io::Result<Option<String>> {
+        match (&self.value_expr, &self.value_weights) {
+            (Some(_), Some(_)) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--value-weights computes the value itself and cannot be combined with --value-expr",
+            )),
+            (Some(value_expr), None) => Ok(Some(value_expr.clone())),
+            (None, Some(value_weights)) => weighted_sum_expression(value_weights).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Gets the options for reading the input CSV file.
+    pub fn read_options(&self) -> std::io::Result<ReadOptions> {
+        let value_expression = self.value_expression()?;
+        if self.columns.is_some() && (self.implicit_origins || value_expression.is_some()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--columns reads a 3-column layout and cannot be combined with --implicit-origins or --value-expr",
+            ));
+        }
+        if self.implicit_origins && value_expression.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--implicit-origins reads a rectangular input and cannot be combined with --value-expr",
+            ));
+        }
+        if self.value_transform.is_some() && value_expression.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--value-transform reads a single value column and cannot be combined with --value-expr",
+            ));
+        }
+        if self.time_unit != TimeUnit::Minutes && self.value_transform != Some(ValueTransform::Hms) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--time-unit sets the unit of --value-transform hms and cannot be used with other values",
+            ));
+        }
+        Ok(ReadOptions {
+            delimiter: self.delimiter_byte()?,
+            tolerate_truncation: self.tolerate_truncation,
+            value_expression,
+            implicit_origins: self.implicit_origins,
+            drop_zero_rows: self.drop_zero_rows,
+            keep_zero_cells: self.fill != 0.0,
+            columns: self.columns.as_deref().map(ColumnMapping::parse).transpose()?,
+            has_header: self.header,
+            rect_orientation: self.rect_orientation,
+            na_values: self.na_values.clone(),
+            lenient_zone_ids: self.lenient_zone_ids,
+            interactive: self.interactive,
+            value_transform: self.value_transform,
+            time_unit: self.time_unit,
+            clamp_negative: self.clamp_negative,
+            skip_rows: self.skip_rows,
+            require_square: self.require_square,
+            zone_prefix: self.zone_prefix.clone().unwrap_or_default(),
+            zone_suffix: self.zone_suffix.clone().unwrap_or_default(),
+            strict: self.strict,
+            cell_split: self.cell_split.clone(),
+            cell_aggregate: self.cell_agg,
+            zone_map: match &self.zone_map {
+                Some(path) => read_zone_map(&ZonesFile {
+                    delimiter: self.delimiter_byte()?,
+                    ..ZonesFile::new(path)
+                })?,
+                None => HashMap::new(),
+            },
+            reject_log: self.reject_log.as_ref().map(|_| RejectLog::default()),
+        })
+    }
+
+    /// Gets the rules for the values placed in the matrix.
+    pub fn cell_rules(&self) -> CellRules {
+        CellRules {
+            fill: self.fill,
+            min_value: self.min_value,
+            max_value: self.max_value,
+        }
+    }
+
+    /// Gets the range of plausible values, if one was given.
+    pub fn value_range(&self) -> std::io::Result<Option<ValueRange>> {
+        self.value_range.as_deref().map(ValueRange::parse).transpose()
+    }
+
+    /// Gets the delimiter as the single byte the csv reader expects.
+    pub fn delimiter_byte(&self) -> std::io::Result<u8> {
+        if self.delimiter.is_ascii() {
+            Ok(self.delimiter as u8)
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The delimiter '{}' must be a single ASCII character", self.delimiter),
+            ))
+        }
+    }
+}
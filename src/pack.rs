@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::MultiGzDecoder;
+
+use crate::mtx::{read_mtx_from, write_output, MtxMatrix, MtxReadOptions};
+
+/// The magic number at the start of every container of several matrices.
+pub const PACK_MAGIC: [u8; 4] = *b"MTXP";
+
+/// The version of the container layout written by `pack_mtx_files`.
+const PACK_VERSION: u32 = 1;
+
+/// Packs several MTX files into a single container file. The container starts with
+/// `PACK_MAGIC`, its version and the number of matrices, followed by a table giving
+/// each matrix's name and the offset and length of its contents, all little-endian.
+/// Each matrix is then stored uncompressed with exactly the bytes of its MTX file, so
+/// its byte order, precision and metadata are kept.
+///
+/// # Arguments
+/// * `input_files` - The MTX files to pack, named by their file name without the
+///   `.mtx` or `.mtx.gz` extension.
+/// * `output_file` - The path to write the container to.
+/// * `read_options` - The byte order of the input files, checked before they are packed.
+/// * `fsync` - Sync the container to disk before it replaces the output file. Like an
+///   MTX file, it is written through a temporary file and compressed if the name ends
+///   with `.gz`.
+pub fn pack_mtx_files(
+    input_files: &[String],
+    output_file: &str,
+    read_options: &MtxReadOptions,
+    fsync: bool,
+) -> std::io::Result<()> {
+    let mut matrices: Vec<(String, Vec<u8>)> = Vec::with_capacity(input_files.len());
+    for input_file in input_files {
+        let name = matrix_name(input_file);
+        if matrices.iter().any(|(other, _)| *other == name) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("More than one input is named {}", name),
+            ));
+        }
+        let contents = read_file(input_file)?;
+        // Only valid matrices are packed
+        read_mtx_from(contents.as_slice(), input_file, read_options)?;
+        matrices.push((name, contents));
+    }
+
+    let table_length: usize = matrices.iter().map(|(name, _)| 4 + name.len() + 8 + 8).sum();
+    let mut offset = (PACK_MAGIC.len() + 4 + 4 + table_length) as u64;
+    write_output(output_file, fsync, |writer| {
+        writer.write_all(&PACK_MAGIC)?;
+        writer.write_u32::<LittleEndian>(PACK_VERSION)?;
+        writer.write_u32::<LittleEndian>(matrices.len() as u32)?;
+        for (name, contents) in &matrices {
+            writer.write_u32::<LittleEndian>(name.len() as u32)?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_u64::<LittleEndian>(offset)?;
+            writer.write_u64::<LittleEndian>(contents.len() as u64)?;
+            offset += contents.len() as u64;
+        }
+        for (_, contents) in &matrices {
+            writer.write_all(contents)?;
+        }
+        Ok(())
+    })
+}
+
+/// Reads the whole of a file, decompressing it if its name ends with `.gz`.
+fn read_file(input_file: &str) -> std::io::Result<Vec<u8>> {
+    if input_file.ends_with(".gz") {
+        let mut contents = Vec::new();
+        MultiGzDecoder::new(File::open(input_file)?).read_to_end(&mut contents)?;
+        Ok(contents)
+    } else {
+        std::fs::read(input_file)
+    }
+}
+
+/// Checks whether a file is a container written by `pack_mtx_files`.
+pub fn is_pack_file(input_file: &str) -> std::io::Result<bool> {
+    let file = File::open(input_file)?;
+    let mut input: Box<dyn Read> = if input_file.ends_with(".gz") {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut magic = [0u8; 4];
+    match input.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == PACK_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the name and MTX contents of each matrix in a container.
+pub fn read_pack_file(input_file: &str) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    let invalid = |reason: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not a valid matrix container: {}", input_file, reason),
+        )
+    };
+    let bytes = read_file(input_file)?;
+    let mut reader = bytes.as_slice();
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != PACK_MAGIC {
+        return Err(invalid("bad magic number"));
+    }
+    let version = reader.read_u32::<LittleEndian>()?;
+    if version != PACK_VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    let count = reader.read_u32::<LittleEndian>()?;
+    let mut matrices = Vec::new();
+    for _ in 0..count {
+        let mut name = vec![0u8; reader.read_u32::<LittleEndian>()? as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| invalid("a matrix name is not UTF-8"))?;
+        let offset = reader.read_u64::<LittleEndian>()? as usize;
+        let length = reader.read_u64::<LittleEndian>()? as usize;
+        let contents = offset
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| invalid(&format!("the matrix {} extends past the end of the file", name)))?;
+        matrices.push((name, contents.to_vec()));
+    }
+    Ok(matrices)
+}
+
+/// Reads every matrix in a container.
+///
+/// # Returns
+/// The name and contents of each matrix, in the order they were packed.
+pub fn read_packed_matrices(input_file: &str, read_options: &MtxReadOptions) -> std::io::Result<Vec<(String, MtxMatrix)>> {
+    read_pack_file(input_file)?
+        .into_iter()
+        .map(|(name, contents)| {
+            let matrix = read_mtx_from(contents.as_slice(), &format!("{}:{}", input_file, name), read_options)?;
+            Ok((name, matrix))
+        })
+        .collect()
+}
+
+/// Writes each matrix in a container back out as `<name>.mtx` in a directory.
+///
+/// # Returns
+/// The paths of the files written.
+pub fn unpack_mtx_files(input_file: &str, output_directory: &str) -> std::io::Result<Vec<String>> {
+    let matrices = read_pack_file(input_file)?;
+    std::fs::create_dir_all(output_directory)?;
+    let mut output_files = Vec::with_capacity(matrices.len());
+    for (name, contents) in matrices {
+        // A name must not be able to write outside of the output directory
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("The matrix name '{}' in {} is not a valid file name", name, input_file),
+            ));
+        }
+        let output_file = Path::new(output_directory).join(format!("{}.mtx", name));
+        std::fs::write(&output_file, contents)?;
+        output_files.push(output_file.to_string_lossy().into_owned());
+    }
+    Ok(output_files)
+}
+
+/// The name a matrix is packed under, its file name without the `.mtx` or `.mtx.gz` extension.
+fn matrix_name(input_file: &str) -> String {
+    let file_name = Path::new(input_file)
+        .file_name()
+        .map_or(input_file.into(), |name| name.to_string_lossy());
+    let name = file_name.strip_suffix(".gz").unwrap_or(&file_name);
+    name.strip_suffix(".mtx").unwrap_or(name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mtx::{write_mtx_file, Endianness, MtxWriteOptions};
+
+    #[test]
+    fn test_pack_and_unpack() -> std::io::Result<()> {
+        let directory = std::env::temp_dir().join("csv_to_mtx_test_pack");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir(&directory)?;
+        let first = directory.join("am.mtx");
+        std::fs::copy("test/test_expected.mtx", &first)?;
+        let matrix = crate::mtx::read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        let second = directory.join("pm.mtx");
+        let options = MtxWriteOptions {
+            endianness: Endianness::Big,
+            metadata: Some("scenario: pm".to_string()),
+            ..MtxWriteOptions::default()
+        };
+        write_mtx_file(&second.to_string_lossy(), &matrix.origins, &matrix.destinations, &matrix.values, &options)?;
+
+        let inputs = vec![first.to_string_lossy().into_owned(), second.to_string_lossy().into_owned()];
+        let container = directory.join("bank.mtxp").to_string_lossy().into_owned();
+        pack_mtx_files(&inputs, &container, &MtxReadOptions::default(), false)?;
+        assert!(is_pack_file(&container)?);
+        assert!(!is_pack_file("test/test_expected.mtx")?);
+
+        let packed = read_packed_matrices(&container, &MtxReadOptions::default())?;
+        let names: Vec<&str> = packed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["am", "pm"]);
+        assert_eq!(packed[1].1.values, matrix.values);
+
+        let unpacked_directory = directory.join("unpacked").to_string_lossy().into_owned();
+        let unpacked = unpack_mtx_files(&container, &unpacked_directory)?;
+        assert_eq!(unpacked.len(), 2);
+        assert_eq!(std::fs::read(&unpacked[0])?, std::fs::read(&first)?);
+        assert_eq!(std::fs::read(&unpacked[1])?, std::fs::read(&second)?);
+
+        // Two inputs cannot share a name
+        assert!(pack_mtx_files(&[inputs[0].clone(), inputs[0].clone()], &container, &MtxReadOptions::default(), false).is_err());
+
+        // A compressed container is written through a temporary file like an MTX file
+        let compressed = directory.join("bank.mtxp.gz").to_string_lossy().into_owned();
+        pack_mtx_files(&inputs, &compressed, &MtxReadOptions::default(), true)?;
+        assert!(!std::path::Path::new(&format!("{}.tmp", compressed)).exists());
+        assert!(is_pack_file(&compressed)?);
+        assert_eq!(read_pack_file(&compressed)?, read_pack_file(&container)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::sync::Arc;
+
+use parquet::data_type::{FloatType, Int32Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+
+/// The number of cells to buffer before writing them out as a row group.
+const ROW_GROUP_SIZE: usize = 1 << 20;
+
+const SCHEMA: &str = "
+    message matrix {
+        REQUIRED INT32 origin;
+        REQUIRED INT32 destination;
+        REQUIRED FLOAT value;
+    }
+";
+
+/// Writes the non-zero cells of a matrix to a Parquet file with `origin`,
+/// `destination` and `value` columns.
+///
+/// # Arguments
+/// * `output_file_name` - The path to the output Parquet file.
+/// * `origins` - The zone numbers for the rows of the matrix.
+/// * `destinations` - The zone numbers for the columns of the matrix.
+/// * `matrix` - The flattened matrix of values.
+pub fn write_parquet_file(
+    output_file_name: &str,
+    origins: &[i32],
+    destinations: &[i32],
+    matrix: &[f32],
+) -> std::io::Result<()> {
+    let schema = Arc::new(parse_message_type(SCHEMA).map_err(std::io::Error::other)?);
+    let properties = Arc::new(WriterProperties::builder().build());
+    let output_file = File::create(output_file_name)?;
+    let mut writer = SerializedFileWriter::new(output_file, schema, properties)
+        .map_err(std::io::Error::other)?;
+
+    let mut cells = SparseCells::default();
+    for (row, &origin) in origins.iter().enumerate() {
+        let values = &matrix[row * destinations.len()..(row + 1) * destinations.len()];
+        for (&destination, &value) in destinations.iter().zip(values) {
+            if value != 0.0 {
+                cells.origins.push(origin);
+                cells.destinations.push(destination);
+                cells.values.push(value);
+            }
+        }
+        if cells.values.len() >= ROW_GROUP_SIZE {
+            cells.write_row_group(&mut writer).map_err(std::io::Error::other)?;
+        }
+    }
+    if !cells.values.is_empty() {
+        cells.write_row_group(&mut writer).map_err(std::io::Error::other)?;
+    }
+    writer.close().map_err(std::io::Error::other)?;
+    Ok(())
+}
+
+/// The cells waiting to be written as the next row group.
+#[derive(Default)]
+struct SparseCells {
+    origins: Vec<i32>,
+    destinations: Vec<i32>,
+    values: Vec<f32>,
+}
+
+impl SparseCells {
+    fn write_row_group(&mut self, writer: &mut SerializedFileWriter<File>) -> parquet::errors::Result<()> {
+        let mut row_group = writer.next_row_group()?;
+        for column in [&self.origins, &self.destinations] {
+            if let Some(mut column_writer) = row_group.next_column()? {
+                column_writer.typed::<Int32Type>().write_batch(column, None, None)?;
+                column_writer.close()?;
+            }
+        }
+        if let Some(mut column_writer) = row_group.next_column()? {
+            column_writer.typed::<FloatType>().write_batch(&self.values, None, None)?;
+            column_writer.close()?;
+        }
+        row_group.close()?;
+        self.origins.clear();
+        self.destinations.clear();
+        self.values.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn test_write_parquet_file() -> std::io::Result<()> {
+        let output_file = "test/test_parquet_output.parquet";
+        let matrix = [0.0, 1.5, 2.5, 0.0];
+        write_parquet_file(output_file, &[1, 2], &[10, 20], &matrix)?;
+
+        let reader = SerializedFileReader::new(File::open(output_file)?).map_err(std::io::Error::other)?;
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        let columns: Vec<&str> = metadata.schema_descr().columns().iter().map(|column| column.name()).collect();
+        assert_eq!(columns, vec!["origin", "destination", "value"]);
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .map_err(std::io::Error::other)?
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert_eq!(rows, vec!["{origin: 1, destination: 20, value: 1.5}", "{origin: 2, destination: 10, value: 2.5}"]);
+        Ok(())
+    }
+}
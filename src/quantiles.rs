@@ -0,0 +1,127 @@
+use rayon::prelude::*; // For parallel processing
+use tdigest::TDigest;
+
+use crate::matrix::CellValue;
+
+/// The number of centroids kept by the t-digest, which bounds its memory and sets
+/// the accuracy of the approximate quantiles.
+const DIGEST_SIZE: usize = 200;
+
+/// Checks that every quantile is a fraction between 0 and 1.
+pub fn check_quantiles(quantiles: &[f64]) -> std::io::Result<()> {
+    match quantiles.iter().find(|quantile| !(0.0..=1.0).contains(*quantile)) {
+        Some(quantile) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("The quantile {} is not between 0 and 1", quantile),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Computes the quantiles of the non-zero, finite values exactly, by sorting a copy
+/// of them. Quantiles between two values are linearly interpolated.
+///
+/// # Returns
+/// The value at each quantile, or `None` if there are no non-zero values.
+pub fn exact_quantiles<V: CellValue>(values: &[V], quantiles: &[f64]) -> Vec<Option<f64>> {
+    let mut sorted: Vec<f64> = values
+        .par_iter()
+        .map(|&value| value.to_f64())
+        .filter(|&value| value != 0.0 && value.is_finite())
+        .collect();
+    sorted.par_sort_unstable_by(f64::total_cmp);
+    quantiles
+        .iter()
+        .map(|&quantile| {
+            let position = quantile * (sorted.len() as f64 - 1.0);
+            let below = *sorted.get(position.floor() as usize)?;
+            let above = sorted[position.ceil() as usize];
+            Some(below + (above - below) * position.fract())
+        })
+        .collect()
+}
+
+/// Zeroes the non-zero cells below the value at a quantile of the non-zero cells, so
+/// only the largest flows are kept.
+///
+/// # Returns
+/// The value a cell needed to be kept, or `None` if there are no non-zero values.
+pub fn keep_top_quantile<V: CellValue>(matrix: &mut [V], quantile: f64) -> Option<f64> {
+    let threshold = exact_quantiles(matrix, &[quantile])[0]?;
+    matrix.par_iter_mut().for_each(|value| {
+        if value.to_f64() < threshold {
+            *value = V::from_f64(0.0);
+        }
+    });
+    Some(threshold)
+}
+
+/// Estimates the quantiles of the non-zero, finite values with a t-digest, which
+/// takes a fixed amount of memory however many values there are.
+///
+/// # Returns
+/// The estimated value at each quantile, or `None` if there are no non-zero values.
+pub fn approximate_quantiles<V: CellValue>(values: &[V], quantiles: &[f64]) -> Vec<Option<f64>> {
+    let mut digest = TDigest::new_with_size(DIGEST_SIZE);
+    digest.extend_values(
+        values
+            .iter()
+            .map(|&value| value.to_f64())
+            .filter(|&value| value != 0.0 && value.is_finite()),
+    );
+    digest.flush();
+    digest.quantiles(quantiles)
+}
+
+/// Prints the value at each quantile to standard error.
+pub fn print_quantiles(quantiles: &[f64], values: &[Option<f64>]) {
+    for (quantile, value) in quantiles.iter().zip(values) {
+        match value {
+            Some(value) => eprintln!("Quantile {}: {}", quantile, value),
+            None => eprintln!("Quantile {}: no non-zero values", quantile),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles() {
+        // Every value from 1 to 10000 once, with the zeros that are ignored
+        let mut values: Vec<f32> = (1..=10_000).map(|value| value as f32).collect();
+        values.extend([0.0; 500]);
+        let quantiles = [0.0, 0.5, 0.95, 1.0];
+        let exact = exact_quantiles(&values, &quantiles);
+        for (value, expected) in exact.iter().zip([1.0, 5000.5, 9500.05, 10000.0]) {
+            assert!((value.unwrap() - expected).abs() < 1e-6, "{:?} != {}", value, expected);
+        }
+        let approximate = approximate_quantiles(&values, &quantiles);
+        for (exact, approximate) in exact.iter().zip(&approximate) {
+            let (exact, approximate) = (exact.unwrap(), approximate.unwrap());
+            assert!((exact - approximate).abs() <= 10_000.0 * 0.01, "{} != {}", approximate, exact);
+        }
+
+        assert_eq!(exact_quantiles(&[0.0f32; 4], &[0.5]), vec![None]);
+        assert_eq!(approximate_quantiles(&[0.0f32; 4], &[0.5]), vec![None]);
+        assert!(check_quantiles(&[0.0, 0.5, 1.0]).is_ok());
+        assert!(check_quantiles(&[95.0]).is_err());
+    }
+
+    #[test]
+    fn test_keep_top_quantile() {
+        let original: Vec<f32> = (0..100).map(|value| ((value * 37) % 100) as f32).collect();
+        let mut matrix = original.clone();
+        let threshold = keep_top_quantile(&mut matrix, 0.95).unwrap();
+        assert_eq!(threshold, exact_quantiles(&original, &[0.95])[0].unwrap());
+        for (&kept, &value) in matrix.iter().zip(&original) {
+            let expected = if (value as f64) < threshold { 0.0 } else { value };
+            assert_eq!(kept, expected);
+        }
+        // Of the values 1 to 99, only 95 and above reach the threshold of 94.1
+        assert_eq!(matrix.iter().filter(|&&value| value != 0.0).count(), 5);
+
+        assert_eq!(keep_top_quantile(&mut [0.0f32; 4], 0.5), None);
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Why a record of the input was left out of the matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The record could not be split into fields, such as a row with too many fields.
+    Malformed,
+    /// A value is not a number.
+    ParseError,
+    /// A zone label is not a zone, or the zone is not in the matrix.
+    UnknownZone,
+    /// A value is outside the range kept in the matrix, such as below `--min-value`.
+    OutOfRange,
+}
+
+impl RejectReason {
+    /// The name of the reason written to the reject log.
+    pub fn name(self) -> &'static str {
+        match self {
+            RejectReason::Malformed => "malformed",
+            RejectReason::ParseError => "parse-error",
+            RejectReason::UnknownZone => "unknown-zone",
+            RejectReason::OutOfRange => "out-of-range",
+        }
+    }
+}
+
+/// A record of the input that was left out of the matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reject {
+    /// The line of the input the record is on, counting from 1.
+    pub line: u64,
+    pub reason: RejectReason,
+    /// What was wrong with the record, such as the text that is not a number.
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+struct RejectState {
+    rejects: Vec<Reject>,
+    /// The line of each row passed on by the reader, in the order the rows are read, so
+    /// a row left out when the matrix is built can be traced back to its line.
+    row_lines: Vec<u64>,
+}
+
+/// Collects the records left out of the matrix while the input is read and the matrix
+/// is built. Clones share the same log, so it can be handed to the reader through
+/// `ReadOptions` and read back afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct RejectLog(Arc<Mutex<RejectState>>);
+
+impl PartialEq for RejectLog {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl RejectLog {
+    fn state(&self) -> MutexGuard<'_, RejectState> {
+        // A panic while holding the lock leaves the log usable, as every update is a push
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Records a rejected record.
+    pub fn reject(&self, line: u64, reason: RejectReason, detail: String) {
+        self.state().rejects.push(Reject { line, reason, detail });
+    }
+
+    /// Records the line of the next row passed on by the reader.
+    pub fn record_row(&self, line: u64) {
+        self.state().row_lines.push(line);
+    }
+
+    /// Gets the line of the row at `index` in the order the rows were read.
+    pub fn row_line(&self, index: usize) -> Option<u64> {
+        self.state().row_lines.get(index).copied()
+    }
+
+    /// Gets the rejected records in the order of their lines.
+    pub fn rejects(&self) -> Vec<Reject> {
+        let mut rejects = self.state().rejects.clone();
+        rejects.sort_by_key(|reject| reject.line);
+        rejects
+    }
+
+    /// Writes the rejected records to a `line,reason,detail` CSV file.
+    ///
+    /// # Returns
+    /// The number of rejected records.
+    pub fn write_csv(&self, output_file: &str) -> std::io::Result<usize> {
+        let rejects = self.rejects();
+        let mut writer = csv::Writer::from_writer(File::create(output_file)?);
+        writer.write_record(["line", "reason", "detail"])?;
+        for reject in &rejects {
+            writer.write_record([reject.line.to_string().as_str(), reject.reason.name(), &reject.detail])?;
+        }
+        writer.flush()?;
+        Ok(rejects.len())
+    }
+}
@@ -0,0 +1,121 @@
+use crate::generate::SplitMix64;
+use crate::mtx::{read_mtx_file, Endianness, GzipFilter, MtxMatrix, MtxReadOptions, MtxWriteOptions};
+
+/// The number of zones in the generated matrix.
+const SELF_TEST_ZONES: usize = 37;
+
+/// Generates a small matrix with gaps in its zone numbers, negative values and empty
+/// cells, so the index arrays and every byte of the values are exercised.
+fn generate_matrix(seed: u64) -> MtxMatrix {
+    let mut rng = SplitMix64(seed);
+    let mut zones = Vec::with_capacity(SELF_TEST_ZONES);
+    let mut zone = 0;
+    for _ in 0..SELF_TEST_ZONES {
+        zone += 1 + (rng.next_u64() % 1000) as i32;
+        zones.push(zone);
+    }
+    let values = (0..SELF_TEST_ZONES * SELF_TEST_ZONES)
+        .map(|_| match rng.next_u64() % 4 {
+            0 => 0.0,
+            1 => -(rng.next_unit() * 100.0) as f32,
+            _ => (rng.next_unit() * 1e6) as f32,
+        })
+        .collect();
+    MtxMatrix {
+        origins: zones.clone(),
+        destinations: zones,
+        values,
+        metadata: None,
+    }
+}
+
+/// Writes the matrix with the given options and reads it back.
+///
+/// # Returns
+/// Why the matrix read back differs from the one written, or `None` if they match.
+fn round_trip(matrix: &MtxMatrix, output_file: &str, options: &MtxWriteOptions) -> std::io::Result<Option<String>> {
+    crate::mtx::write_mtx_file(output_file, &matrix.origins, &matrix.destinations, &matrix.values, options)?;
+    let read = read_mtx_file(output_file, &MtxReadOptions::default());
+    let _ = std::fs::remove_file(output_file);
+    let read = read?;
+    Ok(if read.origins != matrix.origins || read.destinations != matrix.destinations {
+        Some("the zones read back differ from the zones written".to_string())
+    } else if read.metadata != options.metadata {
+        Some("the metadata read back differs from the metadata written".to_string())
+    } else if let Some(cell) = (0..matrix.values.len()).find(|&i| read.values[i].to_bits() != matrix.values[i].to_bits()) {
+        Some(format!(
+            "cell {} was written as {} and read back as {}",
+            cell, matrix.values[cell], read.values[cell]
+        ))
+    } else {
+        None
+    })
+}
+
+/// Writes a generated matrix to temporary MTX files in each byte order, with and
+/// without the byte-transpose filter, compressed and not, and reads each back,
+/// printing `PASS` or `FAIL` for every layout. This checks the build on a platform
+/// where the test suite cannot be run.
+///
+/// # Arguments
+/// * `seed` - The seed for the generated matrix, printed so a failure can be repeated.
+///
+/// # Returns
+/// Whether every layout read back exactly as written.
+pub fn run_self_test(seed: u64) -> std::io::Result<bool> {
+    let matrix = generate_matrix(seed);
+    println!(
+        "Round-tripping a {} x {} matrix generated with seed {}",
+        SELF_TEST_ZONES, SELF_TEST_ZONES, seed
+    );
+    let directory = std::env::temp_dir();
+    let mut passed = true;
+    for endianness in [Endianness::Little, Endianness::Big] {
+        for gzip_filter in [GzipFilter::None, GzipFilter::ByteTranspose] {
+            for extension in ["mtx", "mtx.gz"] {
+                let metadata = (gzip_filter == GzipFilter::None).then(|| "csv_to_mtx selftest".to_string());
+                let options = MtxWriteOptions {
+                    endianness,
+                    metadata,
+                    gzip_filter,
+                    ..MtxWriteOptions::default()
+                };
+                let byte_order = match endianness {
+                    Endianness::Little => "little-endian",
+                    Endianness::Big => "big-endian",
+                };
+                let filter = match gzip_filter {
+                    GzipFilter::None => "",
+                    GzipFilter::ByteTranspose => " byte-transposed",
+                };
+                let name = format!("{}{} .{}", byte_order, filter, extension);
+                let output_file = directory.join(format!("csv_to_mtx_selftest_{}.{}", std::process::id(), extension));
+                match round_trip(&matrix, &output_file.to_string_lossy(), &options) {
+                    Ok(None) => println!("PASS {}", name),
+                    Ok(Some(reason)) => {
+                        println!("FAIL {}: {}", name, reason);
+                        passed = false;
+                    }
+                    Err(e) => {
+                        println!("FAIL {}: {}", name, e);
+                        passed = false;
+                    }
+                }
+            }
+        }
+    }
+    println!("{}", if passed { "PASS" } else { "FAIL" });
+    Ok(passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test() -> std::io::Result<()> {
+        assert!(run_self_test(1)?);
+        assert!(run_self_test(0xDEAD_BEEF)?);
+        Ok(())
+    }
+}
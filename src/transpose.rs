@@ -0,0 +1,82 @@
+use crate::matrix::{transpose, CellValue};
+use crate::mtx::{read_mtx_file_as, read_mtx_value_type, write_mtx_file, GzipFilter, MtxReadOptions, MtxWriteOptions};
+
+/// Reads an MTX file and writes its transpose, swapping the origin and
+/// destination zone systems. The transpose keeps the value type, byte order,
+/// index width and byte-transposed layout of the input.
+///
+/// # Arguments
+/// * `input_file` - The path to the MTX file to transpose.
+/// * `output_file` - The path to write the transposed MTX file to.
+/// * `read_options` - The byte order of the input file and how to handle truncation.
+pub fn transpose_mtx_file(
+    input_file: &str,
+    output_file: &str,
+    read_options: &MtxReadOptions,
+) -> std::io::Result<()> {
+    if read_mtx_value_type(input_file, read_options)? == f64::MTX_TYPE {
+        transpose_values::<f64>(input_file, output_file, read_options)
+    } else {
+        transpose_values::<f32>(input_file, output_file, read_options)
+    }
+}
+
+fn transpose_values<V: CellValue>(
+    input_file: &str,
+    output_file: &str,
+    read_options: &MtxReadOptions,
+) -> std::io::Result<()> {
+    let matrix = read_mtx_file_as::<V>(input_file, read_options)?;
+    let transposed = transpose(&matrix.values, matrix.origins.len(), matrix.destinations.len());
+    let options = MtxWriteOptions {
+        endianness: matrix.endianness,
+        metadata: matrix.metadata.clone(),
+        index_width: matrix.index_width,
+        gzip_filter: if matrix.byte_transposed { GzipFilter::ByteTranspose } else { GzipFilter::None },
+        ..MtxWriteOptions::default()
+    };
+    write_mtx_file(output_file, &matrix.destinations, &matrix.origins, &transposed, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mtx::{read_mtx_file, Endianness, IndexWidth};
+
+    #[test]
+    fn test_transpose_mtx_file() -> std::io::Result<()> {
+        let output_file = "test/test_transpose_output.mtx";
+        transpose_mtx_file("test/test_expected.mtx", output_file, &MtxReadOptions::default())?;
+        let original = read_mtx_file("test/test_expected.mtx", &MtxReadOptions::default())?;
+        let transposed = read_mtx_file(output_file, &MtxReadOptions::default())?;
+        let n = original.origins.len();
+        for i in 0..n {
+            for j in 0..n {
+                assert_eq!(original.values[i * n + j], transposed.values[j * n + i]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_transpose_keeps_layout() -> std::io::Result<()> {
+        let input_file = "test/test_transpose_double_output.mtx";
+        let output_file = "test/test_transpose_double_transposed_output.mtx";
+        let options = MtxWriteOptions {
+            endianness: Endianness::Big,
+            index_width: IndexWidth::W16,
+            ..MtxWriteOptions::default()
+        };
+        let values = [16777217.0f64, 0.1, 2.0, 3.0, 4.0, 5.0];
+        write_mtx_file(input_file, &[1, 2], &[1, 2, 3], &values, &options)?;
+        transpose_mtx_file(input_file, output_file, &MtxReadOptions::default())?;
+
+        let transposed = read_mtx_file_as::<f64>(output_file, &MtxReadOptions::default())?;
+        assert_eq!(transposed.origins, vec![1, 2, 3]);
+        assert_eq!(transposed.destinations, vec![1, 2]);
+        assert_eq!(transposed.values, vec![16777217.0, 3.0, 0.1, 4.0, 2.0, 5.0]);
+        assert_eq!(transposed.endianness, Endianness::Big);
+        assert_eq!(transposed.index_width, IndexWidth::W16);
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::cell::Cell;
+use std::io::Read;
+use std::rc::Rc;
+
+/// Records whether a `TolerantReader` stopped early because its input was truncated.
+#[derive(Debug, Clone, Default)]
+pub struct TruncationReport(Rc<Cell<Option<u64>>>);
+
+impl TruncationReport {
+    /// The number of bytes recovered before the input was found to be truncated,
+    /// or `None` if it was read to the end.
+    pub fn recovered_bytes(&self) -> Option<u64> {
+        self.0.get()
+    }
+}
+
+/// Wraps a gzip decoder so that a decode error part way through the stream ends the
+/// input instead of failing it, salvaging the valid prefix of a truncated file. When
+/// reading text, only whole lines are passed on so the partial line the stream was
+/// cut in is never parsed as a record.
+pub struct TolerantReader<R> {
+    inner: R,
+    whole_lines: bool,
+    /// Bytes after the last newline that are held back until the line is complete.
+    pending: Vec<u8>,
+    ready: Vec<u8>,
+    position: usize,
+    recovered_bytes: u64,
+    finished: bool,
+    report: TruncationReport,
+}
+
+impl<R: Read> TolerantReader<R> {
+    /// Creates the reader along with the report it fills in if it finds a truncation.
+    ///
+    /// # Arguments
+    /// * `inner` - The reader that may fail part way through.
+    /// * `whole_lines` - Only pass on complete lines, for text inputs.
+    pub fn new(inner: R, whole_lines: bool) -> (TolerantReader<R>, TruncationReport) {
+        let report = TruncationReport::default();
+        let reader = TolerantReader {
+            inner,
+            whole_lines,
+            pending: Vec::new(),
+            ready: Vec::new(),
+            position: 0,
+            recovered_bytes: 0,
+            finished: false,
+            report: report.clone(),
+        };
+        (reader, report)
+    }
+}
+
+impl<R: Read> Read for TolerantReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut chunk = [0u8; 64 * 1024];
+        loop {
+            if self.position < self.ready.len() {
+                let count = buf.len().min(self.ready.len() - self.position);
+                buf[..count].copy_from_slice(&self.ready[self.position..self.position + count]);
+                self.position += count;
+                return Ok(count);
+            }
+            if self.finished {
+                return Ok(0);
+            }
+            self.ready.clear();
+            self.position = 0;
+            match self.inner.read(&mut chunk) {
+                Ok(0) => {
+                    self.ready = std::mem::take(&mut self.pending);
+                    self.finished = true;
+                }
+                Ok(count) if self.whole_lines => {
+                    self.pending.extend_from_slice(&chunk[..count]);
+                    if let Some(last_newline) = self.pending.iter().rposition(|&byte| byte == b'\n') {
+                        self.ready = self.pending.drain(..=last_newline).collect();
+                    }
+                }
+                Ok(count) => self.ready.extend_from_slice(&chunk[..count]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.pending.clear();
+                    self.finished = true;
+                    self.report.0.set(Some(self.recovered_bytes));
+                }
+            }
+            self.recovered_bytes += self.ready.len() as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails after passing on its data, like a decoder reaching a cut in the stream.
+    struct Truncated<'a>(&'a [u8]);
+
+    impl Read for Truncated<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "incomplete stream"));
+            }
+            let count = buf.len().min(self.0.len());
+            buf[..count].copy_from_slice(&self.0[..count]);
+            self.0 = &self.0[count..];
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn test_partial_line_is_dropped() -> std::io::Result<()> {
+        let (mut reader, report) = TolerantReader::new(Truncated(b"1,2,3\n4,5,6\n7,8,"), true);
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        assert_eq!(text, "1,2,3\n4,5,6\n");
+        assert_eq!(report.recovered_bytes(), Some(12));
+
+        let (mut reader, report) = TolerantReader::new(Truncated(b"1234567"), false);
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        assert_eq!(bytes, b"1234567");
+        assert_eq!(report.recovered_bytes(), Some(7));
+
+        let (mut reader, report) = TolerantReader::new(&b"1,2,3\n4,5,6"[..], true);
+        reader.read_to_string(&mut text)?;
+        assert_eq!(report.recovered_bytes(), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use crate::mtx::{read_mtx_zones, MtxReadOptions};
+use crate::zones::{read_zones_file, ZonesFile};
+
+/// Checks that a zones file lists the same zones, in the same order, as the origins
+/// and destinations of an MTX file, printing the first position where they differ.
+///
+/// # Arguments
+/// * `zones_file` - The zones CSV file.
+/// * `mtx_file` - The path to the MTX file, of which only the header is read.
+/// * `read_options` - The byte order of the MTX file.
+///
+/// # Returns
+/// `true` if the zones match.
+pub fn validate_zones(
+    zones_file: &ZonesFile,
+    mtx_file: &str,
+    read_options: &MtxReadOptions,
+) -> std::io::Result<bool> {
+    let zones = read_zones_file(zones_file)?;
+    let (origins, destinations) = read_mtx_zones(mtx_file, read_options)?;
+    let mut matches = true;
+    for (name, mtx_zones) in [("origins", &origins), ("destinations", &destinations)] {
+        if let Some(position) = first_difference(&zones, mtx_zones) {
+            let describe = |zones: &[i32]| match zones.get(position) {
+                Some(zone) => format!("has zone {}", zone),
+                None => "has ended".to_string(),
+            };
+            println!(
+                "The {} of {} differ from {} at position {}: {} {} but {} {}",
+                name,
+                mtx_file,
+                zones_file.path,
+                position + 1,
+                zones_file.path,
+                describe(&zones),
+                mtx_file,
+                describe(mtx_zones)
+            );
+            matches = false;
+        }
+    }
+    if matches {
+        println!("{} matches the {} zones of {}", zones_file.path, zones.len(), mtx_file);
+    }
+    Ok(matches)
+}
+
+/// Finds the first position where two lists of zones differ, including where one ends
+/// before the other.
+fn first_difference(first: &[i32], second: &[i32]) -> Option<usize> {
+    match first.iter().zip(second).position(|(a, b)| a != b) {
+        Some(position) => Some(position),
+        None => (first.len() != second.len()).then(|| first.len().min(second.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_zones() -> std::io::Result<()> {
+        let zones_file = std::env::temp_dir().join("csv_to_mtx_test_validate_zones.csv");
+        let zones_file_name = zones_file.to_str().unwrap();
+        std::fs::write(&zones_file, "1\n2\n3\n4\n")?;
+        let options = MtxReadOptions::default();
+        assert!(validate_zones(&ZonesFile::new(zones_file_name), "test/test_expected.mtx", &options)?);
+
+        // Reordered, missing and extra zones are all reported
+        for contents in ["1\n3\n2\n4\n", "1\n2\n3\n", "1\n2\n3\n4\n5\n"] {
+            std::fs::write(&zones_file, contents)?;
+            assert!(!validate_zones(&ZonesFile::new(zones_file_name), "test/test_expected.mtx", &options)?);
+        }
+        assert_eq!(first_difference(&[1, 3, 2], &[1, 2, 3]), Some(1));
+        assert_eq!(first_difference(&[1, 2], &[1, 2, 3]), Some(2));
+        assert_eq!(first_difference(&[1, 2], &[1, 2]), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+/// A built-in transform turning the text of a value field into a number, for inputs
+/// whose values are not written as plain numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ValueTransform {
+    /// Adds the numbers separated by colons, so `3:30` is 33.
+    SumColon,
+    /// Reads `m:ss` or `h:mm:ss` as a number of minutes, so `3:30` is 3.5.
+    MinutesColon,
+    /// Reads an `HH:MM:SS` time, whose minutes and seconds are below 60, as a duration
+    /// in the `TimeUnit` of the read, so `01:30:00` is 90 minutes.
+    Hms,
+}
+
+/// The unit an `HH:MM:SS` time is read in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnit {
+    #[default]
+    Minutes,
+    Seconds,
+}
+
+impl TimeUnit {
+    /// Converts a number of minutes to this unit.
+    pub fn from_minutes(self, minutes: f64) -> f64 {
+        match self {
+            TimeUnit::Minutes => minutes,
+            TimeUnit::Seconds => minutes * 60.0,
+        }
+    }
+}
+
+/// What is done with the negative values of an input, for feeds where a negative value
+/// is a code, such as for an error, rather than data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClampNegative {
+    /// Negative values are kept as they are.
+    #[default]
+    Keep,
+    /// Negative values are missing, so their cells take the fill value.
+    Zero,
+    /// Negative values are replaced by their magnitude.
+    Abs,
+    /// A negative value stops the conversion.
+    Error,
+}
+
+impl ClampNegative {
+    /// Applies the policy to a parsed value.
+    ///
+    /// # Returns
+    /// The value to keep, or `None` if it is missing. `Error` keeps the value, leaving
+    /// the reader to fail.
+    pub fn apply(self, value: f64) -> Option<f64> {
+        match self {
+            ClampNegative::Zero if value < 0.0 => None,
+            ClampNegative::Abs => Some(value.abs()),
+            _ => Some(value),
+        }
+    }
+}
+
+/// The range of plausible values, written `min:max`, outside of which a value points to
+/// corrupt data or the wrong unit, such as a travel time in seconds rather than minutes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl ValueRange {
+    /// Parses a range written as `min:max`, whose minimum is at most its maximum.
+    pub fn parse(text: &str) -> std::io::Result<ValueRange> {
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The value range {} is not written as min:max with the minimum at most the maximum", text),
+            )
+        };
+        let (min, max) = text.split_once(':').ok_or_else(invalid)?;
+        let (Ok(min), Ok(max)) = (min.trim().parse::<f64>(), max.trim().parse::<f64>()) else {
+            return Err(invalid());
+        };
+        if min.is_nan() || max.is_nan() || min > max {
+            return Err(invalid());
+        }
+        Ok(ValueRange { min, max })
+    }
+
+    /// Whether a value is within the range, including its bounds.
+    pub fn contains(self, value: f64) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+impl std::fmt::Display for ValueRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.min, self.max)
+    }
+}
+
+impl ValueTransform {
+    /// Applies the transform to the text of a value.
+    ///
+    /// # Returns
+    /// The value, or `None` if the text is not in the transform's format. An `HH:MM:SS`
+    /// time is given in minutes.
+    pub fn apply(self, text: &str) -> Option<f64> {
+        let mut parts = text.split(':').map(|part| part.trim().parse::<f64>());
+        match self {
+            ValueTransform::SumColon => parts.try_fold(0.0, |sum, part| part.ok().map(|part| sum + part)),
+            ValueTransform::MinutesColon => {
+                let parts = parts.collect::<Result<Vec<f64>, _>>().ok()?;
+                match parts[..] {
+                    [minutes, seconds] => Some(minutes + seconds / 60.0),
+                    [hours, minutes, seconds] => Some(hours * 60.0 + minutes + seconds / 60.0),
+                    _ => None,
+                }
+            }
+            ValueTransform::Hms => {
+                let [hours, minutes, seconds] = text.split(':').collect::<Vec<_>>()[..] else {
+                    return None;
+                };
+                let is_whole = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+                if !is_whole(hours) || !is_whole(minutes) || seconds.starts_with(['+', '-']) {
+                    return None;
+                }
+                let (hours, minutes, seconds) = (hours.parse::<f64>().ok()?, minutes.parse::<f64>().ok()?, seconds.parse::<f64>().ok()?);
+                (minutes < 60.0 && (0.0..60.0).contains(&seconds)).then(|| hours * 60.0 + minutes + seconds / 60.0)
+            }
+        }
+    }
+}
+
+/// How the values of a rectangular cell holding several values, such as `3|4`, are
+/// combined into one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CellAggregate {
+    /// The values are added together.
+    #[default]
+    Sum,
+    /// The first value is kept.
+    First,
+    /// The second value is kept, leaving cells with a single value empty.
+    Second,
+    /// The largest value is kept.
+    Max,
+}
+
+impl CellAggregate {
+    /// Combines the values of a cell.
+    ///
+    /// # Returns
+    /// The value of the cell, or `None` if it does not have the value to keep.
+    pub fn apply(self, values: &[f64]) -> Option<f64> {
+        match self {
+            CellAggregate::Sum => Some(values.iter().sum()),
+            CellAggregate::First => values.first().copied(),
+            CellAggregate::Second => values.get(1).copied(),
+            CellAggregate::Max => values.iter().copied().reduce(f64::max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_transforms() {
+        assert_eq!(ValueTransform::MinutesColon.apply("3:30"), Some(3.5));
+        assert_eq!(ValueTransform::MinutesColon.apply("1:02:15"), Some(62.25));
+        assert_eq!(ValueTransform::MinutesColon.apply("3.5"), None);
+        assert_eq!(ValueTransform::MinutesColon.apply("3:x"), None);
+        assert_eq!(ValueTransform::SumColon.apply("3:30"), Some(33.0));
+        assert_eq!(ValueTransform::SumColon.apply("1.5:2:0.25"), Some(3.75));
+        assert_eq!(ValueTransform::SumColon.apply("2.5"), Some(2.5));
+        assert_eq!(ValueTransform::SumColon.apply("NA"), None);
+    }
+
+    #[test]
+    fn test_hms() {
+        assert_eq!(ValueTransform::Hms.apply("01:30:00"), Some(90.0));
+        assert_eq!(TimeUnit::Seconds.from_minutes(ValueTransform::Hms.apply("01:30:00").unwrap()), 5400.0);
+        assert_eq!(ValueTransform::Hms.apply("00:00:30"), Some(0.5));
+        assert_eq!(ValueTransform::Hms.apply("26:05:30.5"), Some(26.0 * 60.0 + 5.0 + 30.5 / 60.0));
+        assert_eq!(ValueTransform::Hms.apply("1:30"), None);
+        assert_eq!(ValueTransform::Hms.apply("01:60:00"), None);
+        assert_eq!(ValueTransform::Hms.apply("01:30:75"), None);
+        assert_eq!(ValueTransform::Hms.apply("-1:30:00"), None);
+        assert_eq!(ValueTransform::Hms.apply("01:30:-5"), None);
+        assert_eq!(ValueTransform::Hms.apply("90"), None);
+    }
+
+    #[test]
+    fn test_clamp_negative() {
+        assert_eq!(ClampNegative::Keep.apply(-3.0), Some(-3.0));
+        assert_eq!(ClampNegative::Zero.apply(-3.0), None);
+        assert_eq!(ClampNegative::Zero.apply(3.0), Some(3.0));
+        assert_eq!(ClampNegative::Abs.apply(-3.0), Some(3.0));
+        assert_eq!(ClampNegative::Error.apply(-3.0), Some(-3.0));
+    }
+
+    #[test]
+    fn test_value_range() -> std::io::Result<()> {
+        let range = ValueRange::parse("0:1440")?;
+        assert_eq!(range, ValueRange { min: 0.0, max: 1440.0 });
+        assert!(range.contains(0.0) && range.contains(1440.0));
+        assert!(!range.contains(1e7) && !range.contains(-0.5) && !range.contains(f64::NAN));
+        assert_eq!(ValueRange::parse("-5 : 2.5")?.to_string(), "-5:2.5");
+        assert!(ValueRange::parse("10:1").is_err());
+        assert!(ValueRange::parse("10").is_err());
+        assert!(ValueRange::parse("a:b").is_err());
+        Ok(())
+    }
+}
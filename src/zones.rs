@@ -0,0 +1,471 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use rayon::prelude::*; // For parallel processing
+
+use crate::error::Error;
+use crate::input::open_input;
+use crate::mtx::{read_mtx_zones, MtxReadOptions};
+
+/// How the zones listed in a zones file combine with the zones found in the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZonesMode {
+    /// Only the zones in the zones file are used.
+    #[default]
+    Replace,
+    /// The zones in the zones file are added to the zones in the data.
+    Union,
+    /// Only the zones in both the zones file and the data are used.
+    Intersect,
+}
+
+/// What `--relative-to` does when the zones of the input and the base file differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareZones {
+    /// Refuse to build the difference, so misaligned scenarios are never compared.
+    #[default]
+    Match,
+    /// Build the difference over the zones of both files, warning about the zones
+    /// only one of them has.
+    Union,
+}
+
+/// Finds the zones only in the first list and the zones only in the second.
+///
+/// # Returns
+/// The sorted zones of `first` missing from `second`, and of `second` missing from `first`.
+pub fn zone_differences(first: &[i32], second: &[i32]) -> (Vec<i32>, Vec<i32>) {
+    let only = |zones: &[i32], others: &[i32]| {
+        let others: HashSet<i32> = others.iter().copied().collect();
+        let mut only: Vec<i32> = zones.iter().copied().filter(|zone| !others.contains(zone)).collect();
+        only.sort_unstable();
+        only.dedup();
+        only
+    };
+    (only(first, second), only(second, first))
+}
+
+/// Where the zones of the matrix come from, chosen explicitly rather than by whether
+/// a zones file was given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneSource {
+    /// The unique origins and destinations of the input, ignoring any zones file.
+    Data,
+    /// The zones listed in the zones file, combined with the input's by the zones mode.
+    #[default]
+    File,
+    /// Every zone number from 1 to the largest zone in the input, ignoring any zones file.
+    Range,
+    /// The origins of the MTX file given as the zones file, combined like `File`.
+    Mtx,
+}
+
+/// A CSV file listing a zone system in its first column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZonesFile<'a> {
+    /// The path to the zones CSV file, which is not read for `ZoneSource::Range`.
+    pub path: &'a str,
+    /// The byte separating fields in the zones CSV file.
+    pub delimiter: u8,
+    /// If the first row is a header rather than a zone.
+    pub has_header: bool,
+    /// How the zones file combines with the zones in the data.
+    pub mode: ZonesMode,
+    /// Only use the zones in the file, in the order they are listed rather than sorted.
+    pub ordered: bool,
+    /// Whether the zones come from the CSV file, an MTX file or the range of the data.
+    pub source: ZoneSource,
+}
+
+impl<'a> ZonesFile<'a> {
+    /// A header-less, comma separated zones file that replaces the data's zones.
+    pub fn new(path: &'a str) -> ZonesFile<'a> {
+        ZonesFile {
+            path,
+            delimiter: b',',
+            has_header: false,
+            mode: ZonesMode::Replace,
+            ordered: false,
+            source: ZoneSource::File,
+        }
+    }
+}
+
+/// How many times the number of zones the largest zone number can be before the
+/// zone numbers look spurious, such as a timestamp parsed as a zone.
+const SUSPICIOUS_ZONE_RATIO: i64 = 1000;
+
+/// Checks for a zone number that is orders of magnitude larger than the number of
+/// zones, which usually means stray values in the data were read as zones.
+///
+/// # Arguments
+/// * `all_zones` - The sorted vector of unique zone numbers.
+///
+/// # Returns
+/// A description of the problem, or `None` if the zone numbers look plausible.
+pub fn suspicious_zone_numbers(all_zones: &[i32]) -> Option<String> {
+    let largest = all_zones.iter().map(|&zone| (zone as i64).abs()).max()?;
+    (largest > SUSPICIOUS_ZONE_RATIO * all_zones.len() as i64).then(|| {
+        format!(
+            "The largest zone number {} is over {} times the number of zones ({}), so the data may contain spurious zone numbers. Supply a zones file to set the zone system.",
+            largest,
+            SUSPICIOUS_ZONE_RATIO,
+            all_zones.len()
+        )
+    })
+}
+
+/// Checks that there are no more zones than the reader of the output can load, before
+/// the dense matrix is allocated.
+///
+/// # Arguments
+/// * `all_zones` - The zones of the matrix.
+/// * `max_zones` - The most zones allowed, or `None` for no limit.
+pub fn check_zone_limit(all_zones: &[i32], max_zones: Option<usize>) -> std::io::Result<()> {
+    match max_zones {
+        Some(max_zones) if all_zones.len() > max_zones => Err(Error::ZoneOverflow {
+            zones: all_zones.len(),
+            limit: max_zones,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Determines the complete list of zones either from the optional zones CSV file
+/// or by extracting unique origins and destinations from the input data.
+///
+/// # Arguments
+/// * `zones_file` - Optional zones CSV file.
+/// * `data` - The vector of tuples `(i32, i32, V)` representing the input data.
+///
+/// # Returns
+/// A vector of unique zone numbers, sorted unless the zones file is ordered.
+pub fn get_all_zones<V: Sync>(
+    zones_file: Option<&ZonesFile>,
+    data: &[(i32, i32, V)],
+) -> std::io::Result<Vec<i32>> {
+    resolve_zones(zones_file, || Ok(get_data_zones(data)))
+}
+
+/// Determines the complete list of zones like `get_all_zones`, but only asks for the
+/// zones in the data when they are needed. This lets callers that stream the input
+/// skip a pass over it when a zones file replaces the data's zones.
+///
+/// # Arguments
+/// * `zones_file` - Optional zones CSV file.
+/// * `data_zones` - Produces the unique origins and destinations in the input data.
+///
+/// # Returns
+/// A vector of unique zone numbers, in the same order on every run. The zones are
+/// sorted unless the zones file is ordered, which keeps the order of its first listings.
+pub fn resolve_zones(
+    zones_file: Option<&ZonesFile>,
+    data_zones: impl FnOnce() -> std::io::Result<HashSet<i32>>,
+) -> std::io::Result<Vec<i32>> {
+    if let Some(zones_file) = zones_file
+        && zones_file.source == ZoneSource::Range
+    {
+        return zone_range(data_zones()?);
+    }
+    if let Some(zones_file) = zones_file
+        && zones_file.ordered
+    {
+        let mut seen = HashSet::new();
+        let mut zones = read_zones_file(zones_file)?;
+        zones.retain(|&zone| seen.insert(zone));
+        return Ok(zones);
+    }
+    let mut zones: Vec<i32> = if let Some(zones_file) = zones_file {
+        let file_zones = read_zones_file(zones_file)?;
+        match zones_file.mode {
+            ZonesMode::Replace => file_zones,
+            ZonesMode::Union => {
+                let mut zones = data_zones()?;
+                zones.extend(file_zones);
+                zones.into_iter().collect()
+            }
+            ZonesMode::Intersect => {
+                let data_zones = data_zones()?;
+                file_zones
+                    .into_iter()
+                    .filter(|zone| data_zones.contains(zone))
+                    .collect()
+            }
+        }
+    } else {
+        data_zones()?.into_iter().collect()
+    };
+    // Zone numbers are their own sort key and are unique once duplicates listed in
+    // the zones file are removed, so the unstable sort still gives every run the same
+    // order regardless of how the zones were collected.
+    zones.sort_unstable();
+    zones.dedup();
+    Ok(zones)
+}
+
+/// Adds padding zones after the zones so that their number is a power of two, for
+/// readers that need the side of the matrix to be one. The padding zones are numbered
+/// from one more than the largest zone upwards.
+/// An empty zone system is left empty.
+pub fn pad_zones_to_power_of_two(zones: &mut Vec<i32>) -> std::io::Result<()> {
+    let Some(&largest) = zones.iter().max() else {
+        return Ok(());
+    };
+    let padded = zones.len().next_power_of_two();
+    let mut next_zone = largest.checked_add(1);
+    while zones.len() < padded {
+        let Some(zone) = next_zone else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("There are no zone numbers above {} left to pad the zones with", i32::MAX),
+            ));
+        };
+        zones.push(zone);
+        next_zone = zone.checked_add(1);
+    }
+    Ok(())
+}
+
+/// Numbers the zones from 1 to the largest zone in the data, refusing zone numbers
+/// that look spurious, as each would add a row and column to the matrix.
+fn zone_range(data_zones: HashSet<i32>) -> std::io::Result<Vec<i32>> {
+    let mut zones: Vec<i32> = data_zones.into_iter().collect();
+    zones.sort_unstable();
+    if let Some(problem) = suspicious_zone_numbers(&zones) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, problem));
+    }
+    Ok((1..=zones.last().copied().unwrap_or(0)).collect())
+}
+
+/// Reads the zone numbers from the first column of a zones CSV file. Lines starting
+/// with `#`, the rest of a line after a `#`, and blank lines are ignored, so the file
+/// can be maintained by hand. The zones are returned in the order they are listed.
+/// For `ZoneSource::Mtx` they are the origins in the header of an MTX file instead.
+pub fn read_zones_file(zones_file: &ZonesFile) -> std::io::Result<Vec<i32>> {
+    match zones_file.source {
+        ZoneSource::Mtx => Ok(read_mtx_zones(zones_file.path, &MtxReadOptions::default())?.0),
+        _ => read_zone_records(zones_file, |zone, _| Some(zone)),
+    }
+}
+
+/// Reads a two-column `zone,partition` CSV file assigning each zone to a named
+/// partition, in the same way as `read_zones_file`. Rows without a partition are ignored,
+/// and a zone listed again must be in the same partition.
+///
+/// # Returns
+/// Each zone and its partition, in the order they are listed.
+pub fn read_zone_partitions(zones_file: &ZonesFile) -> std::io::Result<Vec<(i32, String)>> {
+    let partitions = read_zone_records(zones_file, |zone, record| {
+        let partition = record.get(1)?.split('#').next()?.trim();
+        (!partition.is_empty()).then(|| (zone, partition.to_string()))
+    })?;
+    let mut assigned = HashMap::new();
+    for (zone, partition) in &partitions {
+        if assigned.insert(*zone, partition).is_some_and(|other| other != partition) {
+            return Err(Error::DuplicateZone {
+                zone: *zone,
+                file: zones_file.path.to_string(),
+            }
+            .into());
+        }
+    }
+    Ok(partitions)
+}
+
+/// Reads a two-column `zone,district` CSV file assigning each zone to the district it
+/// is aggregated into, in the same way as `read_zones_file`. Rows whose district is not
+/// a number, such as a header row, are ignored, and a zone listed again must be in the
+/// same district.
+///
+/// # Returns
+/// The district of each zone.
+pub fn read_zone_aggregation(zones_file: &ZonesFile) -> std::io::Result<HashMap<i32, i32>> {
+    let assignments = read_zone_records(zones_file, |zone, record| {
+        let district = record.get(1)?.split('#').next()?.trim().parse::<i32>().ok()?;
+        Some((zone, district))
+    })?;
+    let mut districts = HashMap::new();
+    for (zone, district) in assignments {
+        if districts.insert(zone, district).is_some_and(|other| other != district) {
+            return Err(Error::DuplicateZone {
+                zone,
+                file: zones_file.path.to_string(),
+            }
+            .into());
+        }
+    }
+    Ok(districts)
+}
+
+/// Reads a two-column `name,zone` CSV file giving the zone number of each zone name
+/// used to label an input, in the same way as `read_zones_file`. Rows whose zone is
+/// not a number, such as a header row, are ignored.
+///
+/// # Returns
+/// The zone number of each name.
+pub fn read_zone_map(zones_file: &ZonesFile) -> std::io::Result<HashMap<String, i32>> {
+    let mut names = HashMap::new();
+    for result in open_zones_file(zones_file)?.records() {
+        let Ok(record) = result else {
+            continue;
+        };
+        let (Some(name), Some(zone)) = (record.get(0), record.get(1)) else {
+            continue;
+        };
+        let Ok(zone) = zone.split('#').next().unwrap_or_default().trim().parse::<i32>() else {
+            continue;
+        };
+        let name = name.trim();
+        if let Some(other) = names.insert(name.to_string(), zone)
+            && other != zone
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("The zone name {} is mapped to both {} and {} in {}", name, other, zone, zones_file.path),
+            ));
+        }
+    }
+    Ok(names)
+}
+
+/// Opens a zones CSV file, skipping its comment lines. Like an input, a gzipped file
+/// is decompressed and a remote path is fetched.
+fn open_zones_file(zones_file: &ZonesFile) -> std::io::Result<csv::Reader<Box<dyn Read>>> {
+    let (input, _) = open_input(zones_file.path, false)?;
+    Ok(csv::ReaderBuilder::new()
+        .has_headers(zones_file.has_header)
+        .delimiter(zones_file.delimiter)
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_reader(input))
+}
+
+/// Reads the rows of a zones CSV file whose first column is a zone number, passing
+/// each zone and its record to `parse`.
+fn read_zone_records<T>(
+    zones_file: &ZonesFile,
+    parse: impl Fn(i32, &csv::StringRecord) -> Option<T>,
+) -> std::io::Result<Vec<T>> {
+    Ok(open_zones_file(zones_file)?
+        .records()
+        .filter_map(|result| {
+            let record = result.ok()?;
+            let zone = record.get(0)?.split('#').next()?.trim();
+            if zone.is_empty() {
+                return None;
+            }
+            parse(zone.parse().ok()?, &record)
+        })
+        .collect())
+}
+
+/// Extracts the unique origins and destinations from the input data.
+fn get_data_zones<V: Sync>(data: &[(i32, i32, V)]) -> HashSet<i32> {
+    data.par_iter()
+        .flat_map(|(origin, destination, _)| vec![*origin, *destination])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zones_modes() -> std::io::Result<()> {
+        // The data uses zones 1 to 4 while the zones file lists 3 to 5
+        let data = [(1, 2, 1.0), (3, 4, 1.0)];
+        let mut zones_file = ZonesFile::new("test/zones_partial.csv");
+        assert_eq!(get_all_zones(Some(&zones_file), &data)?, vec![3, 4, 5]);
+        zones_file.mode = ZonesMode::Union;
+        assert_eq!(get_all_zones(Some(&zones_file), &data)?, vec![1, 2, 3, 4, 5]);
+        zones_file.mode = ZonesMode::Intersect;
+        assert_eq!(get_all_zones(Some(&zones_file), &data)?, vec![3, 4]);
+        assert_eq!(get_all_zones(None, &data)?, vec![1, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zones_file_header() -> std::io::Result<()> {
+        // Without a header the first zone must not be lost
+        let mut zones_file = ZonesFile::new("test/zones_no_header.csv");
+        assert_eq!(get_all_zones::<f32>(Some(&zones_file), &[])?, vec![3, 4, 5]);
+        zones_file.has_header = true;
+        assert_eq!(get_all_zones::<f32>(Some(&zones_file), &[])?, vec![4, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zones_file_comments() -> std::io::Result<()> {
+        let zones_file = ZonesFile {
+            has_header: true,
+            ..ZonesFile::new("test/zones_commented.csv")
+        };
+        assert_eq!(get_all_zones::<f32>(Some(&zones_file), &[])?, vec![1, 2, 3, 10]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzipped_zones_file() -> std::io::Result<()> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&std::fs::read("test/zones_commented.csv")?)?;
+        let file = std::env::temp_dir().join("csv_to_mtx_test_zones.csv.gz");
+        std::fs::write(&file, encoder.finish()?)?;
+        let gzipped = ZonesFile {
+            has_header: true,
+            ..ZonesFile::new(file.to_str().unwrap())
+        };
+        let plain = ZonesFile {
+            has_header: true,
+            ..ZonesFile::new("test/zones_commented.csv")
+        };
+        assert_eq!(get_all_zones::<f32>(Some(&gzipped), &[])?, get_all_zones::<f32>(Some(&plain), &[])?);
+        assert_eq!(get_all_zones::<f32>(Some(&gzipped), &[])?, vec![1, 2, 3, 10]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ordered_zones_file() -> std::io::Result<()> {
+        let file = std::env::temp_dir().join("csv_to_mtx_test_ordered_zones.csv");
+        std::fs::write(&file, "30\n10\n20\n10\n")?;
+        let zones_file = ZonesFile {
+            ordered: true,
+            ..ZonesFile::new(file.to_str().unwrap())
+        };
+        let data = [(10, 40, 1.0), (40, 20, 1.0)];
+        assert_eq!(get_all_zones(Some(&zones_file), &data)?, vec![30, 10, 20]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_suspicious_zone_numbers() {
+        assert_eq!(suspicious_zone_numbers(&[]), None);
+        assert_eq!(suspicious_zone_numbers(&[1, 2, 3, 4]), None);
+        assert_eq!(suspicious_zone_numbers(&[1001, 1002, 1003]), None);
+        // A timestamp read as a zone number
+        let warning = suspicious_zone_numbers(&[1, 2, 3, 1_700_000_000]).unwrap();
+        assert!(warning.contains("1700000000"), "{}", warning);
+        assert!(suspicious_zone_numbers(&[i32::MIN, 1]).is_some());
+    }
+
+    #[test]
+    fn test_deterministic_zone_order() -> std::io::Result<()> {
+        // Each set iterates in its own order, and the repeated zone must only appear once
+        let forward: Vec<i32> = (0..1000).map(|i| (i * 7919) % 1009).collect();
+        let expected = resolve_zones(None, || Ok(forward.iter().copied().collect()))?;
+        for _ in 0..10 {
+            let zones = resolve_zones(None, || Ok(forward.iter().rev().copied().collect()))?;
+            assert_eq!(zones, expected);
+        }
+        assert!(expected.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let file = std::env::temp_dir().join("csv_to_mtx_test_duplicate_zones.csv");
+        std::fs::write(&file, "5\n3\n5\n4\n3\n")?;
+        let zones_file = ZonesFile::new(file.to_str().unwrap());
+        assert_eq!(get_all_zones::<f32>(Some(&zones_file), &[])?, vec![3, 4, 5]);
+        Ok(())
+    }
+}